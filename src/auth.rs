@@ -98,6 +98,9 @@ async fn request_new_token(
         "esi-universe.read_structures.v1",
         "esi-wallet.read_character_wallet.v1",
         "esi-ui.open_window.v1",
+        "esi-markets.read_character_orders.v1",
+        "esi-skills.read_skills.v1",
+        "esi-characters.read_standings.v1",
     ];
 
     let client = create_client(config);
@@ -1,9 +1,8 @@
-use std::{io::Read, num::NonZeroU32};
+use std::{io::Read, path::Path};
 
 use anyhow::anyhow;
 use chrono::Duration;
 
-use governor::{Quota, RateLimiter};
 use oauth2::TokenResponse;
 use rust_eveonline_esi::apis::configuration::Configuration;
 
@@ -13,24 +12,31 @@ use unusable_eve_tradeworks_lib::{
     auth::Auth,
     cached_data::CachedStuff,
     cli::{self, DEST_NAME, SOURCE_NAME},
-    config::{AuthConfig, CommonConfig, Config, RouteConfig},
-    consts::{self, CACHE_AUTH, CACHE_DATADUMP, CONFIG_COMMON},
-    datadump_service::DatadumpService,
+    config::{AuthConfig, CommonConfig, Config, FeeModel, RouteConfig},
+    consts::{self, CACHE_AUTH, CACHE_DATADUMP, CACHE_ETAGS, CONFIG_COMMON},
+    datadump_service::{DatadumpService, GroupFilter},
     good_items::{
+        backtest::BacktestService,
+        fees::{FeeModelService, FeesService},
+        help::make_table_suspicious_orders,
         items_prices::ItemsPricesService,
+        ledger::LedgerService,
+        my_orders::MyOrdersService,
         sell_reprocess::{get_good_items_sell_reprocess, make_table_sell_reprocess},
+        simulation::SimulationService,
         station_trading::StationTradingService,
     },
     item_type::SystemMarketsItemData,
     items_list::{compute_pairs, compute_sell_buy, compute_sell_sell, SimpleDisplay},
+    load_create::record_recommendations,
     logger,
     requests::{
-        item_history::ItemHistoryEsiService, service::EsiRequestsService,
-        transactions::WalletEsiService,
+        etag::EtagCache, item_history::ItemHistoryEsiService, policy::RequestPolicy,
+        service::EsiRequestsService, transactions::WalletEsiService,
     },
     system_interaction::{
-        communicate_paste_into_game, communicate_paste_sell_order_prices,
-        parse_items_from_clipboard, send_notification,
+        communicate_modify_orders, communicate_paste_into_game,
+        communicate_paste_sell_order_prices, parse_items_from_clipboard, send_notification,
     },
     Station,
 };
@@ -70,7 +76,9 @@ async fn run() -> Result<(), anyhow::Error> {
     };
     esi_config.oauth_access_token = Some(auth.token.access_token().secret().clone());
 
-    let esi_requests = EsiRequestsService::new(&esi_config);
+    let etags = EtagCache::load(Path::new("cache/").join(CACHE_ETAGS));
+    let policy = RequestPolicy::default();
+    let esi_requests = EsiRequestsService::new(&esi_config, &etags, &policy);
 
     let path_to_datadump = cache
         .load_or_create_json_async(
@@ -105,21 +113,86 @@ async fn run() -> Result<(), anyhow::Error> {
 
     let force_no_refresh = cli_args.get_flag(cli::FORCE_NO_REFRESH);
 
-    let error_limiter = RateLimiter::direct(Quota::per_minute(NonZeroU32::new(100).unwrap()));
-    let request_limiter = RateLimiter::direct(Quota::per_minute(NonZeroU32::new(600).unwrap()));
-
     let esi_history = ItemHistoryEsiService {
         config: &esi_config,
-        error_limiter: &error_limiter,
-        request_limiter: &request_limiter,
+        policy: &policy,
+        etags: &etags,
     };
 
-    let config_common = CommonConfig::from_file_json(CONFIG_COMMON)?;
+    let mut config_common = CommonConfig::from_file_json(CONFIG_COMMON)?;
+
+    if config_common.fee_model == FeeModel::Skills {
+        let fee_model_service = FeeModelService {
+            esi_requests: &esi_requests,
+        };
+        fee_model_service
+            .apply(&mut config_common, auth.get_character_id())
+            .await?;
+    }
+
+    let fees_flag = cli_args.get_flag(cli::FEES);
+    if fees_flag || config_common.journal_fees.use_paid {
+        log::debug!("Fees paid");
+        let wallet_service = WalletEsiService {
+            esi_config: &esi_config,
+            policy: &policy,
+        };
+
+        let mut fees_service = FeesService {
+            wallet_esi_service: &wallet_service,
+            cache: &mut cache,
+            esi_requests: &esi_requests,
+            config: &config_common,
+        };
+        let fees = fees_service.get_fees(auth.get_character_id()).await?;
+
+        if fees_flag {
+            let rows = fees.make_table_fees(config_common.journal_fees.min_samples);
+            println!("{}", TableBuilder::new().rows(rows).build().render());
+        }
+        if config_common.journal_fees.use_paid {
+            fees.apply(&mut config_common);
+        }
+    }
 
     let sell_sell = cli_args.get_flag(cli::SELL_SELL);
     let sell_buy = cli_args.get_flag(cli::SELL_BUY);
     let reprocess_flag = cli_args.get_flag(cli::REPROCESS);
-    if sell_sell || reprocess_flag || sell_buy {
+    if cli_args.get_flag(cli::SIMULATE) {
+        log::debug!("Simulation");
+        let mut simulation_service = SimulationService {
+            cache: &mut cache,
+            datadump: &data_service,
+            esi_requests: &esi_requests,
+            config: &config_common,
+        };
+
+        let source = cli_args.get_one::<String>(SOURCE_NAME).unwrap();
+        let source = find_station(&config_common.stations, source)?;
+        let simulation = if sell_sell {
+            let dest = cli_args
+                .get_one::<String>(DEST_NAME)
+                .ok_or_else(|| anyhow!("Simulating sell-sell needs a destination"))?;
+            let route = RouteConfig {
+                source,
+                destination: find_station(&config_common.stations, dest)?,
+            };
+            simulation_service
+                .simulate_sell_sell(&route, auth.get_character_id())
+                .await?
+        } else if cli_args.get_flag(cli::STATION_TRADING) {
+            simulation_service
+                .simulate_station_trading(&source, auth.get_character_id())
+                .await?
+        } else {
+            return Err(anyhow!(
+                "Simulation works with --sell-sell or --station-trading"
+            ));
+        };
+
+        let rows = simulation.make_table_simulation();
+        println!("{}", TableBuilder::new().rows(rows).build().render());
+    } else if sell_sell || reprocess_flag || sell_buy {
         let source = cli_args.get_one::<String>(SOURCE_NAME).unwrap();
         let dest = cli_args.get_one::<String>(DEST_NAME).unwrap();
         let config = Config {
@@ -154,6 +227,7 @@ async fn run() -> Result<(), anyhow::Error> {
         log::debug!("Items prices");
         let wallet_service = WalletEsiService {
             esi_config: &esi_config,
+            policy: &policy,
         };
 
         let mut items_prices_service = ItemsPricesService {
@@ -189,14 +263,95 @@ async fn run() -> Result<(), anyhow::Error> {
         let source = cli_args.get_one::<String>(SOURCE_NAME).unwrap();
         let station = find_station(&config_common.stations, source)?;
         let items = items_prices_service
-            .get_prices_for_items(station, auth.get_character_id(), get_debug_item(&cli_args))
+            .get_prices_for_items(
+                station.clone(),
+                auth.get_character_id(),
+                get_debug_item(&cli_args),
+            )
             .await?;
 
         let rows = items.make_table_station_trade(get_name_len(&cli_args));
         let table = TableBuilder::new().rows(rows).build();
         println!("{}", table.render());
+        if get_debug_item(&cli_args).is_some() {
+            let rows = items.make_table_suspicious_orders(&config_common);
+            println!("Orders ignored as suspicious:");
+            println!("{}", TableBuilder::new().rows(rows).build().render());
+        } else {
+            let recommendations = items.recommendations(&station, &config_common);
+            record_recommendations(&mut cache, &config_common, recommendations).await?;
+        }
 
         communicate_paste_into_game(&esi_requests, &items).await?;
+    } else if cli_args.get_flag(cli::MY_ORDERS) {
+        log::debug!("My orders");
+        let my_orders_service = MyOrdersService {
+            esi_requests: &esi_requests,
+        };
+
+        let source = cli_args.get_one::<String>(SOURCE_NAME).unwrap();
+        let station = find_station(&config_common.stations, source)?;
+        let my_orders = my_orders_service
+            .get_my_orders(station, auth.get_character_id())
+            .await?;
+
+        let rows = my_orders.make_table_my_orders(get_name_len(&cli_args));
+        let table = TableBuilder::new().rows(rows).build();
+        println!("{}", table.render());
+
+        if my_orders.outbid_orders().next().is_some() {
+            communicate_modify_orders(&my_orders_service, my_orders).await?;
+        }
+    } else if cli_args.get_flag(cli::LEDGER) {
+        log::debug!("Ledger");
+        let wallet_service = WalletEsiService {
+            esi_config: &esi_config,
+            policy: &policy,
+        };
+
+        let mut ledger_service = LedgerService {
+            wallet_esi_service: &wallet_service,
+            cache: &mut cache,
+            esi_requests: &esi_requests,
+            config: &config_common,
+        };
+        let ledger = ledger_service.get_ledger(auth.get_character_id()).await?;
+
+        println!("Realized profit per item:");
+        let rows = ledger.make_table_items(get_name_len(&cli_args));
+        println!("{}", TableBuilder::new().rows(rows).build().render());
+        println!("Realized profit per route:");
+        let rows = ledger.make_table_routes();
+        println!("{}", TableBuilder::new().rows(rows).build().render());
+        println!("Realized profit per week:");
+        let rows = ledger.make_table_weeks();
+        println!("{}", TableBuilder::new().rows(rows).build().render());
+
+        let unmatched = ledger.ledger.unmatched_sold.values().sum::<i64>();
+        if unmatched > 0 {
+            log::warn!("{unmatched} sold units have no recorded purchase and are not counted");
+        }
+    } else if cli_args.get_flag(cli::BACKTEST) {
+        log::debug!("Backtest");
+        let wallet_service = WalletEsiService {
+            esi_config: &esi_config,
+            policy: &policy,
+        };
+
+        let mut backtest_service = BacktestService {
+            wallet_esi_service: &wallet_service,
+            cache: &mut cache,
+            esi_requests: &esi_requests,
+            esi_history: &esi_history,
+            config: &config_common,
+        };
+        let backtest = backtest_service.backtest(auth.get_character_id()).await?;
+
+        let rows = backtest.make_table_outcomes(get_name_len(&cli_args));
+        println!("{}", TableBuilder::new().rows(rows).build().render());
+        println!("Prediction errors by setting, positive when overestimated:");
+        let rows = backtest.make_table_knobs();
+        println!("{}", TableBuilder::new().rows(rows).build().render());
     }
 
     Ok(())
@@ -214,6 +369,15 @@ async fn print_buy_tables(
     sell_sell: bool,
     force_no_refresh: bool,
 ) -> Result<(), anyhow::Error> {
+    let group_filter = if sell_sell {
+        GroupFilter::new(
+            &data_service,
+            config.common.sell_sell.include_groups.as_ref(),
+            config.common.sell_sell.exclude_groups.as_ref(),
+        )?
+    } else {
+        GroupFilter::default()
+    };
     let mut pairs: Vec<SystemMarketsItemData> = compute_pairs(
         &config,
         &esi_requests,
@@ -221,6 +385,7 @@ async fn print_buy_tables(
         auth.get_character_id(),
         &mut cache,
         &data_service,
+        &group_filter,
     )
     .await?;
     let reprocess_flag = cli_args.get_flag(cli::REPROCESS);
@@ -239,9 +404,20 @@ async fn print_buy_tables(
 
         pairs.retain(|x| x.desc.type_id == v || reprocess.contains(&x.desc.type_id));
         disable_filters = true;
+
+        let markets = pairs.iter().flat_map(|x| {
+            [
+                ("src", x.desc.name.as_str(), &x.source),
+                ("dst", x.desc.name.as_str(), &x.destination),
+            ]
+        });
+        let rows = make_table_suspicious_orders(&config.common, markets);
+        println!("Orders ignored as suspicious:");
+        println!("{}", TableBuilder::new().rows(rows).build().render());
     }
     let esi_config = &esi_config;
     let mut simple_list: Vec<_> = Vec::new();
+    let mut recommendations = Vec::new();
     let rows = {
         let name_len = get_name_len(cli_args);
 
@@ -252,12 +428,12 @@ async fn print_buy_tables(
                 &config,
                 disable_filters,
                 &mut simple_list,
+                &mut recommendations,
                 name_len,
-                cache,
+                &mut cache,
                 force_no_refresh,
                 esi_requests,
                 esi_config,
-                data_service,
             )
             .await?
         } else if reprocess_flag {
@@ -272,11 +448,22 @@ async fn print_buy_tables(
             )?
         } else {
             log::debug!("Sell buy path.");
-            compute_sell_buy(pairs, &config, disable_filters, &mut simple_list, name_len)?
+            compute_sell_buy(
+                pairs,
+                &config,
+                disable_filters,
+                &mut simple_list,
+                &mut recommendations,
+                name_len,
+            )?
         }
     };
     let table = TableBuilder::new().rows(rows).build();
     println!("{}", table.render());
+    // debug runs ignore filters, their items weren't really recommended
+    if !disable_filters {
+        record_recommendations(&mut cache, &config.common, recommendations).await?;
+    }
     if cli_args.get_flag(cli::DISPLAY_SIMPLE_LIST) {
         print_simple_list(&simple_list);
     }
@@ -6,7 +6,14 @@ use std::{
 
 use anyhow::Result;
 use chrono::{DateTime, Utc};
-use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use futures::FutureExt;
+use serde::{
+    de::{DeserializeOwned, IgnoredAny},
+    Deserialize, Serialize,
+};
+
+/// Items pushed into a [`Checkpoint`] between saves.
+const CHECKPOINT_EVERY: usize = 500;
 
 #[derive(Debug)]
 pub struct CachedStuff {
@@ -39,6 +46,31 @@ impl CachedStuff {
         F: FnOnce(Option<T>) -> FO,
         FO: Future<Output = Result<T>>,
         T: Serialize + DeserializeOwned,
+    {
+        self.load_data_or_create_async(
+            &self.path.join(path.as_ref()),
+            depends,
+            DataFormat::Bin,
+            timeout,
+            |previous| gen(previous).map(|x| x.map(|data| Expiring::new(data, None))),
+        )
+        .await
+    }
+
+    /// Same as [`Self::load_or_create_async`] but the generated data carries
+    /// its own expiry time (e.g. from the `Expires` header) which is used
+    /// instead of `timeout` until it runs out.
+    pub async fn load_or_create_expiring_async<T, F, FO>(
+        &mut self,
+        path: impl AsRef<Path>,
+        depends: Vec<&str>,
+        timeout: Option<chrono::Duration>,
+        gen: F,
+    ) -> Result<T>
+    where
+        F: FnOnce(Option<T>) -> FO,
+        FO: Future<Output = Result<Expiring<T>>>,
+        T: Serialize + DeserializeOwned,
     {
         self.load_data_or_create_async(
             &self.path.join(path.as_ref()),
@@ -67,7 +99,7 @@ impl CachedStuff {
             depends,
             DataFormat::Json,
             timeout,
-            gen,
+            |previous| gen(previous).map(|x| x.map(|data| Expiring::new(data, None))),
         )
         .await
     }
@@ -82,7 +114,7 @@ impl CachedStuff {
     ) -> Result<T>
     where
         F: FnOnce(Option<T>) -> FO,
-        FO: Future<Output = Result<T>>,
+        FO: Future<Output = Result<Expiring<T>>>,
         T: Serialize + DeserializeOwned,
     {
         let were_depends_updated = depends
@@ -97,16 +129,9 @@ impl CachedStuff {
             };
             match deser {
                 Ok(deser) => {
-                    match timeout {
-                        Some(timeout) if deser.time + timeout > Utc::now() => {
-                            log::info!("Path {:?} loaded", path);
-                            return Ok(deser.data);
-                        }
-                        None => {
-                            log::info!("Path {:?} loaded", path);
-                            return Ok(deser.data);
-                        }
-                        _ => {}
+                    if deser.is_valid(timeout) {
+                        log::info!("Path {:?} loaded", path);
+                        return Ok(deser.data);
                     }
                     deser_opt = Some(deser);
                 }
@@ -132,24 +157,63 @@ impl CachedStuff {
     ) -> Result<Container<T>>
     where
         F: FnOnce(Option<T>) -> FO,
-        FO: Future<Output = Result<T>>,
+        FO: Future<Output = Result<Expiring<T>>>,
         T: Serialize,
     {
         log::info!("Generating path {:?}", path.as_ref());
         let generated = gen(previous.map(|x| x.data)).await?;
+        if let Some(expires) = generated.expires {
+            log::debug!("Path {:?} expires at {expires}", path.as_ref());
+        }
         let generated = self.save(generated, format, path);
         Ok(generated)
     }
 
+    /// Replaces the data cached at `path`, keeping the time it was created at.
+    /// The cache expires at the earliest of the old and the new expiry.
+    pub fn update_expiring<T>(&mut self, path: impl AsRef<Path>, data: Expiring<T>) -> Result<T>
+    where
+        T: Serialize,
+    {
+        let path = self.path.join(path.as_ref());
+        let old: Container<IgnoredAny> = rmp_serde::from_slice(std::fs::read(&path)?.as_slice())?;
+        let container = Container {
+            data: data.data,
+            time: old.time,
+            expires: earliest_expiry(old.expires, data.expires),
+        };
+        std::fs::write(&path, rmp_serde::to_vec(&container)?)?;
+        self.caches_updated
+            .insert(path.to_str().unwrap().to_string(), true);
+        Ok(container.data)
+    }
+
+    /// Checkpoint for partial results of the data cached at `path`.
+    pub fn checkpoint<T>(&self, path: impl AsRef<Path>, max_age: chrono::Duration) -> Checkpoint<T>
+    where
+        T: Serialize + DeserializeOwned,
+    {
+        Checkpoint::load(self.path.join(path.as_ref()), max_age)
+    }
+
     pub fn save_json<T>(&mut self, generated: T, path: &impl AsRef<Path>) -> T
     where
         T: Serialize,
     {
-        self.save(generated, DataFormat::Json, &self.path.join(path.as_ref()))
-            .data
+        self.save(
+            Expiring::new(generated, None),
+            DataFormat::Json,
+            &self.path.join(path.as_ref()),
+        )
+        .data
     }
 
-    fn save<T>(&mut self, generated: T, format: DataFormat, path: &impl AsRef<Path>) -> Container<T>
+    fn save<T>(
+        &mut self,
+        generated: Expiring<T>,
+        format: DataFormat,
+        path: &impl AsRef<Path>,
+    ) -> Container<T>
     where
         T: Serialize,
     {
@@ -157,8 +221,9 @@ impl CachedStuff {
             .insert(path.as_ref().to_str().unwrap().to_string(), true);
 
         let generated = Container {
-            data: generated,
+            data: generated.data,
             time: Utc::now(),
+            expires: generated.expires,
         };
         let s = match format {
             DataFormat::Json => serde_json::to_vec(&generated).unwrap(),
@@ -181,4 +246,134 @@ enum DataFormat {
 struct Container<T> {
     data: T,
     time: DateTime<Utc>,
+    #[serde(default)]
+    expires: Option<DateTime<Utc>>,
+}
+
+impl<T> Container<T> {
+    fn is_valid(&self, timeout: Option<chrono::Duration>) -> bool {
+        match (timeout, self.expires) {
+            (None, _) => true,
+            (Some(_), Some(expires)) => expires > Utc::now(),
+            (Some(timeout), None) => self.time + timeout > Utc::now(),
+        }
+    }
+}
+
+/// Partial results of a long download, saved while they stream in so that
+/// an interrupted download continues where it stopped on the next run.
+pub struct Checkpoint<T> {
+    path: PathBuf,
+    container: Container<Vec<T>>,
+    unsaved: usize,
+}
+
+impl<T> Checkpoint<T>
+where
+    T: Serialize + DeserializeOwned,
+{
+    fn load(path: PathBuf, max_age: chrono::Duration) -> Self {
+        let container = std::fs::read(&path)
+            .ok()
+            .and_then(|bytes| {
+                rmp_serde::from_slice::<Container<Vec<T>>>(bytes.as_slice())
+                    .map_err(|err| log::warn!("Couldn't deserialize checkpoint {path:?}: {err}"))
+                    .ok()
+            })
+            .filter(|x| x.is_valid(Some(max_age)))
+            .unwrap_or_else(|| Container {
+                data: Vec::new(),
+                time: Utc::now(),
+                expires: None,
+            });
+        if !container.data.is_empty() {
+            log::info!(
+                "Checkpoint {path:?} loaded with {} items",
+                container.data.len()
+            );
+        }
+
+        Self {
+            path,
+            container,
+            unsaved: 0,
+        }
+    }
+
+    pub fn items(&self) -> &[T] {
+        &self.container.data
+    }
+
+    pub fn push(&mut self, item: Expiring<T>) {
+        self.container.data.push(item.data);
+        self.container.expires = earliest_expiry(self.container.expires, item.expires);
+        self.unsaved += 1;
+        if self.unsaved >= CHECKPOINT_EVERY {
+            self.save();
+        }
+    }
+
+    /// Errors are only logged, losing a checkpoint merely means downloading again.
+    pub fn save(&mut self) {
+        let res = rmp_serde::to_vec(&self.container)
+            .map_err(anyhow::Error::from)
+            .and_then(|bytes| Ok(std::fs::write(&self.path, bytes)?));
+        match res {
+            Ok(()) => {
+                log::debug!(
+                    "Checkpoint {:?} saved with {} items",
+                    self.path,
+                    self.container.data.len()
+                );
+                self.unsaved = 0;
+            }
+            Err(err) => log::warn!("Couldn't save checkpoint {:?}: {err}", self.path),
+        }
+    }
+
+    /// Takes the collected items, keeping the checkpoint file.
+    pub fn into_items(self) -> Vec<T> {
+        self.container.data
+    }
+
+    /// Takes the collected items and deletes the checkpoint file.
+    pub fn finish(self) -> Expiring<Vec<T>> {
+        if self.path.exists() {
+            if let Err(err) = std::fs::remove_file(&self.path) {
+                log::warn!("Couldn't remove checkpoint {:?}: {err}", self.path);
+            }
+        }
+        Expiring::new(self.container.data, self.container.expires)
+    }
+}
+
+/// Data together with the time until which the server considers it fresh.
+#[derive(Debug)]
+pub struct Expiring<T> {
+    pub data: T,
+    pub expires: Option<DateTime<Utc>>,
+}
+
+impl<T> Expiring<T> {
+    pub fn new(data: T, expires: Option<DateTime<Utc>>) -> Self {
+        Self { data, expires }
+    }
+
+    pub fn map<U>(self, f: impl FnOnce(T) -> U) -> Expiring<U> {
+        Expiring {
+            data: f(self.data),
+            expires: self.expires,
+        }
+    }
+}
+
+/// Picks the earliest of two optional expiry times.
+pub fn earliest_expiry(
+    a: Option<DateTime<Utc>>,
+    b: Option<DateTime<Utc>>,
+) -> Option<DateTime<Utc>> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(a.min(b)),
+        (a, b) => a.or(b),
+    }
 }
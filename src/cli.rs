@@ -8,6 +8,11 @@ pub const SELL_BUY: &str = "sell-buy";
 pub const REPROCESS: &str = "reprocess";
 pub const ITEMS_PRICES: &str = "items-prices";
 pub const STATION_TRADING: &str = "station-trading";
+pub const MY_ORDERS: &str = "my-orders";
+pub const LEDGER: &str = "ledger";
+pub const BACKTEST: &str = "backtest";
+pub const SIMULATE: &str = "simulate";
+pub const FEES: &str = "fees";
 pub const DISPLAY_SIMPLE_LIST: &str = "simple-list";
 pub const DISPLAY_SIMPLE_LIST_PRICE: &str = "simple-list-price";
 pub const DEBUG_ITEM_ID: &str = "debug-item";
@@ -19,7 +24,7 @@ pub const DEST_NAME: &str = "destination-name";
 
 pub fn matches() -> ArgMatches {
     Command::new("Eve Tradeworks")
-        .arg(Arg::new(SOURCE_NAME).required(true))
+        .arg(Arg::new(SOURCE_NAME).required_unless_present_any([LEDGER, BACKTEST, FEES]))
         .arg(Arg::new(DEST_NAME).required(false))
         .arg(
             Arg::new(SELL_SELL)
@@ -56,6 +61,79 @@ pub fn matches() -> ArgMatches {
                 .action(ArgAction::SetTrue)
                 .conflicts_with_all([SELL_SELL, SELL_BUY, REPROCESS]),
         )
+        .arg(
+            Arg::new(MY_ORDERS)
+                .short('m')
+                .long("my-orders")
+                .action(ArgAction::SetTrue)
+                .conflicts_with_all([
+                    SELL_SELL,
+                    SELL_BUY,
+                    REPROCESS,
+                    ITEMS_PRICES,
+                    STATION_TRADING,
+                ]),
+        )
+        .arg(
+            Arg::new(LEDGER)
+                .short('g')
+                .long("ledger")
+                .action(ArgAction::SetTrue)
+                .conflicts_with_all([
+                    SELL_SELL,
+                    SELL_BUY,
+                    REPROCESS,
+                    ITEMS_PRICES,
+                    STATION_TRADING,
+                    MY_ORDERS,
+                ]),
+        )
+        .arg(
+            Arg::new(BACKTEST)
+                .short('k')
+                .long("backtest")
+                .action(ArgAction::SetTrue)
+                .conflicts_with_all([
+                    SELL_SELL,
+                    SELL_BUY,
+                    REPROCESS,
+                    ITEMS_PRICES,
+                    STATION_TRADING,
+                    MY_ORDERS,
+                    LEDGER,
+                ]),
+        )
+        .arg(
+            Arg::new(SIMULATE)
+                .short('u')
+                .long("simulate")
+                .action(ArgAction::SetTrue)
+                .conflicts_with_all([
+                    SELL_BUY,
+                    REPROCESS,
+                    ITEMS_PRICES,
+                    MY_ORDERS,
+                    LEDGER,
+                    BACKTEST,
+                ]),
+        )
+        .arg(
+            Arg::new(FEES)
+                .short('j')
+                .long("fees")
+                .action(ArgAction::SetTrue)
+                .conflicts_with_all([
+                    SELL_SELL,
+                    SELL_BUY,
+                    REPROCESS,
+                    ITEMS_PRICES,
+                    STATION_TRADING,
+                    MY_ORDERS,
+                    LEDGER,
+                    BACKTEST,
+                    SIMULATE,
+                ]),
+        )
         .arg(
             Arg::new(DISPLAY_SIMPLE_LIST)
                 .short('l')
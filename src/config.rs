@@ -16,12 +16,13 @@ impl AuthConfig {
     }
 }
 
+#[derive(Clone)]
 pub struct Config {
     pub route: RouteConfig,
     pub common: CommonConfig,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct RouteConfig {
     pub source: Station,
     pub destination: Station,
@@ -38,7 +39,7 @@ impl RouteConfig {
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CommonConfig {
     pub days_average: usize,
     pub margin_cutoff: f64,
@@ -56,6 +57,98 @@ pub struct CommonConfig {
     pub max_investment_per_item: f64,
     pub sell_reprocess: ConfigSellReprocess,
     pub stations: Vec<Station>,
+    #[serde(default)]
+    pub history_gap_fill: HistoryGapFill,
+    /// How many days back days without trades are filled in.
+    #[serde(default = "default_history_window_days")]
+    pub history_window_days: i64,
+    /// How daily prices of the last `days_average` days are combined into one.
+    #[serde(default)]
+    pub price_estimator: PriceEstimator,
+    #[serde(default)]
+    pub trend_filter: ConfigTrendFilter,
+    /// What item selection maximizes.
+    #[serde(default)]
+    pub objective: Objective,
+    /// Which orders are considered bait and ignored when pricing.
+    #[serde(default)]
+    pub manipulation: ConfigManipulation,
+    /// How many order downloads of each station are kept to see what traded in between,
+    /// each one is about as big as the cached orders.
+    #[serde(default = "default_order_snapshots_keep")]
+    pub order_snapshots_keep: usize,
+    #[serde(default)]
+    pub station_volume: ConfigStationVolume,
+    #[serde(default)]
+    pub ledger: ConfigLedger,
+    #[serde(default)]
+    pub backtest: ConfigBacktest,
+    #[serde(default)]
+    pub simulation: ConfigSimulation,
+    #[serde(default)]
+    pub journal_fees: ConfigJournalFees,
+    #[serde(default)]
+    pub fee_model: FeeModel,
+}
+
+fn default_history_window_days() -> i64 {
+    360
+}
+
+fn default_order_snapshots_keep() -> usize {
+    12
+}
+
+#[derive(Debug, Serialize, Deserialize, Default, Clone, Copy, PartialEq)]
+pub enum PriceEstimator {
+    Average,
+    #[default]
+    Median,
+    /// Mean without `trim` fraction of the cheapest and of the most expensive days.
+    TrimmedMean {
+        trim: f64,
+    },
+    /// Median where each day counts as much as its traded volume.
+    VolumeWeightedMedian,
+    /// Mean of days that are within `threshold` deviations (MAD) of the median.
+    MadFilteredMean {
+        threshold: f64,
+    },
+    /// Exponentially weighted moving average, `alpha` is the weight of the newest day.
+    Ewma {
+        alpha: f64,
+    },
+}
+
+#[derive(Debug, Serialize, Deserialize, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Objective {
+    /// Total profit of a trip.
+    #[default]
+    Profit,
+    /// Profit per day the capital is locked up, prefers items that sell quickly.
+    ProfitPerDay,
+}
+
+/// Where broker fees of NPC stations and sales tax come from.
+#[derive(Debug, Serialize, Deserialize, Default, Clone, Copy, PartialEq, Eq)]
+pub enum FeeModel {
+    /// As configured.
+    #[default]
+    Configured,
+    /// From skills and standings of the character, citadels keep the configured fee.
+    Skills,
+}
+
+/// What to put into history for days without trades.
+#[derive(Debug, Serialize, Deserialize, Default, Clone, Copy, PartialEq, Eq)]
+pub enum HistoryGapFill {
+    /// Keep only days with trades.
+    Leave,
+    /// Repeat prices of the last day with trades, with zero volume.
+    ForwardFill,
+    /// Use median prices of the whole history, with zero volume.
+    #[default]
+    Median,
 }
 
 impl CommonConfig {
@@ -67,9 +160,78 @@ impl CommonConfig {
 
         Ok(config)
     }
+
+    /// Config for unit tests that doesn't depend on the example config file,
+    /// tests change the fields they rely on.
+    #[cfg(test)]
+    pub fn for_tests() -> Self {
+        use crate::zkb::zkb_requests::ZkillEntityType;
+
+        Self {
+            days_average: 14,
+            margin_cutoff: 0.02,
+            sales_tax: 0.036,
+            items_take: 10,
+            zkill_entity: ZkillEntity {
+                id: 1,
+                tp: ZkillEntityType::Region,
+            },
+            refresh_timeout_hours: 1.,
+            item_history_timeout_hours: 24,
+            min_profit: None,
+            include_groups: None,
+            sell_sell: ConfigSellSell {
+                rcmnd_fill_days: 3.,
+                min_src_volume: 0.,
+                min_dst_volume: 0.,
+                max_filled_for_days_cutoff: 7.,
+                freight_cost_iskm3: 0.,
+                freight_cost_collateral_percent: 0.,
+                sell_sell_zkb: ConfigSellSellZkb {
+                    min_dst_zkb_lost_volume: 0.,
+                    zkb_download_pages: 1,
+                    zkb_losses_volume_multiplier: 1.,
+                },
+                markup_if_no_orders_dest: 0.35,
+                dst_ignore_orders_under_volume_pct: 0.,
+                exclude_groups: None,
+                include_groups: None,
+            },
+            station_trade: ConfigStationTrade {
+                daily_volume_pct: 0.1,
+                min_item_volume: 0.,
+                dst_ignore_orders_under_volume_pct: 0.,
+                exclude_groups: None,
+                include_groups: None,
+                competition: ConfigCompetition::default(),
+                relist: ConfigRelist::default(),
+            },
+            ignore_difference_between_history_and_order_pct: 0.5,
+            cargo_capacity: 1000,
+            max_investment_per_item: 1e9,
+            sell_reprocess: ConfigSellReprocess {
+                repro_portion: 0.5,
+                repro_tax: 0.05,
+            },
+            stations: Vec::new(),
+            history_gap_fill: HistoryGapFill::default(),
+            history_window_days: default_history_window_days(),
+            price_estimator: PriceEstimator::default(),
+            trend_filter: ConfigTrendFilter::default(),
+            objective: Objective::default(),
+            manipulation: ConfigManipulation::default(),
+            order_snapshots_keep: default_order_snapshots_keep(),
+            station_volume: ConfigStationVolume::default(),
+            ledger: ConfigLedger::default(),
+            backtest: ConfigBacktest::default(),
+            simulation: ConfigSimulation::default(),
+            journal_fees: ConfigJournalFees::default(),
+            fee_model: FeeModel::default(),
+        }
+    }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ConfigSellSell {
     pub rcmnd_fill_days: f64,
     pub min_src_volume: f64,
@@ -84,23 +246,207 @@ pub struct ConfigSellSell {
     pub include_groups: Option<Vec<String>>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ConfigStationTrade {
     pub daily_volume_pct: f64,
     pub min_item_volume: f64,
     pub dst_ignore_orders_under_volume_pct: f64,
     pub exclude_groups: Option<Vec<String>>,
     pub include_groups: Option<Vec<String>>,
+    #[serde(default)]
+    pub competition: ConfigCompetition,
+    #[serde(default)]
+    pub relist: ConfigRelist,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+/// How contested the top of the order book of an item is.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConfigCompetition {
+    /// Orders issued or modified this recently count as active competitors.
+    pub recent_hours: f64,
+    /// Orders within this fraction of the best price are at the top of the book.
+    pub top_of_book_pct: f64,
+    /// Skip items with a higher competition score.
+    pub max_score: Option<f64>,
+}
+
+impl Default for ConfigCompetition {
+    fn default() -> Self {
+        Self {
+            recent_hours: 2.,
+            top_of_book_pct: 0.05,
+            max_score: None,
+        }
+    }
+}
+
+/// Modifications needed to keep orders on top of the book, each one costs a relist fee.
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct ConfigRelist {
+    /// Expected modifications of every buy and every sell order.
+    pub modifications_per_order: f64,
+    /// Expected extra modifications per competitor at the top of the book.
+    pub modifications_per_competitor: f64,
+    /// Level of Advanced Broker Relations, the `Skills` fee model reads it from the character.
+    pub advanced_broker_relations: i32,
+}
+
+/// Limits on history trends over the last `days_average` days, all are fractions.
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct ConfigTrendFilter {
+    /// Skip items whose average price falls faster than this per week, e.g. 0.2.
+    pub max_price_drop_per_week: Option<f64>,
+    /// Skip items whose daily average price deviates more than this.
+    pub max_volatility: Option<f64>,
+    /// Skip items whose traded volume changes slower than this per week, e.g. -0.5.
+    pub min_volume_trend: Option<f64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConfigManipulation {
+    /// Buy orders this fraction above the high history band or sell orders
+    /// this fraction below the low one are bait.
+    pub max_history_deviation: f64,
+    /// Orders younger than this outside the history bands are suspicious...
+    pub fresh_order_hours: f64,
+    /// ...when their total volume is 1 or over this many days of traded volume.
+    pub odd_volume_days: f64,
+}
+
+impl Default for ConfigManipulation {
+    fn default() -> Self {
+        Self {
+            max_history_deviation: 0.5,
+            fresh_order_hours: 2.,
+            odd_volume_days: 10.,
+        }
+    }
+}
+
+/// When volume observed in order snapshots replaces the region history volume.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConfigStationVolume {
+    pub enabled: bool,
+    pub min_snapshots: usize,
+    /// Snapshots have to span at least this many days.
+    pub min_days: f64,
+}
+
+impl Default for ConfigStationVolume {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            min_snapshots: 6,
+            min_days: 1.,
+        }
+    }
+}
+
+/// How realized profit is calculated from wallet transactions.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConfigLedger {
+    /// Broker fee of stations that aren't in `stations`.
+    pub unknown_station_broker_fee: f64,
+}
+
+impl Default for ConfigLedger {
+    fn default() -> Self {
+        Self {
+            unknown_station_broker_fee: 0.03,
+        }
+    }
+}
+
+/// How recommendations are compared with what happened after them.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConfigBacktest {
+    /// Trades and market history this many days after a recommendation count.
+    pub window_days: i64,
+    /// Recommendations older than this many days are forgotten.
+    #[serde(default = "default_recommendations_keep_days")]
+    pub keep_days: i64,
+}
+
+fn default_recommendations_keep_days() -> i64 {
+    90
+}
+
+impl Default for ConfigBacktest {
+    fn default() -> Self {
+        Self {
+            window_days: 14,
+            keep_days: default_recommendations_keep_days(),
+        }
+    }
+}
+
+/// How past days are replayed to see how the settings would have done.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConfigSimulation {
+    /// How many days back the first simulated decision is made.
+    pub days: i64,
+    /// Days between two simulated decisions.
+    pub step_days: i64,
+    /// Days after a decision during which the items are traded.
+    pub trade_days: i64,
+    /// Fraction of the volume traded in a day that our orders get.
+    pub market_share: f64,
+    /// Settings to simulate besides the configured ones.
+    pub variants: Vec<ConfigSimulationVariant>,
+}
+
+impl Default for ConfigSimulation {
+    fn default() -> Self {
+        Self {
+            days: 90,
+            step_days: 7,
+            trade_days: 14,
+            market_share: 0.1,
+            variants: Vec::new(),
+        }
+    }
+}
+
+/// Settings that replace the configured ones in a simulation, unset ones are kept.
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct ConfigSimulationVariant {
+    pub days_average: Option<usize>,
+    pub margin_cutoff: Option<f64>,
+    pub rcmnd_fill_days: Option<f64>,
+}
+
+/// Broker fees and sales tax derived from what the wallet journal says was paid.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConfigJournalFees {
+    /// Journal entries this many days old are considered.
+    pub days: i64,
+    /// Replace the configured fees with the paid ones on every run.
+    pub use_paid: bool,
+    /// Rates derived from fewer journal entries are not used.
+    pub min_samples: usize,
+    /// Warn when the configured and the paid rate differ by more than this.
+    pub drift_warning: f64,
+}
+
+impl Default for ConfigJournalFees {
+    fn default() -> Self {
+        Self {
+            days: 30,
+            use_paid: false,
+            min_samples: 3,
+            drift_warning: 0.001,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ConfigSellSellZkb {
     pub min_dst_zkb_lost_volume: f64,
     pub zkb_download_pages: u32,
     pub zkb_losses_volume_multiplier: f64,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ConfigSellReprocess {
     pub repro_portion: f64,
     pub repro_tax: f64,
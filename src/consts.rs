@@ -10,6 +10,12 @@ pub const CACHE_DATADUMP: &str = "datadump.json";
 pub const CACHE_ALL_TYPES: &str = "all_types.json";
 pub const CACHE_ALL_TYPE_DESC: &str = "all_type_descriptions.rmp";
 pub const CACHE_ALL_TYPE_PRICES: &str = "all_type_prices.rmp";
+pub const CACHE_ETAGS: &str = "etags.rmp";
+/// Etags not used for this long are forgotten.
+pub const ETAGS_KEEP_DAYS: i64 = 7;
+/// Cached response bodies are kept under this size, the least recently used go first.
+pub const ETAGS_MAX_BYTES: usize = 256 * 1024 * 1024;
+pub const CACHE_RECOMMENDATIONS: &str = "recommendations.json";
 pub const CONFIG_COMMON: &str = "config.common.json";
 
 pub const UD_SOCKET_PATH: &str = "/tmp/unusable_eve_tradeworks_hotkey.sock";
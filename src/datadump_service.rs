@@ -116,6 +116,41 @@ impl DatadumpService {
     }
 }
 
+/// Include and exclude lists of market groups, resolved to group ids
+/// together with all of their child groups.
+#[derive(Debug, Default)]
+pub struct GroupFilter {
+    include: Option<Vec<i32>>,
+    exclude: Option<Vec<i32>>,
+}
+
+impl GroupFilter {
+    pub fn new(
+        data_service: &DatadumpService,
+        include_groups: Option<&Vec<String>>,
+        exclude_groups: Option<&Vec<String>>,
+    ) -> anyhow::Result<Self> {
+        let include = include_groups
+            .map(|include_groups| data_service.get_group_ids_for_groups(include_groups))
+            .transpose()?;
+        let exclude = exclude_groups
+            .map(|exclude_groups| data_service.get_group_ids_for_groups(exclude_groups))
+            .transpose()?;
+        log::debug!("Include groups {include:?}, exclude groups {exclude:?}");
+        Ok(Self { include, exclude })
+    }
+
+    pub fn matches(&self, market_group_id: Option<i32>) -> bool {
+        let included = self.include.as_ref().map_or(true, |include| {
+            market_group_id.map_or(false, |id| include.contains(&id))
+        });
+        let excluded = self.exclude.as_ref().map_or(false, |exclude| {
+            market_group_id.map_or(false, |id| exclude.contains(&id))
+        });
+        included && !excluded
+    }
+}
+
 #[derive(Debug)]
 pub struct ReprocessItemInfo {
     pub reprocessed_into: Vec<ReprocessInfo>,
@@ -1,6 +1,11 @@
+pub mod backtest;
+pub mod fees;
 pub mod help;
 pub mod items_prices;
+pub mod ledger;
+pub mod my_orders;
 pub mod sell_buy;
 pub mod sell_reprocess;
 pub mod sell_sell;
+pub mod simulation;
 pub mod station_trading;
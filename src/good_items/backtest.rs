@@ -0,0 +1,501 @@
+use std::collections::HashMap;
+
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+use term_table::{row::Row, table_cell::TableCell};
+
+use crate::{
+    cached_data::CachedStuff,
+    config::CommonConfig,
+    consts::DATE_FMT,
+    item_type::ItemHistory,
+    load_create::{load_or_create_history, load_or_create_transactions, load_recommendations},
+    requests::{
+        item_history::ItemHistoryEsiService, service::EsiRequestsService,
+        transactions::WalletEsiService,
+    },
+    StationIdData,
+};
+
+use super::ledger::LedgerTransaction;
+
+/// Item a run recommended to trade, kept to be compared with what happened later.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Recommendation {
+    pub time: DateTime<Utc>,
+    /// Mode of the run, e.g. `sell-sell`.
+    pub mode: String,
+    /// Name of the station the item is bought in.
+    pub source: String,
+    /// Name of the station the item is sold in.
+    pub destination: String,
+    pub type_id: i32,
+    pub name: String,
+    pub quantity: i64,
+    /// Predicted price of a unit, without fees.
+    pub buy_price: f64,
+    /// Predicted price of a unit, without fees.
+    pub sell_price: f64,
+    /// Predicted profit of the whole quantity.
+    pub profit: f64,
+    /// Config settings the prediction depends on, e.g. `markup_if_no_orders_dest=0.35`.
+    pub knobs: Vec<String>,
+}
+
+impl Recommendation {
+    /// Predicted profit relative to the predicted expenses.
+    pub fn margin(&self) -> f64 {
+        self.profit / (self.buy_price * self.quantity as f64)
+    }
+}
+
+/// Adds `new` recommendations to the `previous` ones, oldest first. Of the ones
+/// for the same trade on the same day only the latest is kept, so running a mode
+/// again doesn't count its trades again, and none made before `keep_since`.
+pub fn merge_recommendations(
+    previous: Vec<Recommendation>,
+    new: Vec<Recommendation>,
+    keep_since: DateTime<Utc>,
+) -> Vec<Recommendation> {
+    let mut merged = new
+        .into_iter()
+        .chain(previous)
+        .filter(|x| x.time >= keep_since)
+        .sorted_by_key(|x| std::cmp::Reverse(x.time))
+        .unique_by(|x| {
+            (
+                x.mode.clone(),
+                x.source.clone(),
+                x.destination.clone(),
+                x.type_id,
+                x.time.date_naive(),
+            )
+        })
+        .collect_vec();
+    merged.sort_by_key(|x| x.time);
+    merged
+}
+
+pub struct BacktestService<'a> {
+    pub wallet_esi_service: &'a WalletEsiService<'a>,
+    pub cache: &'a mut CachedStuff,
+    pub esi_requests: &'a EsiRequestsService<'a>,
+    pub esi_history: &'a ItemHistoryEsiService<'a>,
+    pub config: &'a CommonConfig,
+}
+
+impl<'a> BacktestService<'a> {
+    /// Compares recorded recommendations with wallet transactions and market
+    /// history of the days that followed them.
+    pub async fn backtest(&mut self, character_id: i32) -> anyhow::Result<BacktestData> {
+        let recommendations = load_recommendations(self.cache).await?;
+        log::info!("Backtesting {} recommendations", recommendations.len());
+
+        let transactions =
+            load_or_create_transactions(self.cache, self.wallet_esi_service, character_id)
+                .await?
+                .iter()
+                .filter_map(LedgerTransaction::parse)
+                .collect_vec();
+
+        let mut stations = HashMap::new();
+        for name in recommendations
+            .iter()
+            .flat_map(|x| [&x.source, &x.destination])
+            .unique()
+        {
+            let Some(station) = self.config.stations.iter().find(|x| &x.name == name) else {
+                log::warn!("Station {name} is not in the config anymore, skipping it");
+                continue;
+            };
+            let id = self
+                .esi_requests
+                .find_region_id_station(station, character_id)
+                .await?;
+            stations.insert(
+                name.clone(),
+                BacktestStation {
+                    location_id: id.station_id.id,
+                    broker_fee: station.broker_fee,
+                    region: id,
+                },
+            );
+        }
+
+        let mut histories = HashMap::new();
+        let by_region = recommendations
+            .iter()
+            .filter_map(|x| Some((stations.get(&x.destination)?.region, x.type_id)))
+            .into_group_map_by(|(region, _)| region.region_id);
+        for (region_id, types) in by_region {
+            let region = types[0].0;
+            let types = types.into_iter().map(|(_, x)| x).unique().collect_vec();
+            let history =
+                load_or_create_history(self.cache, region, self.config, self.esi_history, &types)
+                    .await?;
+            histories.insert(region_id, history);
+        }
+
+        let window = Duration::try_days(self.config.backtest.window_days).unwrap();
+        let outcomes = recommendations
+            .into_iter()
+            .map(|recommendation| {
+                let source = stations.get(&recommendation.source);
+                let destination = stations.get(&recommendation.destination);
+                let history = destination.and_then(|x| {
+                    histories
+                        .get(&x.region.region_id)?
+                        .get(&recommendation.type_id)
+                });
+                evaluate(
+                    recommendation,
+                    &transactions,
+                    source,
+                    destination,
+                    history,
+                    self.config.sales_tax,
+                    window,
+                )
+            })
+            .collect();
+        Ok(BacktestData { outcomes })
+    }
+}
+
+/// Station named in a recommendation.
+#[derive(Clone, Copy)]
+pub struct BacktestStation {
+    pub location_id: i64,
+    pub broker_fee: f64,
+    pub region: StationIdData,
+}
+
+/// Trades of the character in a station during the backtest window.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Traded {
+    pub quantity: i64,
+    pub average_price: f64,
+}
+
+#[derive(Debug, Clone)]
+pub struct Outcome {
+    pub recommendation: Recommendation,
+    pub bought: Option<Traded>,
+    pub sold: Option<Traded>,
+    /// Average price in the destination region during the window, weighted by volume.
+    pub market_price: Option<f64>,
+    /// Profit of the units both bought and sold during the window.
+    pub realized_profit: Option<f64>,
+    realized_margin: Option<f64>,
+}
+
+impl Outcome {
+    /// Predicted sell price relative to the achieved one, positive when overestimated.
+    pub fn sell_price_error(&self) -> Option<f64> {
+        self.sold
+            .map(|x| self.recommendation.sell_price / x.average_price - 1.)
+    }
+
+    /// Predicted sell price relative to the market price, positive when overestimated.
+    pub fn market_price_error(&self) -> Option<f64> {
+        self.market_price
+            .map(|x| self.recommendation.sell_price / x - 1.)
+    }
+
+    /// Predicted margin minus the realized one, positive when overestimated.
+    pub fn margin_error(&self) -> Option<f64> {
+        self.realized_margin
+            .map(|x| self.recommendation.margin() - x)
+    }
+}
+
+/// Looks at what happened in the `window` after a recommendation.
+pub fn evaluate(
+    recommendation: Recommendation,
+    transactions: &[LedgerTransaction],
+    source: Option<&BacktestStation>,
+    destination: Option<&BacktestStation>,
+    history: Option<&ItemHistory>,
+    sales_tax: f64,
+    window: Duration,
+) -> Outcome {
+    let from = recommendation.time;
+    let to = from + window;
+    let traded = |station: Option<&BacktestStation>, is_buy: bool| {
+        let location_id = station?.location_id;
+        let (quantity, sum) = transactions
+            .iter()
+            .filter(|x| {
+                x.type_id == recommendation.type_id
+                    && x.location_id == location_id
+                    && x.is_buy == is_buy
+                    && x.date >= from
+                    && x.date < to
+            })
+            .fold((0, 0.), |(quantity, sum), x| {
+                (
+                    quantity + x.quantity,
+                    sum + x.unit_price * x.quantity as f64,
+                )
+            });
+        (quantity > 0).then_some(Traded {
+            quantity,
+            average_price: sum / quantity as f64,
+        })
+    };
+    let bought = traded(source, true);
+    let sold = traded(destination, false);
+
+    let (realized_profit, realized_margin) = match (bought, sold, source, destination) {
+        (Some(bought), Some(sold), Some(source), Some(destination)) => {
+            let quantity = bought.quantity.min(sold.quantity) as f64;
+            let expenses = bought.average_price * (1. + source.broker_fee);
+            let income = sold.average_price * (1. - destination.broker_fee - sales_tax);
+            (
+                Some((income - expenses) * quantity),
+                Some((income - expenses) / expenses),
+            )
+        }
+        _ => (None, None),
+    };
+
+    let market_price = history.and_then(|history| {
+        let (from, to) = (from.date_naive(), to.date_naive());
+        let (volume, sum) = history
+            .history
+            .iter()
+            .filter(|x| x.volume > 0)
+            .filter_map(|x| {
+                let date = NaiveDate::parse_from_str(&x.date, DATE_FMT).ok()?;
+                (date >= from && date <= to).then_some((x.volume, x.average?))
+            })
+            .fold((0, 0.), |(volume, sum), (day_volume, average)| {
+                (volume + day_volume, sum + average * day_volume as f64)
+            });
+        (volume > 0).then_some(sum / volume as f64)
+    });
+
+    Outcome {
+        recommendation,
+        bought,
+        sold,
+        market_price,
+        realized_profit,
+        realized_margin,
+    }
+}
+
+/// Mean errors of the predictions that depend on a config setting.
+#[derive(Debug, Clone, PartialEq)]
+pub struct KnobAccuracy {
+    pub knob: String,
+    pub count: usize,
+    pub sell_price_error: Option<f64>,
+    pub market_price_error: Option<f64>,
+    pub margin_error: Option<f64>,
+}
+
+pub fn knob_accuracy(outcomes: &[Outcome]) -> Vec<KnobAccuracy> {
+    let mean = |values: Vec<f64>| {
+        (!values.is_empty()).then(|| values.iter().sum::<f64>() / values.len() as f64)
+    };
+    outcomes
+        .iter()
+        .flat_map(|x| x.recommendation.knobs.iter().map(move |knob| (knob, x)))
+        .into_group_map()
+        .into_iter()
+        .map(|(knob, outcomes)| KnobAccuracy {
+            knob: knob.clone(),
+            count: outcomes.len(),
+            sell_price_error: mean(
+                outcomes
+                    .iter()
+                    .filter_map(|x| x.sell_price_error())
+                    .collect(),
+            ),
+            market_price_error: mean(
+                outcomes
+                    .iter()
+                    .filter_map(|x| x.market_price_error())
+                    .collect(),
+            ),
+            margin_error: mean(outcomes.iter().filter_map(|x| x.margin_error()).collect()),
+        })
+        .sorted_by(|a, b| a.knob.cmp(&b.knob))
+        .collect()
+}
+
+pub struct BacktestData {
+    pub outcomes: Vec<Outcome>,
+}
+
+fn optional(x: Option<f64>) -> String {
+    x.map_or("N/A".to_string(), |x| format!("{:.2}", x))
+}
+
+impl BacktestData {
+    pub fn make_table_outcomes<'b>(&self, name_length: usize) -> Vec<Row<'b>> {
+        std::iter::once(Row::new(vec![
+            TableCell::new("time"),
+            TableCell::new("mode"),
+            TableCell::new("itm nm"),
+            TableCell::new("rcmnd"),
+            TableCell::new("bght"),
+            TableCell::new("sld"),
+            TableCell::new("buy p"),
+            TableCell::new("real buy p"),
+            TableCell::new("sell p"),
+            TableCell::new("real sell p"),
+            TableCell::new("mkt p"),
+            TableCell::new("rgh prft"),
+            TableCell::new("real prft"),
+        ]))
+        .chain(self.outcomes.iter().map(|it| {
+            let rec = &it.recommendation;
+            let short_name = rec.name[..(name_length.min(rec.name.len()))].to_owned();
+            Row::new(vec![
+                TableCell::new(rec.time.format("%Y-%m-%d %H:%M")),
+                TableCell::new(&rec.mode),
+                TableCell::new(short_name),
+                TableCell::new(format!("{}", rec.quantity)),
+                TableCell::new(format!("{}", it.bought.map_or(0, |x| x.quantity))),
+                TableCell::new(format!("{}", it.sold.map_or(0, |x| x.quantity))),
+                TableCell::new(format!("{:.2}", rec.buy_price)),
+                TableCell::new(optional(it.bought.map(|x| x.average_price))),
+                TableCell::new(format!("{:.2}", rec.sell_price)),
+                TableCell::new(optional(it.sold.map(|x| x.average_price))),
+                TableCell::new(optional(it.market_price)),
+                TableCell::new(format!("{:.2}", rec.profit)),
+                TableCell::new(optional(it.realized_profit)),
+            ])
+        }))
+        .collect()
+    }
+
+    pub fn make_table_knobs<'b>(&self) -> Vec<Row<'b>> {
+        std::iter::once(Row::new(vec![
+            TableCell::new("setting"),
+            TableCell::new("count"),
+            TableCell::new("sell p err"),
+            TableCell::new("mkt p err"),
+            TableCell::new("mrgn err"),
+        ]))
+        .chain(knob_accuracy(&self.outcomes).into_iter().map(|it| {
+            Row::new(vec![
+                TableCell::new(it.knob),
+                TableCell::new(format!("{}", it.count)),
+                TableCell::new(optional(it.sell_price_error)),
+                TableCell::new(optional(it.market_price_error)),
+                TableCell::new(optional(it.margin_error)),
+            ])
+        }))
+        .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        item_type::MarketsRegionHistory,
+        test_fixtures::{start, transaction},
+        StationId,
+    };
+
+    use super::*;
+
+    fn station(location_id: i64) -> BacktestStation {
+        BacktestStation {
+            location_id,
+            broker_fee: 0.01,
+            region: StationIdData {
+                station_id: StationId {
+                    is_citadel: false,
+                    id: location_id,
+                },
+                system_id: 1,
+                region_id: 1,
+            },
+        }
+    }
+
+    fn recommendation(hours: i64) -> Recommendation {
+        Recommendation {
+            time: start() + Duration::try_hours(hours).unwrap(),
+            mode: "sell-sell".to_string(),
+            source: "src".to_string(),
+            destination: "dst".to_string(),
+            type_id: 34,
+            name: "Tritanium".to_string(),
+            quantity: 10,
+            buy_price: 10.,
+            sell_price: 15.,
+            profit: 40.,
+            knobs: vec!["markup_if_no_orders_dest=0.5".to_string()],
+        }
+    }
+
+    #[test]
+    fn test_markup_overestimates() {
+        let recommendation = recommendation(0);
+        let transactions = vec![
+            transaction(1, 1, true, 10, 10.),
+            transaction(30, 2, false, 10, 12.),
+            // outside of the window
+            transaction(24 * 30, 2, false, 10, 20.),
+        ];
+        let history = ItemHistory {
+            id: 34,
+            history: vec![MarketsRegionHistory {
+                average: Some(12.5),
+                date: "2024-01-02".to_string(),
+                highest: Some(13.),
+                lowest: Some(12.),
+                order_count: 5,
+                volume: 100,
+            }],
+        };
+
+        let outcome = evaluate(
+            recommendation,
+            &transactions,
+            Some(&station(1)),
+            Some(&station(2)),
+            Some(&history),
+            0.08,
+            Duration::try_days(14).unwrap(),
+        );
+        assert_eq!(outcome.sold.unwrap().average_price, 12.);
+        assert_eq!(outcome.market_price, Some(12.5));
+
+        let accuracy = knob_accuracy(&[outcome]);
+        assert_eq!(accuracy.len(), 1);
+        assert!((accuracy[0].sell_price_error.unwrap() - 0.25).abs() < 1e-9);
+        assert!(accuracy[0].margin_error.unwrap() > 0.);
+    }
+
+    #[test]
+    fn test_merge_keeps_latest_of_a_day() {
+        let previous = vec![
+            // too old to keep
+            recommendation(-24 * 60),
+            recommendation(1),
+        ];
+        let new = vec![
+            Recommendation {
+                profit: 50.,
+                ..recommendation(2)
+            },
+            Recommendation {
+                type_id: 35,
+                ..recommendation(2)
+            },
+        ];
+
+        let merged =
+            merge_recommendations(previous, new, start() - Duration::try_days(30).unwrap());
+
+        let kept = merged.iter().map(|x| (x.type_id, x.profit)).collect_vec();
+        assert_eq!(kept, vec![(34, 50.), (35, 40.)]);
+    }
+}
@@ -0,0 +1,565 @@
+use std::collections::HashMap;
+
+use chrono::{DateTime, Duration, Utc};
+use itertools::Itertools;
+use ordered_float::NotNan;
+use term_table::{row::Row, table_cell::TableCell};
+
+use crate::{
+    cached_data::CachedStuff,
+    config::{CommonConfig, ConfigRelist},
+    item_type::{CharacterOrder, CharacterSkills, JournalEntry},
+    load_create::load_or_create_transactions,
+    requests::{service::EsiRequestsService, transactions::WalletEsiService},
+    stat::MedianStat,
+};
+
+use super::{ledger::LedgerTransaction, station_trading::relist_fee_rate};
+
+/// Broker fee charged when it would be lower, fees this small say nothing about the rate.
+const MIN_BROKER_FEE: f64 = 100.;
+/// Broker fee rates within this fraction of each other are the same rate.
+const BROKER_RATE_TOLERANCE: f64 = 0.1;
+
+const ACCOUNTING: i32 = 16622;
+const BROKER_RELATIONS: i32 = 3446;
+const ADVANCED_BROKER_RELATIONS: i32 = 3451;
+const CONNECTIONS: i32 = 3359;
+const DIPLOMACY: i32 = 3357;
+
+pub struct FeeModelService<'a> {
+    pub esi_requests: &'a EsiRequestsService<'a>,
+}
+
+impl<'a> FeeModelService<'a> {
+    /// Replaces the sales tax, broker fees of NPC stations and the relist discount
+    /// in the config with the ones skills and standings of the character give.
+    pub async fn apply(&self, config: &mut CommonConfig, character_id: i32) -> anyhow::Result<()> {
+        let skills = self.esi_requests.get_character_skills(character_id).await?;
+        let standings = self
+            .esi_requests
+            .get_character_standings(character_id)
+            .await?;
+        let standing = |from_id: i32| {
+            let raw = standings
+                .iter()
+                .find(|x| x.from_id == from_id)
+                .map_or(0., |x| x.standing);
+            effective_standing(&skills, raw)
+        };
+
+        config.sales_tax = sales_tax(&skills);
+        log::info!("Sales tax is {:.4}", config.sales_tax);
+        config.station_trade.relist.advanced_broker_relations =
+            skills.level(ADVANCED_BROKER_RELATIONS);
+        for station in config.stations.iter_mut().filter(|x| !x.is_citadel) {
+            let station_id = self
+                .esi_requests
+                .find_region_id_station(station, character_id)
+                .await?
+                .station_id
+                .id;
+            let owner = self.esi_requests.get_station_owner(station_id).await?;
+            station.broker_fee = npc_broker_fee(
+                &skills,
+                owner.faction_id.map_or(0., standing),
+                standing(owner.corporation_id),
+            );
+            log::info!(
+                "Broker fee in {} is {:.4}",
+                station.name,
+                station.broker_fee
+            );
+        }
+        Ok(())
+    }
+}
+
+/// 8% reduced by 11% per level of Accounting.
+pub fn sales_tax(skills: &CharacterSkills) -> f64 {
+    0.08 * (1. - 0.11 * skills.level(ACCOUNTING) as f64)
+}
+
+/// Standing NPCs see, the raw one moved 4% towards 10 per level of Connections,
+/// or of Diplomacy when the raw standing is negative.
+pub fn effective_standing(skills: &CharacterSkills, standing: f64) -> f64 {
+    let skill = if standing < 0. {
+        DIPLOMACY
+    } else {
+        CONNECTIONS
+    };
+    standing + (10. - standing) * 0.04 * skills.level(skill) as f64
+}
+
+/// 3% reduced by 0.3% per level of Broker Relations, by 0.03% per point of
+/// standing with the faction of the station and by 0.02% per point of standing
+/// with its corporation.
+pub fn npc_broker_fee(
+    skills: &CharacterSkills,
+    faction_standing: f64,
+    corporation_standing: f64,
+) -> f64 {
+    0.03 - 0.003 * skills.level(BROKER_RELATIONS) as f64
+        - 0.0003 * faction_standing
+        - 0.0002 * corporation_standing
+}
+
+pub struct FeesService<'a> {
+    pub wallet_esi_service: &'a WalletEsiService<'a>,
+    pub cache: &'a mut CachedStuff,
+    pub esi_requests: &'a EsiRequestsService<'a>,
+    pub config: &'a CommonConfig,
+}
+
+impl<'a> FeesService<'a> {
+    /// Derives broker fees and sales tax paid recently from the wallet journal
+    /// and compares them with the configured ones.
+    pub async fn get_fees(&mut self, character_id: i32) -> anyhow::Result<FeesData> {
+        let since = Utc::now() - Duration::try_days(self.config.journal_fees.days).unwrap();
+        let payments = self
+            .esi_requests
+            .get_wallet_journal(character_id)
+            .await?
+            .iter()
+            .filter_map(FeePayment::parse)
+            .filter(|x| x.date >= since)
+            .collect_vec();
+
+        let mut orders = self.esi_requests.get_character_orders(character_id).await?;
+        orders.extend(
+            self.esi_requests
+                .get_character_orders_history(character_id)
+                .await?,
+        );
+        let transactions =
+            load_or_create_transactions(self.cache, self.wallet_esi_service, character_id)
+                .await?
+                .iter()
+                .filter_map(LedgerTransaction::parse)
+                .collect_vec();
+
+        let paid = PaidFees::from_payments(
+            &payments,
+            &orders,
+            &transactions,
+            &self.config.station_trade.relist,
+        );
+
+        let mut broker_fees = Vec::new();
+        for station in &self.config.stations {
+            let location_id = self
+                .esi_requests
+                .find_region_id_station(station, character_id)
+                .await?
+                .station_id
+                .id;
+            broker_fees.push(FeeComparison {
+                name: station
+                    .short
+                    .clone()
+                    .unwrap_or_else(|| station.name.clone()),
+                configured: station.broker_fee,
+                paid: paid.broker_fees.get(&location_id).copied(),
+            });
+        }
+        let data = FeesData {
+            broker_fees,
+            sales_tax: FeeComparison {
+                name: "all".to_string(),
+                configured: self.config.sales_tax,
+                paid: paid.sales_tax,
+            },
+        };
+
+        for (kind, fee) in data.comparisons() {
+            let drift = fee.drift(self.config.journal_fees.min_samples);
+            if drift.map_or(false, |x| x.abs() > self.config.journal_fees.drift_warning) {
+                log::warn!(
+                    "{kind} in {} is {:.4} in the config but {:.4} was paid recently",
+                    fee.name,
+                    fee.configured,
+                    fee.paid.unwrap().rate
+                );
+            }
+        }
+        Ok(data)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FeeKind {
+    BrokerFee,
+    SalesTax,
+}
+
+/// Fee paid according to the wallet journal.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FeePayment {
+    pub kind: FeeKind,
+    pub date: DateTime<Utc>,
+    /// Positive amount of isk paid.
+    pub amount: f64,
+    /// Market transaction the fee was paid for, if the journal says.
+    pub transaction_id: Option<i64>,
+}
+
+impl FeePayment {
+    pub fn parse(value: &JournalEntry) -> Option<Self> {
+        let kind = match value.ref_type.as_str() {
+            "brokers_fee" => FeeKind::BrokerFee,
+            "transaction_tax" => FeeKind::SalesTax,
+            _ => return None,
+        };
+        let date = DateTime::parse_from_rfc3339(&value.date)
+            .ok()?
+            .with_timezone(&Utc);
+        let transaction_id = value
+            .context_id
+            .filter(|_| value.context_id_type.as_deref() == Some("market_transaction_id"));
+        Some(Self {
+            kind,
+            date,
+            amount: value.amount?.abs(),
+            transaction_id,
+        })
+    }
+}
+
+/// Rate paid, median of the rates of individual payments.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PaidRate {
+    pub rate: f64,
+    pub samples: usize,
+}
+
+impl PaidRate {
+    fn from_rates(rates: Vec<f64>) -> Option<Self> {
+        let samples = rates.len();
+        let rate = rates
+            .into_iter()
+            .filter_map(|x| NotNan::new(x).ok())
+            .median()?;
+        Some(Self {
+            rate: *rate,
+            samples,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct PaidFees {
+    /// Broker fee by location of the order.
+    pub broker_fees: HashMap<i64, PaidRate>,
+    pub sales_tax: Option<PaidRate>,
+}
+
+impl PaidFees {
+    /// Relates payments to what they were paid for. Broker fees are paid when an
+    /// order is issued or modified, so they are matched with the order issued at
+    /// the same second; sales tax is matched with the sale it is for.
+    ///
+    /// ESI resets `issued` when an order is modified, so the matched fee may be a
+    /// relist fee on the volume left. Partly filled orders are skipped as that
+    /// volume is unknown, the rest are told apart with [`placement_rate`].
+    pub fn from_payments(
+        payments: &[FeePayment],
+        orders: &[CharacterOrder],
+        transactions: &[LedgerTransaction],
+        relist: &ConfigRelist,
+    ) -> Self {
+        let orders_by_issued = orders
+            .iter()
+            .unique_by(|x| x.order_id)
+            .filter(|x| x.volume_remain == x.volume_total)
+            .filter_map(|x| {
+                let issued = DateTime::parse_from_rfc3339(&x.issued).ok()?;
+                Some((issued.with_timezone(&Utc), x))
+            })
+            .into_group_map();
+        let sales = transactions.iter().filter(|x| !x.is_buy).collect_vec();
+        let sales_by_date = sales.iter().into_group_map_by(|x| x.date);
+        let sales_by_id = sales
+            .iter()
+            .map(|x| (x.transaction_id, *x))
+            .collect::<HashMap<_, _>>();
+
+        let mut broker_rates = HashMap::<i64, Vec<f64>>::new();
+        let mut tax_rates = Vec::new();
+        for payment in payments {
+            match payment.kind {
+                FeeKind::BrokerFee => {
+                    if payment.amount <= MIN_BROKER_FEE {
+                        continue;
+                    }
+                    let order = match orders_by_issued.get(&payment.date).map(|x| x.as_slice()) {
+                        Some([order]) => order,
+                        _ => continue,
+                    };
+                    let value = order.price * order.volume_total as f64;
+                    broker_rates
+                        .entry(order.location_id)
+                        .or_default()
+                        .push(payment.amount / value);
+                }
+                FeeKind::SalesTax => {
+                    let sale = match payment.transaction_id {
+                        Some(id) => sales_by_id.get(&id).copied(),
+                        None => match sales_by_date.get(&payment.date).map(|x| x.as_slice()) {
+                            Some([sale]) => Some(**sale),
+                            _ => None,
+                        },
+                    };
+                    let Some(sale) = sale else {
+                        continue;
+                    };
+                    let value = sale.unit_price * sale.quantity as f64;
+                    tax_rates.push(payment.amount / value);
+                }
+            }
+        }
+
+        let relist_discount = relist_fee_rate(1., relist, 1.);
+        Self {
+            broker_fees: broker_rates
+                .into_iter()
+                .filter_map(|(location, rates)| {
+                    Some((location, placement_rate(&rates, relist_discount)?))
+                })
+                .collect(),
+            sales_tax: PaidRate::from_rates(tax_rates),
+        }
+    }
+}
+
+/// Broker fee rate most of `rates` agree with, each one taken either as paid
+/// for placing an order or as a relist fee, `relist_discount` of the full rate.
+/// When both readings fit equally well, the rates are taken as placements.
+fn placement_rate(rates: &[f64], relist_discount: f64) -> Option<PaidRate> {
+    let readings = |rate: f64| {
+        std::iter::once(rate).chain((relist_discount > 0.).then(|| rate / relist_discount))
+    };
+    let agreeing = |candidate: f64| {
+        rates
+            .iter()
+            .filter_map(|&rate| {
+                readings(rate).find(|x| (x - candidate).abs() <= candidate * BROKER_RATE_TOLERANCE)
+            })
+            .collect_vec()
+    };
+    let best = rates
+        .iter()
+        .flat_map(|&rate| readings(rate))
+        .map(agreeing)
+        .fold(
+            Vec::new(),
+            |best, x| if x.len() > best.len() { x } else { best },
+        );
+    PaidRate::from_rates(best)
+}
+
+/// Configured rate next to the paid one.
+#[derive(Debug, Clone)]
+pub struct FeeComparison {
+    pub name: String,
+    pub configured: f64,
+    pub paid: Option<PaidRate>,
+}
+
+impl FeeComparison {
+    /// How much more was paid than configured, if enough was paid to tell.
+    pub fn drift(&self, min_samples: usize) -> Option<f64> {
+        self.paid
+            .filter(|x| x.samples >= min_samples)
+            .map(|x| x.rate - self.configured)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct FeesData {
+    /// Broker fees in the order of the configured stations.
+    pub broker_fees: Vec<FeeComparison>,
+    pub sales_tax: FeeComparison,
+}
+
+impl FeesData {
+    fn comparisons(&self) -> impl Iterator<Item = (&'static str, &FeeComparison)> {
+        self.broker_fees
+            .iter()
+            .map(|x| ("Broker fee", x))
+            .chain(std::iter::once(("Sales tax", &self.sales_tax)))
+    }
+
+    /// Replaces the configured rates with the paid ones where there are enough samples.
+    pub fn apply(&self, config: &mut CommonConfig) {
+        let min_samples = config.journal_fees.min_samples;
+        for (station, fee) in config.stations.iter_mut().zip(&self.broker_fees) {
+            if let Some(drift) = fee.drift(min_samples) {
+                station.broker_fee += drift;
+                log::info!(
+                    "Using paid broker fee {:.4} in {}",
+                    station.broker_fee,
+                    fee.name
+                );
+            }
+        }
+        if let Some(drift) = self.sales_tax.drift(min_samples) {
+            config.sales_tax += drift;
+            log::info!("Using paid sales tax {:.4}", config.sales_tax);
+        }
+    }
+
+    pub fn make_table_fees<'b>(&self, min_samples: usize) -> Vec<Row<'b>> {
+        std::iter::once(Row::new(vec![
+            TableCell::new("fee"),
+            TableCell::new("where"),
+            TableCell::new("config"),
+            TableCell::new("paid"),
+            TableCell::new("samples"),
+            TableCell::new("drift"),
+        ]))
+        .chain(self.comparisons().map(|(kind, it)| {
+            let rate = |x: Option<f64>| x.map_or("N/A".to_string(), |x| format!("{:.4}", x));
+            Row::new(vec![
+                TableCell::new(kind),
+                TableCell::new(&it.name),
+                TableCell::new(format!("{:.4}", it.configured)),
+                TableCell::new(rate(it.paid.map(|x| x.rate))),
+                TableCell::new(it.paid.map_or(0, |x| x.samples)),
+                TableCell::new(rate(it.drift(min_samples))),
+            ])
+        }))
+        .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        item_type::CharacterSkill,
+        test_fixtures::{character_order, transaction},
+    };
+
+    fn date(x: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(x).unwrap().with_timezone(&Utc)
+    }
+
+    fn order(order_id: i64, location_id: i64, issued: &str) -> CharacterOrder {
+        CharacterOrder {
+            location_id,
+            volume_remain: 100,
+            volume_total: 100,
+            issued: issued.to_string(),
+            ..character_order(order_id, false, 100.)
+        }
+    }
+
+    fn payment(kind: FeeKind, at: &str, amount: f64, id: Option<i64>) -> FeePayment {
+        FeePayment {
+            kind,
+            date: date(at),
+            amount,
+            transaction_id: id,
+        }
+    }
+
+    #[test]
+    fn test_skill_fees() {
+        let skills = CharacterSkills {
+            skills: vec![
+                CharacterSkill {
+                    skill_id: ACCOUNTING,
+                    active_skill_level: 4,
+                },
+                CharacterSkill {
+                    skill_id: BROKER_RELATIONS,
+                    active_skill_level: 5,
+                },
+            ],
+        };
+
+        assert!((sales_tax(&skills) - 0.0448).abs() < 1e-9);
+        assert!((npc_broker_fee(&skills, 5., -2.) - 0.0139).abs() < 1e-9);
+        let untrained = CharacterSkills { skills: Vec::new() };
+        assert!((npc_broker_fee(&untrained, 0., 0.) - 0.03).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_effective_standing() {
+        let skills = CharacterSkills {
+            skills: vec![
+                CharacterSkill {
+                    skill_id: CONNECTIONS,
+                    active_skill_level: 5,
+                },
+                CharacterSkill {
+                    skill_id: DIPLOMACY,
+                    active_skill_level: 2,
+                },
+            ],
+        };
+
+        assert!((effective_standing(&skills, 2.) - 3.6).abs() < 1e-9);
+        assert!((effective_standing(&skills, -5.) - -3.8).abs() < 1e-9);
+        let untrained = CharacterSkills { skills: Vec::new() };
+        assert!((effective_standing(&untrained, 2.) - 2.).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_paid_rates() {
+        let orders = vec![
+            order(1, 1, "2024-01-01T00:00:00Z"),
+            order(2, 1, "2024-01-02T00:00:00Z"),
+            order(3, 2, "2024-01-03T00:00:00Z"),
+            // two orders issued at once can't be told apart
+            order(4, 2, "2024-01-04T00:00:00Z"),
+            order(5, 2, "2024-01-04T00:00:00Z"),
+        ];
+        let sale = transaction(7, 1, false, 10, 1000.);
+        let payments = vec![
+            payment(FeeKind::BrokerFee, "2024-01-01T00:00:00Z", 300., None),
+            payment(FeeKind::BrokerFee, "2024-01-02T00:00:00Z", 300., None),
+            payment(FeeKind::BrokerFee, "2024-01-03T00:00:00Z", 150., None),
+            payment(FeeKind::BrokerFee, "2024-01-04T00:00:00Z", 500., None),
+            payment(FeeKind::SalesTax, "2024-01-06T00:00:00Z", 360., Some(7)),
+        ];
+
+        let paid = PaidFees::from_payments(&payments, &orders, &[sale], &ConfigRelist::default());
+
+        let rate = |rate, samples| Some(PaidRate { rate, samples });
+        assert_eq!(paid.broker_fees.get(&1).copied(), rate(0.03, 2));
+        assert_eq!(paid.broker_fees.get(&2).copied(), rate(0.015, 1));
+        assert_eq!(paid.sales_tax, rate(0.036, 1));
+    }
+
+    #[test]
+    fn test_relist_fees_are_not_placement_rates() {
+        let orders = vec![
+            order(1, 1, "2024-01-01T00:00:00Z"),
+            // modified after issued, relisting costs half the broker fee
+            order(2, 1, "2024-01-02T00:00:00Z"),
+            order(3, 1, "2024-01-03T00:00:00Z"),
+            // relisted after a part was sold, on a volume that isn't known
+            CharacterOrder {
+                volume_remain: 40,
+                ..order(4, 1, "2024-01-04T00:00:00Z")
+            },
+        ];
+        let payments = vec![
+            payment(FeeKind::BrokerFee, "2024-01-01T00:00:00Z", 300., None),
+            payment(FeeKind::BrokerFee, "2024-01-02T00:00:00Z", 150., None),
+            payment(FeeKind::BrokerFee, "2024-01-03T00:00:00Z", 150., None),
+            payment(FeeKind::BrokerFee, "2024-01-04T00:00:00Z", 120., None),
+        ];
+
+        let paid = PaidFees::from_payments(&payments, &orders, &[], &ConfigRelist::default());
+
+        assert_eq!(
+            paid.broker_fees.get(&1).copied(),
+            Some(PaidRate {
+                rate: 0.03,
+                samples: 3
+            })
+        );
+    }
+}
@@ -2,12 +2,17 @@ use anyhow::anyhow;
 use good_lp::{IntoAffineExpression, SolverModel};
 use itertools::Itertools;
 use ordered_float::NotNan;
+use term_table::{row::Row, table_cell::TableCell};
 
 use crate::{
-    config::CommonConfig,
-    item_type::{ItemHistoryDay, ItemTypeAveraged, Order},
+    config::{CommonConfig, ConfigTrendFilter, Objective, PriceEstimator},
+    item_type::{ItemHistoryDay, ItemTypeAveraged, MarketData, Order},
+    order_ext::suspicious_orders,
     requests::service::to_not_nan,
-    stat::{AverageStat, MedianStat},
+    stat::{
+        AverageStat, EwmaStat, MedianStat, OutlierStat, SlopeStat, StdDevStat, TrimmedMeanStat,
+        WeightedMedianStat,
+    },
 };
 
 #[derive(Debug, Clone, Copy)]
@@ -16,6 +21,41 @@ pub struct ItemProfitData {
     pub expenses: f64,
     pub sell_price: f64,
     pub max_item_amount: i64,
+    /// Expected days until the bought items are sold, see [`locked_days`].
+    pub days_to_sell: f64,
+}
+
+/// Shortest time capital is locked up for, even items sold right away
+/// have to be bought and hauled first.
+const MIN_LOCKED_DAYS: f64 = 1.;
+
+/// Days capital is locked up for. Items with unknown time to sell never
+/// pay back as far as per day metrics are concerned.
+pub fn locked_days(days_to_sell: Option<f64>) -> f64 {
+    days_to_sell.unwrap_or(f64::INFINITY).max(MIN_LOCKED_DAYS)
+}
+
+impl ItemProfitData {
+    pub fn unit_profit(&self) -> f64 {
+        self.sell_price - self.expenses
+    }
+
+    /// Profit relative to expenses earned per day of capital lock-up,
+    /// `None` when nothing is spent.
+    pub fn return_per_day(&self) -> Option<f64> {
+        if self.expenses <= 0. {
+            return None;
+        }
+        Some(self.unit_profit() / self.expenses / self.days_to_sell)
+    }
+
+    /// Value of a single unit for the configured objective.
+    pub fn unit_value(&self, objective: Objective) -> f64 {
+        match objective {
+            Objective::Profit => self.unit_profit(),
+            Objective::ProfitPerDay => self.unit_profit() / self.days_to_sell,
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -38,6 +78,7 @@ pub trait DataVecExt<T> {
         self,
         max_cargo: i32,
         max_number_of_items: i32,
+        objective: Objective,
     ) -> Result<ProfitableItemsSummary<T>, anyhow::Error>;
 }
 
@@ -49,6 +90,7 @@ where
         self,
         max_cargo: i32,
         max_number_of_items: i32,
+        objective: Objective,
     ) -> Result<ProfitableItemsSummary<T>, anyhow::Error> {
         use good_lp::{default_solver, variable, Expression, ProblemVariables, Solution, Variable};
         let mut vars = ProblemVariables::new();
@@ -71,7 +113,7 @@ where
             .zip(self.iter())
             .map(|(&var, item): (&Variable, &T)| -> Expression {
                 let item: ItemProfitData = (item.clone()).into();
-                (item.sell_price - item.expenses) * var
+                item.unit_value(objective) * var
             })
             .sum::<Expression>();
 
@@ -120,24 +162,27 @@ where
                 let volume = (recommend_buy as f64 * item_converted.single_item_volume_m3) as i64;
                 ProcessedItemProfitData {
                     profit_data: item_converted,
-                    rough_profit: (item_converted.sell_price - item_converted.expenses)
-                        * recommend_buy as f64,
+                    rough_profit: item_converted.unit_profit() * recommend_buy as f64,
                     recommend_buy,
                     volume_m3: volume,
                     item,
                 }
             })
             .filter(|x: &ProcessedItemProfitData<_>| x.recommend_buy > 0)
-            .sorted_unstable_by_key(|x| NotNan::new(-x.rough_profit).unwrap())
+            .sorted_unstable_by_key(|x| {
+                NotNan::new(-x.profit_data.unit_value(objective) * x.recommend_buy as f64).unwrap()
+            })
             .collect::<Vec<_>>();
 
         let volume = recommended_items
             .iter()
             .map(|x| x.profit_data.single_item_volume_m3 * x.recommend_buy as f64)
             .sum::<f64>() as i32;
+        // the goal isn't profit for every objective
+        let sum_profit = recommended_items.iter().map(|x| x.rough_profit).sum();
         Ok(ProfitableItemsSummary {
             items: recommended_items,
-            sum_profit: solution.eval(&goal),
+            sum_profit,
             total_volume: volume,
         })
     }
@@ -201,26 +246,10 @@ pub fn calculate_item_averages(
         .take(config.days_average)
         .collect::<Vec<_>>();
 
-    let avg_price = last_n_days
-        .iter()
-        .filter_map(|x| x.average)
-        .map(to_not_nan)
-        .median()
-        .map(|x| *x)?;
-
-    let avg_low_price = last_n_days
-        .iter()
-        .filter_map(|x| x.lowest)
-        .map(to_not_nan)
-        .median()
-        .map(|x| *x)?;
-
-    let avg_high_price = last_n_days
-        .iter()
-        .filter_map(|x| x.highest)
-        .map(to_not_nan)
-        .median()
-        .map(|x| *x)?;
+    let estimator = config.price_estimator;
+    let avg_price = estimate_price(estimator, &last_n_days, |x| x.average)?;
+    let avg_low_price = estimate_price(estimator, &last_n_days, |x| x.lowest)?;
+    let avg_high_price = estimate_price(estimator, &last_n_days, |x| x.highest)?;
 
     let mut avg_volume = *last_n_days
         .iter()
@@ -235,14 +264,226 @@ pub fn calculate_item_averages(
             .average()?;
     }
 
+    let volume_std_dev = *last_n_days
+        .iter()
+        .map(|x| to_not_nan(x.volume as f64))
+        .std_dev()?;
+    let trends = calculate_trends(&last_n_days);
+
     Some(ItemTypeAveraged {
         average: avg_price,
         low_average: avg_low_price,
         high_average: avg_high_price,
         volume: avg_volume,
+        volume_std_dev,
+        price_trend: trends.price_trend,
+        volatility: trends.volatility,
+        volume_trend: trends.volume_trend,
+    })
+}
+
+/// One-sided z-score of the 90th percentile of the normal distribution.
+const Z_P90: f64 = 1.2816;
+
+/// Expected days until an order is sold out, as median and 90th percentile.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TimeToSell {
+    pub p50: f64,
+    pub p90: f64,
+}
+
+/// Days to sell `quantity` units when `queue_ahead` units of competing orders
+/// at or below our price are sold first. Daily volume is assumed to be
+/// independent and normally distributed, so the volume of `n` days has mean
+/// `n * mean_daily` and standard deviation `sqrt(n) * std_dev_daily`.
+pub fn time_to_sell(
+    quantity: i64,
+    queue_ahead: i64,
+    mean_daily: f64,
+    std_dev_daily: f64,
+) -> Option<TimeToSell> {
+    if mean_daily <= 0. {
+        return None;
+    }
+    let total = (quantity + queue_ahead) as f64;
+    // solves n * mean - z * sqrt(n) * std_dev = total for sqrt(n)
+    let days = |z: f64| {
+        let spread = z * std_dev_daily;
+        let sqrt_days =
+            (spread + (spread.powi(2) + 4. * mean_daily * total).sqrt()) / (2. * mean_daily);
+        sqrt_days.powi(2)
+    };
+    Some(TimeToSell {
+        p50: days(0.),
+        p90: days(Z_P90),
     })
 }
 
+struct Trends {
+    price_trend: f64,
+    volatility: f64,
+    volume_trend: f64,
+}
+
+/// Trends of `days` (newest first). Prices only count days with trades
+/// so that filled in gaps don't flatten them.
+fn calculate_trends(days: &[&ItemHistoryDay]) -> Trends {
+    const DAYS_IN_WEEK: f64 = 7.;
+
+    // x is the day number counting from the oldest day
+    let day_number = |i: usize| (days.len() - 1 - i) as f64;
+    let prices = days
+        .iter()
+        .enumerate()
+        .filter(|(_, x)| x.volume > 0)
+        .filter_map(|(i, x)| x.average.map(|avg| (day_number(i), avg)))
+        .collect::<Vec<_>>();
+    let mean_price = prices.iter().map(|x| to_not_nan(x.1)).average();
+    let mean_volume = days.iter().map(|x| to_not_nan(x.volume as f64)).average();
+
+    let relative = |value: Option<f64>, mean: Option<NotNan<f64>>| match (value, mean) {
+        (Some(value), Some(mean)) if *mean > 0. => value / *mean,
+        _ => 0.,
+    };
+    Trends {
+        price_trend: relative(
+            prices.iter().copied().slope().map(|x| x * DAYS_IN_WEEK),
+            mean_price,
+        ),
+        volatility: relative(
+            prices.iter().map(|x| to_not_nan(x.1)).std_dev().map(|x| *x),
+            mean_price,
+        ),
+        volume_trend: relative(
+            days.iter()
+                .enumerate()
+                .map(|(i, x)| (day_number(i), x.volume as f64))
+                .slope()
+                .map(|x| x * DAYS_IN_WEEK),
+            mean_volume,
+        ),
+    }
+}
+
+/// Whether price and volume trends of an item are within the configured limits.
+/// Items without history pass, they are dealt with by volume filters.
+pub fn passes_trend_filter(config: &ConfigTrendFilter, avgs: Option<ItemTypeAveraged>) -> bool {
+    let Some(avgs) = avgs else {
+        return true;
+    };
+    config
+        .max_price_drop_per_week
+        .map_or(true, |max_drop| avgs.price_trend >= -max_drop)
+        && config
+            .max_volatility
+            .map_or(true, |max_volatility| avgs.volatility <= max_volatility)
+        && config
+            .min_volume_trend
+            .map_or(true, |min_trend| avgs.volume_trend >= min_trend)
+}
+
+/// Orders ignored when pricing, for each `(market, item name, market data)`.
+pub fn make_table_suspicious_orders<'a, 'b>(
+    config: &CommonConfig,
+    markets: impl IntoIterator<Item = (&'a str, &'a str, &'a MarketData)>,
+) -> Vec<Row<'b>> {
+    std::iter::once(Row::new(vec![
+        TableCell::new("mkt"),
+        TableCell::new("itm nm"),
+        TableCell::new("side"),
+        TableCell::new("price"),
+        TableCell::new("vlm"),
+        TableCell::new("issued"),
+        TableCell::new("reason"),
+    ]))
+    .chain(
+        markets
+            .into_iter()
+            .flat_map(|(market_name, item_name, market)| {
+                let avgs = calculate_item_averages(config, &market.history);
+                suspicious_orders(&market.orders, avgs, &config.manipulation)
+                    .into_iter()
+                    .map(|(order, reason)| {
+                        Row::new(vec![
+                            TableCell::new(market_name),
+                            TableCell::new(item_name),
+                            TableCell::new(if order.is_buy_order { "buy" } else { "sell" }),
+                            TableCell::new(format!("{:.2}", order.price)),
+                            TableCell::new(format!(
+                                "{}/{}",
+                                order.volume_remain, order.volume_total
+                            )),
+                            TableCell::new(&order.issued),
+                            TableCell::new(reason),
+                        ])
+                    })
+                    .collect::<Vec<_>>()
+            }),
+    )
+    .collect()
+}
+
+/// Trend, volatility and volume trend columns shown in every table.
+pub fn trend_cells<'b>(avgs: Option<ItemTypeAveraged>) -> [TableCell<'b>; 3] {
+    let cell = |value: fn(&ItemTypeAveraged) -> f64| {
+        TableCell::new(
+            avgs.as_ref()
+                .map_or("N/A".to_string(), |x| format!("{:.2}", value(x))),
+        )
+    };
+    [
+        cell(|x| x.price_trend),
+        cell(|x| x.volatility),
+        cell(|x| x.volume_trend),
+    ]
+}
+
+pub fn trend_header_cells<'b>() -> [TableCell<'b>; 3] {
+    [
+        TableCell::new("trnd"),
+        TableCell::new("vlty"),
+        TableCell::new("vlm trnd"),
+    ]
+}
+
+/// Same as [`calculate_item_averages`] of the market history, with volume
+/// observed at the station when there is one.
+pub fn calculate_market_averages(
+    config: &CommonConfig,
+    market: &MarketData,
+) -> Option<ItemTypeAveraged> {
+    let mut averages = calculate_item_averages(config, &market.history)?;
+    if let Some(station_volume) = market.station_volume {
+        averages.volume = station_volume;
+    }
+    Some(averages)
+}
+
+/// Combines one of the daily prices of `days` (newest first) into a single price.
+fn estimate_price(
+    estimator: PriceEstimator,
+    days: &[&ItemHistoryDay],
+    price: fn(&ItemHistoryDay) -> Option<f64>,
+) -> Option<f64> {
+    let prices = || days.iter().filter_map(|x| price(x)).map(to_not_nan);
+    let estimate = match estimator {
+        PriceEstimator::Average => prices().average(),
+        PriceEstimator::Median => prices().median(),
+        PriceEstimator::TrimmedMean { trim } => prices().trimmed_mean(trim),
+        PriceEstimator::VolumeWeightedMedian => days
+            .iter()
+            .filter_map(|x| price(x).map(|p| (to_not_nan(p), x.volume as f64)))
+            .weighted_median()
+            // no volume at all, every day is as good as any other
+            .or_else(|| prices().median()),
+        PriceEstimator::MadFilteredMean { threshold } => {
+            prices().without_outliers(threshold).into_iter().average()
+        }
+        PriceEstimator::Ewma { alpha } => prices().rev().ewma(alpha),
+    };
+    estimate.map(|x| *x)
+}
+
 pub fn calculate_weighted_price(
     config: &CommonConfig,
     history: &[ItemHistoryDay],
@@ -253,7 +494,13 @@ pub fn calculate_weighted_price(
         .take(config.days_average)
         .collect::<Vec<_>>();
 
-    let sum = last_n_days.iter().map(|x| x.volume).sum::<i64>();
+    // days without an average price can't be weighted, their volume is left out too
+    let priced_days = last_n_days
+        .iter()
+        .filter_map(|x| x.average.map(|average| (average, x.volume)))
+        .collect::<Vec<_>>();
+
+    let sum = priced_days.iter().map(|x| x.1).sum::<i64>();
     if sum == 0 {
         return Err(anyhow!("Historical volume is zero"));
     }
@@ -261,13 +508,9 @@ pub fn calculate_weighted_price(
     let sum_volume = to_not_nan(sum as f64);
 
     Ok(*(to_not_nan(
-        last_n_days
-            .iter()
-            .map(
-                |x| Ok(x.average.ok_or(anyhow!("Item history average is None"))? * x.volume as f64),
-            )
-            .collect::<anyhow::Result<Vec<_>>>()?
+        priced_days
             .iter()
+            .map(|(average, volume)| average * *volume as f64)
             .sum::<f64>(),
     ) / sum_volume))
 }
@@ -373,6 +616,13 @@ pub fn outbid_price(price: f64, is_buy_order: bool) -> f64 {
 
 #[cfg(test)]
 mod tests {
+    use chrono::NaiveDate;
+
+    use crate::{
+        config::HistoryGapFill,
+        item_type::{ImputedRows, ItemHistory, ItemOrders, MarketData, MarketsRegionHistory},
+    };
+
     use super::*;
 
     #[test]
@@ -476,4 +726,161 @@ mod tests {
             "Failed to maintain 4 significant digits for sell order: {result_sell} != {expected_sell}"
         );
     }
+
+    fn sparse_history_averages(fill: HistoryGapFill) -> ItemTypeAveraged {
+        let mut config = CommonConfig::for_tests();
+        config.days_average = 10;
+        config.price_estimator = PriceEstimator::Median;
+
+        let day = |date: &str, average: f64, volume: i64| MarketsRegionHistory {
+            average: Some(average),
+            date: date.to_string(),
+            highest: Some(average + 10.),
+            lowest: Some(average - 10.),
+            order_count: 1,
+            volume,
+        };
+        let mut history = ItemHistory {
+            id: 1,
+            history: vec![
+                day("2024-01-01", 100., 50),
+                day("2024-01-02", 100., 40),
+                day("2024-01-25", 200., 10),
+                day("2024-01-28", 210., 20),
+            ],
+        };
+        let today = NaiveDate::from_ymd_opt(2024, 1, 31).unwrap();
+        history.fill_gaps(fill, 30, today);
+
+        let market = MarketData::new(ItemOrders::default(), history);
+        calculate_item_averages(&config, &market.history).unwrap()
+    }
+
+    #[test]
+    fn test_leave_gaps_averages_days_with_trades() {
+        let avgs = sparse_history_averages(HistoryGapFill::Leave);
+        assert_eq!(avgs.average, 150.);
+        assert_eq!(avgs.volume, 30.);
+    }
+
+    #[test]
+    fn test_forward_fill_follows_recent_prices() {
+        let avgs = sparse_history_averages(HistoryGapFill::ForwardFill);
+        assert_eq!(avgs.average, 200.);
+        assert_eq!(avgs.high_average, 210.);
+        assert_eq!(avgs.volume, 3.);
+    }
+
+    #[test]
+    fn test_median_fill_pulls_towards_whole_history() {
+        let avgs = sparse_history_averages(HistoryGapFill::Median);
+        assert_eq!(avgs.average, 150.);
+        assert_eq!(avgs.low_average, 140.);
+        assert_eq!(avgs.volume, 3.);
+    }
+
+    #[test]
+    fn test_weighted_price_skips_days_without_average() {
+        let mut config = CommonConfig::for_tests();
+        config.days_average = 3;
+        let day = |average: Option<f64>, volume: i64| ItemHistoryDay {
+            average,
+            highest: None,
+            lowest: None,
+            order_count: 1,
+            volume,
+        };
+        let history = [day(Some(100.), 1), day(None, 100), day(Some(200.), 3)];
+
+        let price = calculate_weighted_price(&config, &history).unwrap();
+        assert_eq!(price, 175.);
+    }
+
+    #[test]
+    fn test_impute_missing_prices() {
+        let day = |average: Option<f64>, highest: Option<f64>, lowest: Option<f64>| {
+            MarketsRegionHistory {
+                average,
+                date: "2024-01-01".to_string(),
+                highest,
+                lowest,
+                order_count: 1,
+                volume: 1,
+            }
+        };
+        let mut history = ItemHistory {
+            id: 1,
+            history: vec![
+                day(Some(10.), Some(12.), Some(8.)),
+                day(None, Some(12.), Some(8.)),
+                day(Some(10.), None, None),
+                day(None, None, None),
+            ],
+        };
+
+        let rows = history.impute_missing_prices();
+        assert_eq!(
+            rows,
+            ImputedRows {
+                imputed: 2,
+                dropped: 1
+            }
+        );
+        let prices = history
+            .history
+            .iter()
+            .map(|x| (x.average, x.highest, x.lowest))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            prices,
+            vec![
+                (Some(10.), Some(12.), Some(8.)),
+                (Some(10.), Some(12.), Some(8.)),
+                (Some(10.), Some(10.), Some(10.)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_trends_of_crashing_item() {
+        // oldest first, the price falls by 10 per day, volume dries up, gap has no trades
+        let days = [(140., 10), (130., 8), (120., 0), (110., 4), (100., 2)]
+            .into_iter()
+            .rev()
+            .map(|(average, volume)| ItemHistoryDay {
+                average: Some(if volume > 0 { average } else { 1000. }),
+                highest: Some(average),
+                lowest: Some(average),
+                order_count: 1,
+                volume,
+            })
+            .collect::<Vec<_>>();
+        let trends = calculate_trends(&days.iter().collect::<Vec<_>>());
+
+        assert!((trends.price_trend - (-70. / 120.)).abs() < 1e-9);
+        assert!(trends.volume_trend < 0.);
+        assert!(trends.volatility > 0.);
+
+        let avgs = ItemTypeAveraged {
+            price_trend: trends.price_trend,
+            ..Default::default()
+        };
+        let filter = ConfigTrendFilter {
+            max_price_drop_per_week: Some(0.2),
+            ..Default::default()
+        };
+        assert!(!passes_trend_filter(&filter, Some(avgs)));
+        assert!(passes_trend_filter(&filter, None));
+    }
+
+    #[test]
+    fn test_time_to_sell_behind_competitors() {
+        let tts = time_to_sell(50, 150, 20., 10.).unwrap();
+        assert!((tts.p50 - 10.).abs() < 1e-9);
+        assert!(tts.p90 > tts.p50);
+        // at p90 even the 10th percentile of sold volume covers the queue and our order
+        assert!((tts.p90 * 20. - Z_P90 * tts.p90.sqrt() * 10. - 200.).abs() < 1e-6);
+
+        assert_eq!(time_to_sell(50, 0, 0., 0.), None);
+    }
 }
@@ -1,7 +1,6 @@
 use std::collections::HashMap;
 
 use anyhow::anyhow;
-use chrono::Duration;
 use itertools::Itertools;
 use rust_eveonline_esi::models::GetCharactersCharacterIdWalletTransactions200Ok;
 
@@ -12,7 +11,7 @@ use crate::{
     good_items::{help::calculate_item_averages, sell_sell::calculate_sell_price},
     helper_ext::HashMapJoin,
     item_type::{ItemOrders, MarketData, TypeDescription},
-    load_create::{load_or_create_history, load_or_create_orders},
+    load_create::{load_or_create_history, load_or_create_orders, load_or_create_transactions},
     requests::{
         item_history::ItemHistoryEsiService, service::EsiRequestsService,
         transactions::WalletEsiService,
@@ -53,19 +52,14 @@ impl<'a> ItemsPricesService<'a> {
         let item_history = load_or_create_history(
             self.cache,
             station_id,
-            Duration::try_hours(self.config.item_history_timeout_hours).unwrap(),
+            self.config,
             self.esi_history,
             &all_type_descriptions.iter().map(|x| *x.0).collect_vec(),
         )
         .await?;
 
-        let item_orders = load_or_create_orders(
-            self.cache,
-            Duration::try_seconds((self.config.refresh_timeout_hours * 60. * 60.) as i64).unwrap(),
-            self.esi_requests,
-            station_id,
-        )
-        .await?;
+        let item_orders =
+            load_or_create_orders(self.cache, self.config, self.esi_requests, station_id).await?;
 
         let name_to_item_desc = all_type_descriptions
             .into_iter()
@@ -144,31 +138,8 @@ impl<'a> ItemsPricesService<'a> {
         &mut self,
         character_id: i32,
     ) -> anyhow::Result<CharacterTransactions> {
-        let transactions = self
-            .cache
-            .load_or_create_async(
-                format!("wallet-history-{character_id}.rmp"),
-                vec![],
-                Some(Duration::try_seconds(10 * 60).unwrap()),
-                |mut previous| {
-                    let item_hist = &mut self.wallet_esi_service;
-                    async move {
-                        let mut new_transactions =
-                            item_hist.get_transactions_history(character_id).await?;
-
-                        if let Some(ref mut previous) = previous {
-                            new_transactions.append(previous)
-                        }
-
-                        new_transactions.sort_unstable_by_key(|x| -x.transaction_id);
-                        new_transactions.dedup_by_key(|x| x.transaction_id);
-
-                        Ok(new_transactions)
-                    }
-                },
-            )
-            .await?;
-
+        let transactions =
+            load_or_create_transactions(self.cache, self.wallet_esi_service, character_id).await?;
         Ok(CharacterTransactions { transactions })
     }
 }
@@ -0,0 +1,391 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    hash::Hash,
+};
+
+use chrono::{DateTime, NaiveDate, Utc, Weekday};
+use itertools::Itertools;
+use rust_eveonline_esi::models::GetCharactersCharacterIdWalletTransactions200Ok;
+use term_table::{row::Row, table_cell::TableCell};
+
+use crate::{
+    cached_data::CachedStuff,
+    config::CommonConfig,
+    item_type::TypeDescription,
+    load_create::load_or_create_transactions,
+    requests::{service::EsiRequestsService, transactions::WalletEsiService},
+};
+
+pub struct LedgerService<'a> {
+    pub wallet_esi_service: &'a WalletEsiService<'a>,
+    pub cache: &'a mut CachedStuff,
+    pub esi_requests: &'a EsiRequestsService<'a>,
+    pub config: &'a CommonConfig,
+}
+
+impl<'a> LedgerService<'a> {
+    /// Matches all known wallet transactions of the character into realized trades.
+    pub async fn get_ledger(&mut self, character_id: i32) -> anyhow::Result<LedgerData> {
+        let transactions =
+            load_or_create_transactions(self.cache, self.wallet_esi_service, character_id)
+                .await?
+                .iter()
+                .filter_map(LedgerTransaction::parse)
+                .collect_vec();
+
+        let mut fees = LedgerFees {
+            broker_fees: HashMap::new(),
+            default_broker_fee: self.config.ledger.unknown_station_broker_fee,
+            sales_tax: self.config.sales_tax,
+        };
+        let mut location_names = HashMap::new();
+        for station in &self.config.stations {
+            let station_id = self
+                .esi_requests
+                .find_region_id_station(station, character_id)
+                .await?
+                .station_id
+                .id;
+            fees.broker_fees.insert(station_id, station.broker_fee);
+            let name = station
+                .short
+                .clone()
+                .unwrap_or_else(|| station.name.clone());
+            location_names.insert(station_id, name);
+        }
+
+        let ledger = Ledger::from_transactions(transactions, &fees);
+
+        let mut item_names = HashMap::new();
+        for type_id in ledger.trades.iter().map(|x| x.type_id).unique() {
+            let name = self
+                .esi_requests
+                .get_item_description(type_id)
+                .await?
+                .map(|x| TypeDescription::from(x).name)
+                .unwrap_or_else(|| type_id.to_string());
+            item_names.insert(type_id, name);
+        }
+
+        Ok(LedgerData {
+            ledger,
+            item_names,
+            location_names,
+        })
+    }
+}
+
+/// Wallet transaction with the fields the ledger needs.
+#[derive(Debug, Clone)]
+pub struct LedgerTransaction {
+    pub transaction_id: i64,
+    pub date: DateTime<Utc>,
+    pub type_id: i32,
+    pub location_id: i64,
+    pub is_buy: bool,
+    pub quantity: i64,
+    pub unit_price: f64,
+}
+
+impl LedgerTransaction {
+    pub fn parse(value: &GetCharactersCharacterIdWalletTransactions200Ok) -> Option<Self> {
+        let date = match DateTime::parse_from_rfc3339(&value.date) {
+            Ok(date) => date.with_timezone(&Utc),
+            Err(err) => {
+                log::warn!(
+                    "Skipping transaction {} with date {}: {err}",
+                    value.transaction_id,
+                    value.date
+                );
+                return None;
+            }
+        };
+        Some(Self {
+            transaction_id: value.transaction_id,
+            date,
+            type_id: value.type_id,
+            location_id: value.location_id,
+            is_buy: value.is_buy,
+            quantity: value.quantity as i64,
+            unit_price: value.unit_price,
+        })
+    }
+}
+
+/// Fees applied the same way the tool applies them when predicting profit:
+/// broker fee of the station on both buying and selling, sales tax on selling.
+#[derive(Debug, Clone)]
+pub struct LedgerFees {
+    pub broker_fees: HashMap<i64, f64>,
+    /// Broker fee in stations missing from the config.
+    pub default_broker_fee: f64,
+    pub sales_tax: f64,
+}
+
+impl LedgerFees {
+    fn broker_fee(&self, location_id: i64) -> f64 {
+        self.broker_fees
+            .get(&location_id)
+            .copied()
+            .unwrap_or(self.default_broker_fee)
+    }
+}
+
+/// Units of a sale matched with the purchase they came from.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RealizedTrade {
+    pub type_id: i32,
+    pub quantity: i64,
+    pub bought_in: i64,
+    pub sold_in: i64,
+    /// Price of a unit with broker fee.
+    pub buy_price: f64,
+    /// Price of a unit after broker fee and sales tax.
+    pub sell_price: f64,
+    pub sold_at: DateTime<Utc>,
+}
+
+impl RealizedTrade {
+    pub fn profit(&self) -> f64 {
+        (self.sell_price - self.buy_price) * self.quantity as f64
+    }
+
+    /// Monday of the week the trade was closed in.
+    pub fn week(&self) -> NaiveDate {
+        self.sold_at.date_naive().week(Weekday::Mon).first_day()
+    }
+}
+
+/// Units bought at the same price that are not sold yet.
+#[derive(Debug, Clone)]
+struct Lot {
+    location_id: i64,
+    quantity: i64,
+    unit_cost: f64,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Ledger {
+    pub trades: Vec<RealizedTrade>,
+    /// Sold volume per type that no recorded purchase accounts for.
+    pub unmatched_sold: HashMap<i32, i64>,
+    /// Bought volume per type that is not sold yet.
+    pub unsold: HashMap<i32, i64>,
+}
+
+impl Ledger {
+    /// Matches sales with the earliest purchases of the same type, wherever
+    /// they were made.
+    pub fn from_transactions(mut transactions: Vec<LedgerTransaction>, fees: &LedgerFees) -> Self {
+        transactions.sort_by_key(|x| (x.date, x.transaction_id));
+
+        let mut ledger = Ledger::default();
+        let mut lots = HashMap::<i32, VecDeque<Lot>>::new();
+        for transaction in transactions {
+            let type_lots = lots.entry(transaction.type_id).or_default();
+            if transaction.is_buy {
+                type_lots.push_back(Lot {
+                    location_id: transaction.location_id,
+                    quantity: transaction.quantity,
+                    unit_cost: transaction.unit_price
+                        * (1. + fees.broker_fee(transaction.location_id)),
+                });
+                continue;
+            }
+
+            let sell_price = transaction.unit_price
+                * (1. - fees.broker_fee(transaction.location_id) - fees.sales_tax);
+            let mut left = transaction.quantity;
+            while left > 0 {
+                let Some(lot) = type_lots.front_mut() else {
+                    *ledger
+                        .unmatched_sold
+                        .entry(transaction.type_id)
+                        .or_default() += left;
+                    break;
+                };
+                let quantity = left.min(lot.quantity);
+                ledger.trades.push(RealizedTrade {
+                    type_id: transaction.type_id,
+                    quantity,
+                    bought_in: lot.location_id,
+                    sold_in: transaction.location_id,
+                    buy_price: lot.unit_cost,
+                    sell_price,
+                    sold_at: transaction.date,
+                });
+                left -= quantity;
+                lot.quantity -= quantity;
+                if lot.quantity == 0 {
+                    type_lots.pop_front();
+                }
+            }
+        }
+
+        ledger.unsold = lots
+            .into_iter()
+            .map(|(type_id, lots)| (type_id, lots.iter().map(|x| x.quantity).sum()))
+            .filter(|(_, quantity)| *quantity > 0)
+            .collect();
+        ledger
+    }
+
+    /// Trades summed up by `key`, most profitable first.
+    pub fn summarize<K, F>(&self, key: F) -> Vec<(K, LedgerSummary)>
+    where
+        K: Hash + Eq,
+        F: Fn(&RealizedTrade) -> K,
+    {
+        let mut summaries = HashMap::<K, LedgerSummary>::new();
+        for trade in &self.trades {
+            summaries.entry(key(trade)).or_default().add(trade);
+        }
+        summaries
+            .into_iter()
+            .sorted_by(|(_, a), (_, b)| b.profit().total_cmp(&a.profit()))
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct LedgerSummary {
+    pub quantity: i64,
+    pub cost: f64,
+    pub revenue: f64,
+}
+
+impl LedgerSummary {
+    fn add(&mut self, trade: &RealizedTrade) {
+        self.quantity += trade.quantity;
+        self.cost += trade.buy_price * trade.quantity as f64;
+        self.revenue += trade.sell_price * trade.quantity as f64;
+    }
+
+    pub fn profit(&self) -> f64 {
+        self.revenue - self.cost
+    }
+
+    pub fn margin(&self) -> f64 {
+        self.profit() / self.cost
+    }
+}
+
+pub struct LedgerData {
+    pub ledger: Ledger,
+    item_names: HashMap<i32, String>,
+    location_names: HashMap<i64, String>,
+}
+
+impl LedgerData {
+    fn location_name(&self, location_id: i64) -> String {
+        self.location_names
+            .get(&location_id)
+            .cloned()
+            .unwrap_or_else(|| location_id.to_string())
+    }
+
+    pub fn make_table_items<'b>(&self, name_length: usize) -> Vec<Row<'b>> {
+        let summaries = self.ledger.summarize(|x| x.type_id);
+        make_table_summaries(
+            "itm nm",
+            summaries.into_iter().map(|(type_id, summary)| {
+                let name = self.item_names.get(&type_id).cloned().unwrap_or_default();
+                (name[..(name_length.min(name.len()))].to_owned(), summary)
+            }),
+        )
+    }
+
+    pub fn make_table_routes<'b>(&self) -> Vec<Row<'b>> {
+        let summaries = self.ledger.summarize(|x| (x.bought_in, x.sold_in));
+        make_table_summaries(
+            "route",
+            summaries.into_iter().map(|((from, to), summary)| {
+                let route = format!("{} -> {}", self.location_name(from), self.location_name(to));
+                (route, summary)
+            }),
+        )
+    }
+
+    pub fn make_table_weeks<'b>(&self) -> Vec<Row<'b>> {
+        let summaries = self
+            .ledger
+            .summarize(|x| x.week())
+            .into_iter()
+            .sorted_by_key(|(week, _)| *week);
+        make_table_summaries(
+            "week",
+            summaries.map(|(week, summary)| (week.to_string(), summary)),
+        )
+    }
+}
+
+fn make_table_summaries<'b>(
+    key_header: &str,
+    summaries: impl Iterator<Item = (String, LedgerSummary)>,
+) -> Vec<Row<'b>> {
+    let mut total = LedgerSummary::default();
+    let mut rows = vec![Row::new(vec![
+        TableCell::new(key_header),
+        TableCell::new("qty"),
+        TableCell::new("cost"),
+        TableCell::new("revenue"),
+        TableCell::new("profit"),
+        TableCell::new("mrgn"),
+    ])];
+    for (key, summary) in summaries {
+        total.quantity += summary.quantity;
+        total.cost += summary.cost;
+        total.revenue += summary.revenue;
+        rows.push(summary_row(key, &summary));
+    }
+    rows.push(summary_row("total".to_string(), &total));
+    rows
+}
+
+fn summary_row<'b>(key: String, summary: &LedgerSummary) -> Row<'b> {
+    Row::new(vec![
+        TableCell::new(key),
+        TableCell::new(format!("{}", summary.quantity)),
+        TableCell::new(format!("{:.2}", summary.cost)),
+        TableCell::new(format!("{:.2}", summary.revenue)),
+        TableCell::new(format!("{:.2}", summary.profit())),
+        TableCell::new(format!("{:.2}", summary.margin())),
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_fixtures::transaction;
+
+    #[test]
+    fn test_fifo_matching() {
+        let fees = LedgerFees {
+            broker_fees: HashMap::from([(1, 0.01), (2, 0.02)]),
+            default_broker_fee: 0.03,
+            sales_tax: 0.08,
+        };
+        let transactions = vec![
+            // sold before anything was bought
+            transaction(1, 2, false, 5, 20.),
+            transaction(2, 1, true, 10, 10.),
+            transaction(3, 1, true, 10, 12.),
+            transaction(4, 2, false, 15, 20.),
+        ];
+        let ledger = Ledger::from_transactions(transactions, &fees);
+
+        assert_eq!(ledger.unmatched_sold, HashMap::from([(34, 5)]));
+        assert_eq!(ledger.unsold, HashMap::from([(34, 5)]));
+        assert_eq!(ledger.trades.len(), 2);
+        assert_eq!(ledger.trades[0].quantity, 10);
+        assert_eq!(ledger.trades[0].buy_price, 10. * 1.01);
+        assert_eq!(ledger.trades[1].quantity, 5);
+        assert_eq!(ledger.trades[1].buy_price, 12. * 1.01);
+        assert_eq!(ledger.trades[1].sell_price, 20. * (1. - 0.02 - 0.08));
+
+        let routes = ledger.summarize(|x| (x.bought_in, x.sold_in));
+        assert_eq!(routes.len(), 1);
+        let expected = 15. * 20. * 0.9 - (10. * 10. + 5. * 12.) * 1.01;
+        assert!((routes[0].1.profit() - expected).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,286 @@
+use std::collections::{HashMap, HashSet};
+
+use itertools::Itertools;
+use term_table::{row::Row, table_cell::TableCell};
+
+use crate::{
+    item_type::{CharacterOrder, Order, TypeDescription},
+    requests::service::{to_not_nan, EsiRequestsService},
+    Station, StationIdData,
+};
+
+use super::help::outbid_price;
+
+pub struct MyOrdersService<'a> {
+    pub esi_requests: &'a EsiRequestsService<'a>,
+}
+
+impl<'a> MyOrdersService<'a> {
+    /// Compares open orders of the character in the station with the best
+    /// competing orders there.
+    pub async fn get_my_orders(
+        &self,
+        station_config: Station,
+        character_id: i32,
+    ) -> anyhow::Result<MyOrdersData> {
+        let station = self
+            .esi_requests
+            .find_region_id_station(&station_config, character_id)
+            .await?;
+
+        let my_orders = self
+            .esi_requests
+            .get_character_orders(character_id)
+            .await?
+            .into_iter()
+            .filter(|x| x.location_id == station.station_id.id)
+            .collect_vec();
+        log::info!(
+            "{} open orders in station {}",
+            my_orders.len(),
+            station_config.name
+        );
+
+        let mut names = HashMap::new();
+        for type_id in my_orders.iter().map(|x| x.type_id).unique() {
+            let name = self
+                .esi_requests
+                .get_item_description(type_id)
+                .await?
+                .map(|x| TypeDescription::from(x).name)
+                .unwrap_or_else(|| type_id.to_string());
+            names.insert(type_id, name);
+        }
+
+        let mut data = MyOrdersData {
+            orders: my_orders
+                .into_iter()
+                .map(|order| MyOrderStatus {
+                    name: names.get(&order.type_id).cloned().unwrap_or_default(),
+                    order,
+                    best_competitor: None,
+                })
+                .collect(),
+            station,
+        };
+        self.refresh_book(&mut data).await?;
+        Ok(data)
+    }
+
+    /// Compares the orders with the current order book of the station.
+    pub async fn refresh_book(&self, data: &mut MyOrdersData) -> anyhow::Result<()> {
+        // ESI doesn't serve a newer book before the cached one expires, so the cache is
+        // as fresh as it gets
+        let station_orders = self.esi_requests.get_orders_station(data.station).await?;
+        data.update(|_| true, &station_orders.data);
+        Ok(())
+    }
+
+    /// Compares the orders of one type with its current order book of the station.
+    pub async fn refresh_type(&self, data: &mut MyOrdersData, type_id: i32) -> anyhow::Result<()> {
+        let station_orders = self
+            .esi_requests
+            .get_orders_station_type(data.station, type_id)
+            .await?;
+        data.update(|x| x.type_id == type_id, &station_orders.data);
+        Ok(())
+    }
+}
+
+/// Order of the character next to the best competing order.
+#[derive(Debug, Clone)]
+pub struct MyOrderStatus {
+    pub order: CharacterOrder,
+    pub name: String,
+    pub best_competitor: Option<f64>,
+}
+
+impl MyOrderStatus {
+    /// How much better the best competing order is, if it is better.
+    pub fn outbid_by(&self) -> Option<f64> {
+        let best = self.best_competitor?;
+        let by = if self.order.is_buy_order {
+            best - self.order.price
+        } else {
+            self.order.price - best
+        };
+        (by > 0.).then_some(by)
+    }
+
+    /// Price that gets the order back to the top of the book.
+    pub fn new_price(&self) -> Option<f64> {
+        self.outbid_by()?;
+        self.best_competitor
+            .map(|best| outbid_price(best, self.order.is_buy_order))
+    }
+}
+
+/// Pairs every order of the character with the best order of somebody else
+/// of the same type and side, outbid orders first.
+pub fn compare_orders(
+    my_orders: Vec<CharacterOrder>,
+    station_orders: &[Order],
+) -> Vec<MyOrderStatus> {
+    let my_order_ids = my_orders.iter().map(|x| x.order_id).collect::<HashSet<_>>();
+    let competitors = station_orders
+        .iter()
+        .filter(|x| !my_order_ids.contains(&x.order_id))
+        .into_group_map_by(|x| (x.type_id, x.is_buy_order));
+
+    my_orders
+        .into_iter()
+        .map(|order| {
+            let same_market = competitors.get(&(order.type_id, order.is_buy_order));
+            let prices = same_market
+                .into_iter()
+                .flatten()
+                .map(|x| to_not_nan(x.price));
+            let best_competitor = if order.is_buy_order {
+                prices.max()
+            } else {
+                prices.min()
+            };
+            MyOrderStatus {
+                order,
+                name: String::new(),
+                best_competitor: best_competitor.map(|x| *x),
+            }
+        })
+        .sorted_by_key(|x| (x.outbid_by().is_none(), x.order.type_id))
+        .collect()
+}
+
+pub struct MyOrdersData {
+    orders: Vec<MyOrderStatus>,
+    station: StationIdData,
+}
+
+impl MyOrdersData {
+    pub fn make_table_my_orders<'b>(&self, name_length: usize) -> Vec<Row<'b>> {
+        std::iter::once(Row::new(vec![
+            TableCell::new("id"),
+            TableCell::new("itm nm"),
+            TableCell::new("side"),
+            TableCell::new("my p"),
+            TableCell::new("best p"),
+            TableCell::new("outbid by"),
+            TableCell::new("new p"),
+            TableCell::new("left"),
+        ]))
+        .chain(self.orders.iter().map(|it| {
+            let short_name = it.name[..(name_length.min(it.name.len()))].to_owned();
+            let price = |x: Option<f64>| x.map_or("N/A".to_string(), |x| format!("{:.2}", x));
+            Row::new(vec![
+                TableCell::new(format!("{}", it.order.type_id)),
+                TableCell::new(short_name),
+                TableCell::new(if it.order.is_buy_order { "buy" } else { "sell" }),
+                TableCell::new(format!("{:.2}", it.order.price)),
+                TableCell::new(price(it.best_competitor)),
+                TableCell::new(price(it.outbid_by())),
+                TableCell::new(price(it.new_price())),
+                TableCell::new(format!(
+                    "{}/{}",
+                    it.order.volume_remain, it.order.volume_total
+                )),
+            ])
+        }))
+        .collect()
+    }
+
+    /// Outbid orders in the order they are listed in the table.
+    pub fn outbid_orders(&self) -> impl Iterator<Item = &MyOrderStatus> + '_ {
+        self.orders.iter().filter(|x| x.outbid_by().is_some())
+    }
+
+    pub fn get(&self, order_id: i64) -> Option<&MyOrderStatus> {
+        self.orders.iter().find(|x| x.order.order_id == order_id)
+    }
+
+    /// Compares the orders `which` selects with `station_orders`, keeping the others.
+    fn update(&mut self, which: impl Fn(&CharacterOrder) -> bool, station_orders: &[Order]) {
+        let (updated, kept): (Vec<_>, Vec<_>) =
+            self.orders.drain(..).partition(|x| which(&x.order));
+        let names = updated
+            .iter()
+            .map(|x| (x.order.order_id, x.name.clone()))
+            .collect::<HashMap<_, _>>();
+        let updated = compare_orders(
+            updated.into_iter().map(|x| x.order).collect(),
+            station_orders,
+        )
+        .into_iter()
+        .map(|mut x| {
+            x.name = names.get(&x.order.order_id).cloned().unwrap_or_default();
+            x
+        });
+        self.orders = kept
+            .into_iter()
+            .chain(updated)
+            .sorted_by_key(|x| (x.outbid_by().is_none(), x.order.type_id))
+            .collect();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        test_fixtures::{character_order, order, TYPE_ID},
+        StationId,
+    };
+
+    #[test]
+    fn test_outbid_orders_come_first() {
+        let station_orders = vec![
+            order(1, false, 10.),
+            order(2, false, 9.5),
+            order(3, true, 7.),
+            // our own orders are not competition
+            order(10, true, 8.),
+            order(11, false, 10.),
+        ];
+        let statuses = compare_orders(
+            vec![
+                character_order(10, true, 8.),
+                character_order(11, false, 10.),
+            ],
+            &station_orders,
+        );
+
+        assert_eq!(statuses[0].order.order_id, 11);
+        assert_eq!(statuses[0].outbid_by(), Some(0.5));
+        assert_eq!(statuses[0].new_price(), Some(outbid_price(9.5, false)));
+        assert_eq!(statuses[1].outbid_by(), None);
+        assert_eq!(statuses[1].new_price(), None);
+    }
+
+    #[test]
+    fn test_update_keeps_other_types() {
+        let other_type = CharacterOrder {
+            type_id: 35,
+            ..character_order(11, false, 10.)
+        };
+        let mut data = MyOrdersData {
+            orders: compare_orders(
+                vec![character_order(10, false, 10.), other_type],
+                &[Order {
+                    type_id: 35,
+                    ..order(2, false, 9.)
+                }],
+            ),
+            station: StationIdData {
+                station_id: StationId {
+                    is_citadel: false,
+                    id: 1,
+                },
+                system_id: 1,
+                region_id: 1,
+            },
+        };
+
+        data.update(|x| x.type_id == TYPE_ID, &[order(1, false, 9.5)]);
+
+        assert_eq!(data.get(10).unwrap().outbid_by(), Some(0.5));
+        assert_eq!(data.get(11).unwrap().outbid_by(), Some(1.));
+    }
+}
@@ -1,14 +1,17 @@
+use chrono::Utc;
 use itertools::Itertools;
 use num_format::{Locale, ToFormattedString};
 use ordered_float::NotNan;
 use term_table::{row::Row, table_cell::TableCell};
 
 use crate::{
+    cli,
     config::Config,
     item_type::{ItemTypeAveraged, SystemMarketsItemData},
     order_ext::OrderIterExt,
 };
 
+use super::backtest::Recommendation;
 use super::help::{self, calculate_item_averages, DataVecExt};
 pub fn get_good_items_sell_buy(
     pairs: Vec<SystemMarketsItemData>,
@@ -19,10 +22,14 @@ pub fn get_good_items_sell_buy(
         .into_iter()
         .filter_map(|x| calculate_pairs(x, config))
         .filter(|x| disable_filters || x.margin > config.common.margin_cutoff)
+        .filter(|x| {
+            disable_filters || help::passes_trend_filter(&config.common.trend_filter, x.dst_avgs)
+        })
         .collect::<Vec<_>>()
         .take_maximizing_profit(
             config.common.cargo_capacity,
             config.common.items_take as i32,
+            config.common.objective,
         )
 }
 
@@ -137,66 +144,107 @@ fn calculate_prices_volumes(
     ))
 }
 
+/// Recommended items, recorded for the backtest.
+pub fn recommendations_sell_buy(
+    good_items: &help::ProfitableItemsSummary<PairCalculatedDataSellBuy>,
+    config: &Config,
+) -> Vec<Recommendation> {
+    let time = Utc::now();
+    good_items
+        .items
+        .iter()
+        .map(|it| Recommendation {
+            time,
+            mode: cli::SELL_BUY.to_string(),
+            source: config.route.source.name.clone(),
+            destination: config.route.destination.name.clone(),
+            type_id: it.item.market.desc.type_id,
+            name: it.item.market.desc.name.clone(),
+            quantity: it.recommend_buy,
+            buy_price: it.item.src_buy_price,
+            sell_price: it.item.dest_min_sell_price,
+            profit: it.rough_profit,
+            knobs: vec![format!("mode={}", cli::SELL_BUY)],
+        })
+        .collect()
+}
+
 pub fn make_table_sell_buy<'b>(
     good_items: &help::ProfitableItemsSummary<PairCalculatedDataSellBuy>,
     name_length: usize,
 ) -> Vec<Row<'b>> {
-    let rows = std::iter::once(Row::new(vec![
-        TableCell::new("id"),
-        TableCell::new("item name"),
-        TableCell::new("src prc"),
-        TableCell::new("dst prc"),
-        TableCell::new("expenses"),
-        TableCell::new("sell prc"),
-        TableCell::new("margin"),
-        TableCell::new("vlm src"),
-        TableCell::new("vlm dst"),
-        TableCell::new("mkt src"),
-        TableCell::new("mkt dst"),
-        TableCell::new("rough prft"),
-        TableCell::new("rcmnd"),
-        TableCell::new("vlm"),
-    ]))
+    let rows = std::iter::once(Row::new(
+        vec![
+            TableCell::new("id"),
+            TableCell::new("item name"),
+            TableCell::new("src prc"),
+            TableCell::new("dst prc"),
+            TableCell::new("expenses"),
+            TableCell::new("sell prc"),
+            TableCell::new("margin"),
+            TableCell::new("vlm src"),
+            TableCell::new("vlm dst"),
+            TableCell::new("mkt src"),
+            TableCell::new("mkt dst"),
+            TableCell::new("rough prft"),
+            TableCell::new("roc/d"),
+            TableCell::new("rcmnd"),
+            TableCell::new("vlm"),
+        ]
+        .into_iter()
+        .chain(help::trend_header_cells())
+        .collect::<Vec<_>>(),
+    ))
     .chain(good_items.items.iter().map(|it| {
         let item = &it.item;
         let short_name =
             item.market.desc.name[..(name_length.min(item.market.desc.name.len()))].to_owned();
-        Row::new(vec![
-            TableCell::new(format!("{}", item.market.desc.type_id)),
-            TableCell::new(short_name),
-            TableCell::new(format!("{:.2}", item.src_buy_price)),
-            TableCell::new(format!("{:.2}", item.dest_min_sell_price)),
-            TableCell::new(format!(
-                "{:.2}",
-                item.expenses * item.max_profitable_buy_volume as f64
-            )),
-            TableCell::new(format!("{:.2}", item.sell_price)),
-            TableCell::new(format!("{:.2}", item.margin)),
-            TableCell::new(format!(
-                "{:.2}",
-                item.src_avgs.map(|x| x.volume).unwrap_or(0f64)
-            )),
-            TableCell::new(format!(
-                "{:.2}",
-                item.dst_avgs.map(|x| x.volume).unwrap_or(0f64)
-            )),
-            TableCell::new(format!("{:.2}", item.market_src_volume)),
-            TableCell::new(format!("{:.2}", item.market_dest_volume)),
-            TableCell::new(format!("{:.2}", it.rough_profit)),
-            TableCell::new(format!("{}", it.recommend_buy)),
-            TableCell::new(format!("{}", it.volume_m3)),
-        ])
+        Row::new(
+            vec![
+                TableCell::new(format!("{}", item.market.desc.type_id)),
+                TableCell::new(short_name),
+                TableCell::new(format!("{:.2}", item.src_buy_price)),
+                TableCell::new(format!("{:.2}", item.dest_min_sell_price)),
+                TableCell::new(format!(
+                    "{:.2}",
+                    item.expenses * item.max_profitable_buy_volume as f64
+                )),
+                TableCell::new(format!("{:.2}", item.sell_price)),
+                TableCell::new(format!("{:.2}", item.margin)),
+                TableCell::new(format!(
+                    "{:.2}",
+                    item.src_avgs.map(|x| x.volume).unwrap_or(0f64)
+                )),
+                TableCell::new(format!(
+                    "{:.2}",
+                    item.dst_avgs.map(|x| x.volume).unwrap_or(0f64)
+                )),
+                TableCell::new(format!("{:.2}", item.market_src_volume)),
+                TableCell::new(format!("{:.2}", item.market_dest_volume)),
+                TableCell::new(format!("{:.2}", it.rough_profit)),
+                TableCell::new(
+                    it.profit_data
+                        .return_per_day()
+                        .map_or("N/A".to_string(), |x| format!("{:.4}", x)),
+                ),
+                TableCell::new(format!("{}", it.recommend_buy)),
+                TableCell::new(format!("{}", it.volume_m3)),
+            ]
+            .into_iter()
+            .chain(help::trend_cells(item.dst_avgs))
+            .collect::<Vec<_>>(),
+        )
     }))
     .chain(std::iter::once(Row::new(vec![
         TableCell::new("total profit"),
         TableCell::new_with_col_span(
             (good_items.sum_profit.round() as i64).to_formatted_string(&Locale::fr),
-            13,
+            17,
         ),
     ])))
     .chain(std::iter::once(Row::new(vec![
         TableCell::new("total volume"),
-        TableCell::new_with_col_span(good_items.total_volume.to_formatted_string(&Locale::fr), 13),
+        TableCell::new_with_col_span(good_items.total_volume.to_formatted_string(&Locale::fr), 17),
     ])))
     .collect::<Vec<_>>();
     rows
@@ -224,6 +272,8 @@ impl From<PairCalculatedDataSellBuy> for help::ItemProfitData {
             expenses: value.expenses,
             sell_price: value.sell_price,
             max_item_amount: value.max_profitable_buy_volume,
+            // sold into buy orders right away
+            days_to_sell: help::locked_days(Some(0.)),
         }
     }
 }
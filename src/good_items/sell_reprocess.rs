@@ -12,7 +12,9 @@ use crate::{
     order_ext::OrderIterExt,
 };
 
-use super::help::{calculate_item_averages, match_buy_from_sell_orders, match_buy_orders_profit};
+use super::help::{
+    self, calculate_item_averages, match_buy_from_sell_orders, match_buy_orders_profit,
+};
 
 pub fn get_good_items_sell_reprocess(
     pairs: Vec<SystemMarketsItemData>,
@@ -29,6 +31,9 @@ pub fn get_good_items_sell_reprocess(
         .par_iter()
         .filter_map(|x| process_item_pair(datadump, x, config, &items_map))
         .filter(|x| disable_filters || x.margin > config.common.margin_cutoff)
+        .filter(|x| {
+            disable_filters || help::passes_trend_filter(&config.common.trend_filter, x.dst_avgs)
+        })
         .filter(|x| {
             disable_filters
                 || config
@@ -236,64 +241,74 @@ pub fn make_table_sell_reprocess<'b>(
     good_items: &ProcessedSellBuyItems,
     name_length: usize,
 ) -> Vec<Row<'b>> {
-    let rows = std::iter::once(Row::new(vec![
-        TableCell::new("id"),
-        TableCell::new("item name"),
-        TableCell::new("src prc"),
-        TableCell::new("dst prc"),
-        TableCell::new("expenses"),
-        TableCell::new("profit"),
-        TableCell::new("margin"),
-        TableCell::new("vlm src"),
-        TableCell::new("vlm dst"),
-        TableCell::new("mkt src"),
-        TableCell::new("mkt dst"),
-        TableCell::new("rough prft"),
-        TableCell::new("rcmnd"),
-        TableCell::new("portion"),
-    ]))
+    let rows = std::iter::once(Row::new(
+        vec![
+            TableCell::new("id"),
+            TableCell::new("item name"),
+            TableCell::new("src prc"),
+            TableCell::new("dst prc"),
+            TableCell::new("expenses"),
+            TableCell::new("profit"),
+            TableCell::new("margin"),
+            TableCell::new("vlm src"),
+            TableCell::new("vlm dst"),
+            TableCell::new("mkt src"),
+            TableCell::new("mkt dst"),
+            TableCell::new("rough prft"),
+            TableCell::new("rcmnd"),
+            TableCell::new("portion"),
+        ]
+        .into_iter()
+        .chain(help::trend_header_cells())
+        .collect::<Vec<_>>(),
+    ))
     .chain(good_items.items.iter().map(|it| {
         let short_name =
             it.market.desc.name[..(name_length.min(it.market.desc.name.len()))].to_owned();
-        Row::new(vec![
-            TableCell::new(format!("{}", it.market.desc.type_id)),
-            TableCell::new(short_name),
-            TableCell::new(format!("{:.2}", it.src_buy_price)),
-            TableCell::new(format!("{:.2}", it.dest_min_sell_price)),
-            TableCell::new(format!("{:.2}", it.expenses)),
-            TableCell::new(format!("{:.2}", it.profit)),
-            TableCell::new(format!("{:.2}", it.margin)),
-            TableCell::new(format!(
-                "{:.2}",
-                it.src_avgs.map(|x| x.volume).unwrap_or(0f64)
-            )),
-            TableCell::new(format!(
-                "{:.2}",
-                it.dst_avgs.map(|x| x.volume).unwrap_or(0f64)
-            )),
-            TableCell::new(format!("{:.2}", it.market_src_volume)),
-            TableCell::new(format!("{:.2}", it.market_dest_volume)),
-            TableCell::new(format!("{:.2}", it.rough_profit)),
-            TableCell::new(format!("{}", it.recommend_buy)),
-            TableCell::new(format!("{}", it.portion_size)),
-        ])
+        Row::new(
+            vec![
+                TableCell::new(format!("{}", it.market.desc.type_id)),
+                TableCell::new(short_name),
+                TableCell::new(format!("{:.2}", it.src_buy_price)),
+                TableCell::new(format!("{:.2}", it.dest_min_sell_price)),
+                TableCell::new(format!("{:.2}", it.expenses)),
+                TableCell::new(format!("{:.2}", it.profit)),
+                TableCell::new(format!("{:.2}", it.margin)),
+                TableCell::new(format!(
+                    "{:.2}",
+                    it.src_avgs.map(|x| x.volume).unwrap_or(0f64)
+                )),
+                TableCell::new(format!(
+                    "{:.2}",
+                    it.dst_avgs.map(|x| x.volume).unwrap_or(0f64)
+                )),
+                TableCell::new(format!("{:.2}", it.market_src_volume)),
+                TableCell::new(format!("{:.2}", it.market_dest_volume)),
+                TableCell::new(format!("{:.2}", it.rough_profit)),
+                TableCell::new(format!("{}", it.recommend_buy)),
+                TableCell::new(format!("{}", it.portion_size)),
+            ]
+            .into_iter()
+            .chain(help::trend_cells(it.dst_avgs))
+            .collect::<Vec<_>>(),
+        )
     }))
     .chain(std::iter::once(Row::new(vec![
         TableCell::new("total profit"),
         TableCell::new_with_col_span(
             (good_items.sum_profit.round() as i64).to_formatted_string(&Locale::fr),
-            13,
+            16,
         ),
     ])))
     .chain(std::iter::once(Row::new(vec![
         TableCell::new("total volume"),
-        TableCell::new_with_col_span(good_items.sum_volume.to_formatted_string(&Locale::fr), 13),
+        TableCell::new_with_col_span(good_items.sum_volume.to_formatted_string(&Locale::fr), 16),
     ])))
     .chain(std::iter::once(Row::new(vec![
         TableCell::new("reprocess volume"),
         TableCell::new_with_col_span(
             good_items.reprocess_volume.to_formatted_string(&Locale::fr),
-            13,
+            16,
         ),
     ])))
     .collect::<Vec<_>>();
@@ -1,17 +1,20 @@
+use chrono::Utc;
 use num_format::{Locale, ToFormattedString};
 
 use term_table::{row::Row, table_cell::TableCell};
 
 use crate::{
+    cli,
     config::{CommonConfig, Config},
     item_type::{ItemTypeAveraged, MarketData, SystemMarketsItemData},
-    order_ext::OrderIterExt,
+    order_ext::{genuine_orders, OrderIterExt},
     requests::service::to_not_nan,
     zkb::killmails::ItemFrequencies,
 };
 
-use super::help::{self, calculate_item_averages, calculate_optimal_buy_volume, outbid_price};
-use super::help::{calculate_weighted_price, DataVecExt};
+use super::backtest::Recommendation;
+use super::help::{self, calculate_market_averages, calculate_optimal_buy_volume, outbid_price};
+use super::help::{calculate_weighted_price, time_to_sell, DataVecExt, TimeToSell};
 
 pub fn get_good_items_sell_sell(
     pairs: Vec<SystemMarketsItemData>,
@@ -33,9 +36,8 @@ pub fn get_good_items_sell_sell(
             let dst_mkt_orders = market_data.destination.orders.clone();
             let dst_volume_on_market = dst_mkt_orders.iter().sell_order_volume();
 
-            let src_avgs = calculate_item_averages(&config.common, &market_data.source.history);
-            let dst_avgs =
-                calculate_item_averages(&config.common, &market_data.destination.history);
+            let src_avgs = calculate_market_averages(&config.common, &market_data.source);
+            let dst_avgs = calculate_market_averages(&config.common, &market_data.destination);
 
             let common = prepare_sell_sell(
                 config,
@@ -70,6 +72,9 @@ pub fn get_good_items_sell_sell(
                         .min_profit
                         .map_or(true, |min_prft| x.rough_profit > min_prft)
         })
+        .filter(|x| {
+            disable_filters || help::passes_trend_filter(&config.common.trend_filter, x.dst_avgs)
+        })
         .filter(|x| {
             disable_filters
                 || if let Some(filled_for_days) = x.filled_for_days {
@@ -82,71 +87,123 @@ pub fn get_good_items_sell_sell(
         .take_maximizing_profit(
             config.common.cargo_capacity,
             config.common.items_take as i32,
+            config.common.objective,
         )
 }
 
+/// Recommended items, recorded for the backtest.
+pub fn recommendations_sell_sell(
+    good_items: &help::ProfitableItemsSummary<PairCalculatedDataSellSell>,
+    config: &Config,
+) -> Vec<Recommendation> {
+    let time = Utc::now();
+    good_items
+        .items
+        .iter()
+        .map(|it| Recommendation {
+            time,
+            mode: cli::SELL_SELL.to_string(),
+            source: config.route.source.name.clone(),
+            destination: config.route.destination.name.clone(),
+            type_id: it.item.market.desc.type_id,
+            name: it.item.market.desc.name.clone(),
+            quantity: it.recommend_buy,
+            buy_price: it.item.src_buy_price,
+            sell_price: it.item.dest_min_sell_price,
+            profit: it.rough_profit,
+            knobs: vec![
+                format!("mode={}", cli::SELL_SELL),
+                it.item.sell_price_basis.knob(&config.common),
+            ],
+        })
+        .collect()
+}
+
 pub fn make_table_sell_sell<'b>(
     good_items: &help::ProfitableItemsSummary<PairCalculatedDataSellSell>,
     name_length: usize,
 ) -> Vec<Row<'b>> {
-    let rows = std::iter::once(Row::new(vec![
-        TableCell::new("id"),
-        TableCell::new("itm nm"),
-        TableCell::new("src p"),
-        TableCell::new("dst p"),
-        TableCell::new("expns"),
-        TableCell::new("sll p"),
-        TableCell::new("mrgn"),
-        TableCell::new("vlm src"),
-        TableCell::new("vlm dst"),
-        TableCell::new("mkt src"),
-        TableCell::new("mkt dst"),
-        TableCell::new("lst"),
-        TableCell::new("rgh prft"),
-        TableCell::new("buy"),
-        TableCell::new("fld"),
-    ]))
+    let rows = std::iter::once(Row::new(
+        vec![
+            TableCell::new("id"),
+            TableCell::new("itm nm"),
+            TableCell::new("src p"),
+            TableCell::new("dst p"),
+            TableCell::new("expns"),
+            TableCell::new("sll p"),
+            TableCell::new("mrgn"),
+            TableCell::new("vlm src"),
+            TableCell::new("vlm dst"),
+            TableCell::new("mkt src"),
+            TableCell::new("mkt dst"),
+            TableCell::new("lst"),
+            TableCell::new("rgh prft"),
+            TableCell::new("roc/d"),
+            TableCell::new("buy"),
+            TableCell::new("fld"),
+            TableCell::new("tts50"),
+            TableCell::new("tts90"),
+        ]
+        .into_iter()
+        .chain(help::trend_header_cells())
+        .collect::<Vec<_>>(),
+    ))
     .chain(good_items.items.iter().map(|it| {
+        let return_per_day = it.profit_data.return_per_day();
         let it = &it.item;
         let short_name =
             it.market.desc.name[..(name_length.min(it.market.desc.name.len()))].to_owned();
-        Row::new(vec![
-            TableCell::new(format!("{}", it.market.desc.type_id)),
-            TableCell::new(short_name),
-            TableCell::new(format!("{:.2}", it.src_buy_price)),
-            TableCell::new(format!("{:.2}", it.dest_min_sell_price)),
-            TableCell::new(format!("{:.2}", it.expenses * it.recommend_buy as f64)),
-            TableCell::new(format!("{:.2}", it.sell_price)),
-            TableCell::new(format!("{:.2}", it.margin)),
-            TableCell::new(format!(
-                "{:.2}",
-                it.src_avgs.map(|x| x.volume).unwrap_or(0f64)
-            )),
-            TableCell::new(format!(
-                "{:.2}",
-                it.dst_avgs.map(|x| x.volume).unwrap_or(0f64)
-            )),
-            TableCell::new(format!("{:.2}", it.market_src_volume)),
-            TableCell::new(format!("{:.2}", it.market_dest_volume)),
-            TableCell::new(format!("{:.2}", it.lost_per_day)),
-            TableCell::new(format!("{:.2}", it.rough_profit)),
-            TableCell::new(format!("{}", it.recommend_buy)),
-            TableCell::new(
-                it.filled_for_days
-                    .map_or("N/A".to_string(), |x| format!("{:.2}", x)),
-            ),
-        ])
+        Row::new(
+            vec![
+                TableCell::new(format!("{}", it.market.desc.type_id)),
+                TableCell::new(short_name),
+                TableCell::new(format!("{:.2}", it.src_buy_price)),
+                TableCell::new(format!("{:.2}", it.dest_min_sell_price)),
+                TableCell::new(format!("{:.2}", it.expenses * it.recommend_buy as f64)),
+                TableCell::new(format!("{:.2}", it.sell_price)),
+                TableCell::new(format!("{:.2}", it.margin)),
+                TableCell::new(format!(
+                    "{:.2}",
+                    it.src_avgs.map(|x| x.volume).unwrap_or(0f64)
+                )),
+                TableCell::new(format!(
+                    "{:.2}",
+                    it.dst_avgs.map(|x| x.volume).unwrap_or(0f64)
+                )),
+                TableCell::new(format!("{:.2}", it.market_src_volume)),
+                TableCell::new(format!("{:.2}", it.market_dest_volume)),
+                TableCell::new(format!("{:.2}", it.lost_per_day)),
+                TableCell::new(format!("{:.2}", it.rough_profit)),
+                TableCell::new(return_per_day.map_or("N/A".to_string(), |x| format!("{:.4}", x))),
+                TableCell::new(format!("{}", it.recommend_buy)),
+                TableCell::new(
+                    it.filled_for_days
+                        .map_or("N/A".to_string(), |x| format!("{:.2}", x)),
+                ),
+                TableCell::new(
+                    it.time_to_sell
+                        .map_or("N/A".to_string(), |x| format!("{:.2}", x.p50)),
+                ),
+                TableCell::new(
+                    it.time_to_sell
+                        .map_or("N/A".to_string(), |x| format!("{:.2}", x.p90)),
+                ),
+            ]
+            .into_iter()
+            .chain(help::trend_cells(it.dst_avgs))
+            .collect::<Vec<_>>(),
+        )
     }))
     .chain(std::iter::once(Row::new(vec![
         TableCell::new("total profit"),
         TableCell::new_with_col_span(
             (good_items.sum_profit.round() as i64).to_formatted_string(&Locale::fr),
-            14,
+            20,
         ),
     ])))
     .chain(std::iter::once(Row::new(vec![
         TableCell::new("total volume"),
-        TableCell::new_with_col_span(good_items.total_volume.to_formatted_string(&Locale::fr), 14),
+        TableCell::new_with_col_span(good_items.total_volume.to_formatted_string(&Locale::fr), 20),
     ])))
     .collect::<Vec<_>>();
     rows
@@ -162,8 +219,11 @@ pub struct PairCalculatedDataSellSell {
     pub expenses: f64,
     pub sell_price: f64,
     pub filled_for_days: Option<f64>,
+    /// Days to sell the recommended amount behind competing orders.
+    pub time_to_sell: Option<TimeToSell>,
     pub src_buy_price: f64,
     pub dest_min_sell_price: f64,
+    pub sell_price_basis: SellPriceBasis,
     pub src_avgs: Option<ItemTypeAveraged>,
     pub dst_avgs: Option<ItemTypeAveraged>,
     pub market_src_volume: i64,
@@ -177,6 +237,7 @@ impl From<PairCalculatedDataSellSell> for help::ItemProfitData {
             expenses: value.expenses,
             sell_price: value.sell_price,
             max_item_amount: value.recommend_buy,
+            days_to_sell: help::locked_days(value.time_to_sell.map(|x| x.p50)),
         }
     }
 }
@@ -192,7 +253,7 @@ pub fn prepare_sell_sell(
 ) -> Option<PairCalculatedDataSellSell> {
     let src_lowest_sell_order = market_data.source.orders.iter().sell_order_min_price()?;
 
-    let dest_sell_price = calculate_sell_price(
+    let (dest_sell_price, sell_price_basis) = calculate_sell_price_with_basis(
         dst_avgs,
         &market_data.destination,
         &config.common,
@@ -243,6 +304,19 @@ pub fn prepare_sell_sell(
         None
     };
 
+    // zkb losses only raise the expected volume, they don't make it more volatile
+    let competing_volume = market_data
+        .destination
+        .orders
+        .iter()
+        .sell_order_volume_up_to_price(dest_sell_price);
+    let time_to_sell = time_to_sell(
+        buy_from_src_volume,
+        competing_volume,
+        expected_item_volume_per_day,
+        dst_avgs.map_or(0., |x| x.volume_std_dev),
+    );
+
     Some(PairCalculatedDataSellSell {
         market: market_data,
         margin,
@@ -252,8 +326,10 @@ pub fn prepare_sell_sell(
         expenses,
         sell_price: sell_price_with_taxes,
         filled_for_days,
+        time_to_sell,
         src_buy_price: buy_from_src_price,
         dest_min_sell_price: dest_sell_price,
+        sell_price_basis,
         market_src_volume: src_volume_on_market,
         src_avgs,
         dst_avgs,
@@ -268,41 +344,88 @@ pub fn calculate_sell_price(
     buy_price: f64,
     conservative: bool,
 ) -> f64 {
+    calculate_sell_price_with_basis(dst_avgs, dest_market, config, buy_price, conservative).0
+}
+
+/// Same as [`calculate_sell_price`], along with what the price is based on.
+pub fn calculate_sell_price_with_basis(
+    dst_avgs: Option<ItemTypeAveraged>,
+    dest_market: &MarketData,
+    config: &CommonConfig,
+    buy_price: f64,
+    conservative: bool,
+) -> (f64, SellPriceBasis) {
+    let orders = || genuine_orders(&dest_market.orders, dst_avgs, &config.manipulation);
     let dst_lowest_sell_order = if let Some(dst_avgs) = dst_avgs {
-        dest_market.orders.iter().get_lowest_sell_order_over_volume(
+        orders().get_lowest_sell_order_over_volume(
             dst_avgs.volume * config.sell_sell.dst_ignore_orders_under_volume_pct,
         )
     } else {
-        dest_market.orders.iter().sell_order_min_price()
+        orders().sell_order_min_price()
     };
 
-    let mut sell_with_markup = buy_price * (1. + config.sell_sell.markup_if_no_orders_dest);
-
-    let weighted_price = calculate_weighted_price(config, &dest_market.history);
-    if let Ok(weighted_price) = weighted_price {
-        sell_with_markup = sell_with_markup.max(weighted_price)
-    }
+    let sell_with_markup = buy_price * (1. + config.sell_sell.markup_if_no_orders_dest);
+    let sell_with_markup = match calculate_weighted_price(config, &dest_market.history) {
+        Ok(weighted_price) if weighted_price > sell_with_markup => {
+            (weighted_price, SellPriceBasis::History)
+        }
+        _ => (sell_with_markup, SellPriceBasis::Markup),
+    };
+    let high_average_or_markup = |high_average: f64| {
+        if high_average >= sell_with_markup.0 {
+            (high_average, SellPriceBasis::History)
+        } else {
+            sell_with_markup
+        }
+    };
 
     if let (Some(dst_lowest_sell_order), Some(dst_avgs)) = (dst_lowest_sell_order, dst_avgs) {
         if conservative && dst_lowest_sell_order > dst_avgs.high_average {
-            return dst_avgs.high_average;
+            return (dst_avgs.high_average, SellPriceBasis::History);
         }
-
         if dst_lowest_sell_order > dst_avgs.high_average
             && ((dst_lowest_sell_order - dst_avgs.high_average) / dst_avgs.high_average)
                 > config.ignore_difference_between_history_and_order_pct
         {
-            return dst_avgs.high_average.max(sell_with_markup);
+            return high_average_or_markup(dst_avgs.high_average);
         }
     };
 
     if let Some(dst_lowest_sell_order) = dst_lowest_sell_order {
-        return outbid_price(dst_lowest_sell_order, false);
+        return (
+            outbid_price(dst_lowest_sell_order, false),
+            SellPriceBasis::Orders,
+        );
     }
 
     if let Some(dst_avgs) = dst_avgs {
-        return dst_avgs.high_average.max(sell_with_markup);
+        return high_average_or_markup(dst_avgs.high_average);
     }
 
     sell_with_markup
 }
+
+/// What a predicted sell price comes from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SellPriceBasis {
+    /// Undercutting the lowest sell order.
+    Orders,
+    /// Prices in the market history.
+    History,
+    /// `markup_if_no_orders_dest` over the buy price.
+    Markup,
+}
+
+impl SellPriceBasis {
+    /// Name of the config setting the price depends on, used to group backtest results.
+    pub fn knob(&self, config: &CommonConfig) -> String {
+        match self {
+            SellPriceBasis::Orders => "sell price from orders".to_string(),
+            SellPriceBasis::History => format!("price_estimator={:?}", config.price_estimator),
+            SellPriceBasis::Markup => format!(
+                "markup_if_no_orders_dest={}",
+                config.sell_sell.markup_if_no_orders_dest
+            ),
+        }
+    }
+}
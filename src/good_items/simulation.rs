@@ -0,0 +1,546 @@
+use std::collections::HashMap;
+
+use chrono::{Duration, NaiveDate, TimeZone, Utc};
+use itertools::Itertools;
+use term_table::{row::Row, table_cell::TableCell};
+
+use crate::{
+    cached_data::CachedStuff,
+    config::{CommonConfig, Config, ConfigSimulationVariant, RouteConfig},
+    consts::DATE_FMT,
+    datadump_service::{DatadumpService, GroupFilter},
+    item_type::{
+        ItemHistory, ItemHistoryDay, ItemOrders, MarketData, MarketsRegionHistory, Order,
+        SystemMarketsItemData,
+    },
+    load_create::{create_load_all_types, create_load_item_descriptions, load_cached_history},
+    requests::service::EsiRequestsService,
+    zkb::killmails::ItemFrequencies,
+    Station,
+};
+
+use super::{
+    sell_sell::get_good_items_sell_sell,
+    station_trading::{calculate_station_trade, pick_station_trades, Competition},
+};
+
+pub struct SimulationService<'a> {
+    pub cache: &'a mut CachedStuff,
+    pub datadump: &'a DatadumpService,
+    pub esi_requests: &'a EsiRequestsService<'a>,
+    pub config: &'a CommonConfig,
+}
+
+impl<'a> SimulationService<'a> {
+    /// Replays past sell-sell decisions on the route using cached history.
+    pub async fn simulate_sell_sell(
+        &mut self,
+        route: &RouteConfig,
+        character_id: i32,
+    ) -> anyhow::Result<SimulationData> {
+        let source = self
+            .esi_requests
+            .find_region_id_station(&route.source, character_id)
+            .await?;
+        let destination = self
+            .esi_requests
+            .find_region_id_station(&route.destination, character_id)
+            .await?;
+        let all_types =
+            create_load_all_types(self.cache, self.esi_requests, source, destination).await?;
+        let descriptions =
+            create_load_item_descriptions(self.cache, &all_types, self.esi_requests).await?;
+        let group_filter = GroupFilter::new(
+            self.datadump,
+            self.config.sell_sell.include_groups.as_ref(),
+            self.config.sell_sell.exclude_groups.as_ref(),
+        )?;
+
+        let source_history = dated_histories(load_cached_history(self.cache, source).await?);
+        let destination_history =
+            dated_histories(load_cached_history(self.cache, destination).await?);
+
+        let no_losses = ItemFrequencies {
+            items: HashMap::new(),
+            period_seconds: 1,
+        };
+        let results = self
+            .variants()
+            .into_iter()
+            .map(|(variant, config)| {
+                let config = Config {
+                    route: route.clone(),
+                    common: config,
+                };
+                let mut total = SimulatedTrade::default();
+                for day in decision_days(&config.common) {
+                    let pairs = source_history
+                        .iter()
+                        .filter_map(|(type_id, source)| {
+                            let desc = descriptions.get(type_id)?.as_ref()?;
+                            if !group_filter.matches(desc.market_group_id) {
+                                return None;
+                            }
+                            Some(SystemMarketsItemData {
+                                desc: desc.clone(),
+                                adjusted_price: None,
+                                source: source.market_at(day, &config.common)?,
+                                destination: destination_history
+                                    .get(type_id)?
+                                    .market_at(day, &config.common)?,
+                            })
+                        })
+                        .collect_vec();
+                    let good_items =
+                        get_good_items_sell_sell(pairs, &config, false, no_losses.clone())?;
+                    for item in good_items.items {
+                        let it = &item.item;
+                        let plan = TradePlan {
+                            quantity: item.recommend_buy,
+                            buy_order_price: None,
+                            buy_cost: it.expenses,
+                            sell_order_price: it.dest_min_sell_price,
+                            sell_income: it.sell_price,
+                        };
+                        let Some(following) = destination_history.get(&it.market.desc.type_id)
+                        else {
+                            continue;
+                        };
+                        total += simulate_trade(
+                            &plan,
+                            following.following(day, config.common.simulation.trade_days),
+                            config.common.simulation.market_share,
+                            config.common.sales_tax,
+                        );
+                    }
+                }
+                Ok(SimulationResult { variant, total })
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        Ok(SimulationData { results })
+    }
+
+    /// Replays past station trading decisions in the station using cached history.
+    pub async fn simulate_station_trading(
+        &mut self,
+        station_config: &Station,
+        character_id: i32,
+    ) -> anyhow::Result<SimulationData> {
+        let station = self
+            .esi_requests
+            .find_region_id_station(station_config, character_id)
+            .await?;
+        let all_types =
+            create_load_all_types(self.cache, self.esi_requests, station, station).await?;
+        let descriptions =
+            create_load_item_descriptions(self.cache, &all_types, self.esi_requests).await?;
+        let group_filter = GroupFilter::new(
+            self.datadump,
+            self.config.station_trade.include_groups.as_ref(),
+            self.config.station_trade.exclude_groups.as_ref(),
+        )?;
+
+        let histories = dated_histories(load_cached_history(self.cache, station).await?);
+
+        let results = self
+            .variants()
+            .into_iter()
+            .map(|(variant, config)| {
+                let mut total = SimulatedTrade::default();
+                for day in decision_days(&config) {
+                    let items = histories
+                        .iter()
+                        .filter_map(|(type_id, history)| {
+                            let desc = descriptions.get(type_id)?.as_ref()?;
+                            if !group_filter.matches(desc.market_group_id) {
+                                return None;
+                            }
+                            let market = history.market_at(day, &config)?;
+                            // snapshots of past order books aren't kept, so no competition
+                            calculate_station_trade(
+                                desc.clone(),
+                                market,
+                                Competition::default(),
+                                &config,
+                                station_config,
+                            )
+                        })
+                        .collect_vec();
+                    for it in pick_station_trades(items, &config, false) {
+                        let plan = TradePlan {
+                            quantity: it.recommend_buy,
+                            buy_order_price: Some(it.buy_price),
+                            buy_cost: it.buy_price * (1. + station_config.broker_fee),
+                            sell_order_price: it.sell_price,
+                            sell_income: it.gain_per_item,
+                        };
+                        let history = &histories[&it.desc.type_id];
+                        total += simulate_trade(
+                            &plan,
+                            history.following(day, config.simulation.trade_days),
+                            config.simulation.market_share,
+                            config.sales_tax,
+                        );
+                    }
+                }
+                SimulationResult { variant, total }
+            })
+            .collect();
+        Ok(SimulationData { results })
+    }
+
+    /// The configured settings followed by every variant, with their descriptions.
+    fn variants(&self) -> Vec<(String, CommonConfig)> {
+        std::iter::once(ConfigSimulationVariant::default())
+            .chain(self.config.simulation.variants.iter().cloned())
+            .map(|variant| {
+                let mut config = self.config.clone();
+                if let Some(days_average) = variant.days_average {
+                    config.days_average = days_average;
+                }
+                if let Some(margin_cutoff) = variant.margin_cutoff {
+                    config.margin_cutoff = margin_cutoff;
+                }
+                if let Some(rcmnd_fill_days) = variant.rcmnd_fill_days {
+                    config.sell_sell.rcmnd_fill_days = rcmnd_fill_days;
+                }
+                let description = format!(
+                    "days_average={} margin_cutoff={} rcmnd_fill_days={}",
+                    config.days_average, config.margin_cutoff, config.sell_sell.rcmnd_fill_days
+                );
+                (description, config)
+            })
+            .collect()
+    }
+}
+
+/// Days decisions are simulated at, oldest first, each one followed by
+/// `trade_days` of history.
+fn decision_days(config: &CommonConfig) -> Vec<NaiveDate> {
+    let today = Utc::now().date_naive();
+    let first = today - Duration::try_days(config.simulation.days).unwrap();
+    let last = today - Duration::try_days(config.simulation.trade_days).unwrap();
+    let step = config.simulation.step_days.max(1) as usize;
+    first
+        .iter_days()
+        .take_while(|&x| x <= last)
+        .step_by(step)
+        .collect()
+}
+
+fn dated_histories(histories: HashMap<i32, ItemHistory>) -> HashMap<i32, DatedHistory> {
+    histories
+        .into_iter()
+        .map(|(type_id, history)| (type_id, DatedHistory::new(&history)))
+        .collect()
+}
+
+/// History of an item as downloaded with parsed dates, oldest first.
+pub struct DatedHistory {
+    type_id: i32,
+    dates: Vec<NaiveDate>,
+    rows: Vec<MarketsRegionHistory>,
+    days: Vec<ItemHistoryDay>,
+}
+
+impl DatedHistory {
+    pub fn new(history: &ItemHistory) -> Self {
+        let (dates, rows): (Vec<_>, Vec<_>) = history
+            .history
+            .iter()
+            .filter_map(|x| {
+                let date = NaiveDate::parse_from_str(&x.date, DATE_FMT).ok()?;
+                Some((date, x.clone()))
+            })
+            .sorted_by_key(|(date, _)| *date)
+            .unzip();
+        let days = rows
+            .iter()
+            .map(|x| ItemHistoryDay {
+                average: x.average,
+                highest: x.highest,
+                lowest: x.lowest,
+                order_count: x.order_count,
+                volume: x.volume,
+            })
+            .collect();
+        Self {
+            type_id: history.id,
+            dates,
+            rows,
+            days,
+        }
+    }
+
+    /// Market of the item as it was at the end of `day`: history up to the day
+    /// repaired as if it was today and, if the item traded that day, an order
+    /// on each side at its extreme prices.
+    pub fn market_at(&self, day: NaiveDate, config: &CommonConfig) -> Option<MarketData> {
+        let end = self.dates.partition_point(|&x| x <= day);
+        if end == 0 {
+            return None;
+        }
+        let last = &self.rows[end - 1];
+        let prices = last
+            .impute_prices()
+            .filter(|_| self.dates[end - 1] == day && last.volume > 0);
+        let orders = match prices {
+            Some((_, highest, lowest)) => {
+                let issued = Utc
+                    .from_utc_datetime(&day.and_hms_opt(0, 0, 0).unwrap())
+                    .to_rfc3339();
+                let order = |is_buy_order: bool, price: f64| Order {
+                    duration: 90,
+                    is_buy_order,
+                    issued: issued.clone(),
+                    location_id: 0,
+                    min_volume: 1,
+                    order_id: is_buy_order as i64,
+                    price,
+                    type_id: self.type_id,
+                    volume_remain: last.volume,
+                    volume_total: last.volume,
+                };
+                vec![order(false, highest), order(true, lowest)]
+            }
+            None => Vec::new(),
+        };
+
+        // gaps are filled only from the days known by then, later prices would leak in
+        let mut history = ItemHistory {
+            id: self.type_id,
+            history: self.rows[..end].to_vec(),
+        };
+        history.impute_missing_prices();
+        history.fill_gaps(config.history_gap_fill, config.history_window_days, day);
+        Some(MarketData::new(
+            ItemOrders {
+                id: self.type_id,
+                orders,
+            },
+            history,
+        ))
+    }
+
+    /// Up to `days` days of history right after `day`.
+    pub fn following(&self, day: NaiveDate, days: i64) -> &[ItemHistoryDay] {
+        let last = day + Duration::try_days(days).unwrap();
+        let start = self.dates.partition_point(|&x| x <= day);
+        let end = self.dates.partition_point(|&x| x <= last);
+        &self.days[start..end]
+    }
+}
+
+/// What a simulated decision does with an item.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TradePlan {
+    pub quantity: i64,
+    /// Price of the buy order, `None` when the items are bought right away.
+    pub buy_order_price: Option<f64>,
+    /// Price of a unit with fees.
+    pub buy_cost: f64,
+    pub sell_order_price: f64,
+    /// Price of a unit after fees.
+    pub sell_income: f64,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct SimulatedTrade {
+    pub items: usize,
+    pub bought: i64,
+    pub sold: i64,
+    pub cost: f64,
+    pub revenue: f64,
+    /// What the items left unsold would bring sold into buy orders.
+    pub unsold_value: f64,
+}
+
+impl SimulatedTrade {
+    pub fn pnl(&self) -> f64 {
+        self.revenue + self.unsold_value - self.cost
+    }
+}
+
+impl std::ops::AddAssign for SimulatedTrade {
+    fn add_assign(&mut self, rhs: Self) {
+        self.items += rhs.items;
+        self.bought += rhs.bought;
+        self.sold += rhs.sold;
+        self.cost += rhs.cost;
+        self.revenue += rhs.revenue;
+        self.unsold_value += rhs.unsold_value;
+    }
+}
+
+/// Fills the orders of the plan day by day: an order gets `market_share` of
+/// the volume of each day the item traded at its price.
+pub fn simulate_trade(
+    plan: &TradePlan,
+    days: &[ItemHistoryDay],
+    market_share: f64,
+    sales_tax: f64,
+) -> SimulatedTrade {
+    let mut bought = if plan.buy_order_price.is_none() {
+        plan.quantity
+    } else {
+        0
+    };
+    let mut sold = 0;
+    for day in days {
+        let share = (day.volume as f64 * market_share).floor() as i64;
+        if let Some(buy_order_price) = plan.buy_order_price {
+            if day.lowest.map_or(false, |x| x <= buy_order_price) {
+                bought += share.min(plan.quantity - bought);
+            }
+        }
+        if day.highest.map_or(false, |x| x >= plan.sell_order_price) {
+            sold += share.min(bought - sold);
+        }
+    }
+
+    let last_lowest = days
+        .iter()
+        .rev()
+        .filter(|x| x.volume > 0)
+        .find_map(|x| x.lowest)
+        .unwrap_or(0.);
+    SimulatedTrade {
+        items: 1,
+        bought,
+        sold,
+        cost: bought as f64 * plan.buy_cost,
+        revenue: sold as f64 * plan.sell_income,
+        unsold_value: (bought - sold) as f64 * last_lowest * (1. - sales_tax),
+    }
+}
+
+pub struct SimulationResult {
+    /// Settings the result was simulated with.
+    pub variant: String,
+    pub total: SimulatedTrade,
+}
+
+pub struct SimulationData {
+    pub results: Vec<SimulationResult>,
+}
+
+impl SimulationData {
+    pub fn make_table_simulation<'b>(&self) -> Vec<Row<'b>> {
+        std::iter::once(Row::new(vec![
+            TableCell::new("settings"),
+            TableCell::new("items"),
+            TableCell::new("bght"),
+            TableCell::new("sld"),
+            TableCell::new("cost"),
+            TableCell::new("revenue"),
+            TableCell::new("unsold"),
+            TableCell::new("pnl"),
+            TableCell::new("return"),
+        ]))
+        .chain(self.results.iter().map(|it| {
+            let total = &it.total;
+            Row::new(vec![
+                TableCell::new(&it.variant),
+                TableCell::new(format!("{}", total.items)),
+                TableCell::new(format!("{}", total.bought)),
+                TableCell::new(format!("{}", total.sold)),
+                TableCell::new(format!("{:.2}", total.cost)),
+                TableCell::new(format!("{:.2}", total.revenue)),
+                TableCell::new(format!("{:.2}", total.unsold_value)),
+                TableCell::new(format!("{:.2}", total.pnl())),
+                TableCell::new(format!("{:.2}", total.pnl() / total.cost)),
+            ])
+        }))
+        .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::HistoryGapFill;
+
+    use super::*;
+
+    fn config(history_gap_fill: HistoryGapFill) -> CommonConfig {
+        CommonConfig {
+            history_gap_fill,
+            history_window_days: 10,
+            ..CommonConfig::for_tests()
+        }
+    }
+
+    fn day(date: &str, lowest: f64, highest: f64, volume: i64) -> MarketsRegionHistory {
+        MarketsRegionHistory {
+            average: Some((lowest + highest) / 2.),
+            date: date.to_string(),
+            highest: Some(highest),
+            lowest: Some(lowest),
+            order_count: 1,
+            volume,
+        }
+    }
+
+    #[test]
+    fn test_replay_station_trade() {
+        let history = DatedHistory::new(&ItemHistory {
+            id: 34,
+            history: vec![
+                day("2024-01-01", 9., 11., 100),
+                day("2024-01-02", 9.5, 10.5, 100),
+                day("2024-01-03", 8., 10., 100),
+                day("2024-01-04", 9., 12., 100),
+            ],
+        });
+        let date = |x| NaiveDate::parse_from_str(x, DATE_FMT).unwrap();
+
+        let market = history
+            .market_at(date("2024-01-02"), &config(HistoryGapFill::Leave))
+            .unwrap();
+        assert_eq!(market.history.len(), 2);
+        assert_eq!(market.orders.len(), 2);
+
+        let plan = TradePlan {
+            quantity: 15,
+            buy_order_price: Some(9.),
+            buy_cost: 9.,
+            sell_order_price: 11.,
+            sell_income: 10.,
+        };
+        let trade = simulate_trade(&plan, history.following(date("2024-01-02"), 7), 0.1, 0.);
+        // bought 10 on the 3rd and 5 on the 4th, sold 10 on the 4th
+        assert_eq!(trade.bought, 15);
+        assert_eq!(trade.sold, 10);
+        assert_eq!(trade.pnl(), 10. * 10. + 5. * 9. - 15. * 9.);
+    }
+
+    #[test]
+    fn test_market_at_ignores_later_days() {
+        let known = vec![
+            day("2024-01-01", 9., 11., 100),
+            day("2024-01-03", 9., 11., 100),
+        ];
+        let with_later = known
+            .iter()
+            .cloned()
+            .chain([day("2024-01-10", 90., 110., 100)])
+            .collect_vec();
+        let prices_at = |history: Vec<MarketsRegionHistory>| {
+            let history = DatedHistory::new(&ItemHistory { id: 34, history });
+            let day = NaiveDate::parse_from_str("2024-01-05", DATE_FMT).unwrap();
+            let market = history
+                .market_at(day, &config(HistoryGapFill::Median))
+                .unwrap();
+            market
+                .history
+                .iter()
+                .map(|x| (x.average, x.volume))
+                .collect_vec()
+        };
+
+        let prices = prices_at(known);
+        // the ten days up to the 5th are filled with the median of the known days
+        assert_eq!(prices.len(), 11);
+        assert!(prices.iter().all(|&(average, _)| average == Some(10.)));
+        assert_eq!(prices, prices_at(with_later));
+    }
+}
@@ -1,23 +1,28 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
 use anyhow::anyhow;
-use chrono::Duration;
+use chrono::{DateTime, Duration, Utc};
 use itertools::Itertools;
 
 use term_table::{row::Row, table_cell::TableCell};
 
 use crate::{
     cached_data::CachedStuff,
-    config::CommonConfig,
-    datadump_service::DatadumpService,
-    good_items::{help::calculate_item_averages, sell_sell::calculate_sell_price},
+    cli,
+    config::{CommonConfig, ConfigCompetition, ConfigRelist, Objective},
+    datadump_service::{DatadumpService, GroupFilter},
+    good_items::{
+        help::calculate_market_averages,
+        sell_sell::{calculate_sell_price_with_basis, SellPriceBasis},
+    },
     helper_ext::HashMapJoin,
     item_type::{ItemOrders, ItemTypeAveraged, MarketData, TypeDescription},
     load_create::{
         create_load_all_types, create_load_item_descriptions, load_or_create_history,
-        load_or_create_orders,
+        load_or_create_orders, load_order_snapshots, station_volumes,
     },
-    order_ext::OrderIterExt,
+    order_ext::{genuine_orders, OrderIterExt},
+    order_snapshots::{OrderSnapshots, SnapshotOrder},
     requests::{
         item_history::ItemHistoryEsiService,
         service::{to_not_nan, EsiRequestsService},
@@ -25,7 +30,8 @@ use crate::{
     Station,
 };
 
-use super::help::outbid_price;
+use super::backtest::Recommendation;
+use super::help::{self, outbid_price};
 
 pub struct StationTradingService<'a> {
     pub cache: &'a mut CachedStuff,
@@ -52,37 +58,47 @@ impl<'a> StationTradingService<'a> {
         let all_type_descriptions =
             create_load_item_descriptions(self.cache, &all_types, self.esi_requests).await?;
 
+        let item_orders =
+            load_or_create_orders(self.cache, self.config, self.esi_requests, station).await?;
+
+        let group_filter = GroupFilter::new(
+            self.datadump,
+            self.config.station_trade.include_groups.as_ref(),
+            self.config.station_trade.exclude_groups.as_ref(),
+        )?;
+        // history is the slowest to download, so only get it for types that can end up in the table
+        let relevant_types = all_type_descriptions
+            .iter()
+            .filter(|(id, _)| debug_item_id.map_or(true, |x| x == **id))
+            .filter(|(id, _)| item_orders.get(id).map_or(false, |x| !x.orders.is_empty()))
+            .filter(|(_, desc)| {
+                desc.as_ref()
+                    .map_or(false, |x| group_filter.matches(x.market_group_id))
+            })
+            .map(|(id, _)| *id)
+            .collect::<HashSet<_>>();
+        log::info!(
+            "Loading history for {} of {} types",
+            relevant_types.len(),
+            all_type_descriptions.len()
+        );
+
         let item_history = load_or_create_history(
             self.cache,
             station,
-            Duration::try_hours(self.config.item_history_timeout_hours).unwrap(),
+            self.config,
             self.esi_history,
-            &all_type_descriptions.iter().map(|x| *x.0).collect_vec(),
+            &relevant_types.iter().copied().collect_vec(),
         )
         .await?;
 
-        let item_orders = load_or_create_orders(
-            self.cache,
-            Duration::try_seconds((self.config.refresh_timeout_hours * 60. * 60.) as i64).unwrap(),
-            self.esi_requests,
-            station,
-        )
-        .await?;
+        let snapshots = load_order_snapshots(self.cache, station).await?;
+        let station_volumes = station_volumes(self.config, station, &snapshots);
+        let competition = Competition::of(&snapshots, &self.config.station_trade.competition);
 
         let mut item_order_history = item_history.outer_join(item_orders);
-
-        let mut disable_filters = false;
-        if let Some(v) = debug_item_id {
-            item_order_history.retain(|&k, _| k == v);
-            disable_filters = true;
-        }
-
-        retain_item_groups(
-            self.config,
-            self.datadump,
-            &mut item_order_history,
-            &all_type_descriptions,
-        )?;
+        item_order_history.retain(|k, _| relevant_types.contains(k));
+        let disable_filters = debug_item_id.is_some();
 
         let item_data = item_order_history
             .into_iter()
@@ -114,146 +130,144 @@ impl<'a> StationTradingService<'a> {
                         }
                     }
                     .clone(),
-                );
-                let average_history = calculate_item_averages(self.config, &market_data.history);
-
-                let buy_price = if let Ok(buy_price) =
-                    calculate_buy_price(average_history, &market_data, self.config)
-                {
-                    buy_price
-                } else {
-                    log::debug!("No calculate_buy_price for item {}", desc.name);
-                    return Ok(None);
-                };
-                log::debug!("Item {} buy price: {}", type_id, buy_price);
-
-                let sell_price = calculate_sell_price(
-                    average_history,
-                    &market_data,
-                    self.config,
-                    buy_price,
-                    true,
-                );
-
-                let buy_price_with_taxes = buy_price * (1. + station_config.broker_fee);
-                let sell_price_with_taxes =
-                    sell_price * (1. - station_config.broker_fee - self.config.sales_tax);
-
-                let margin = (sell_price_with_taxes - buy_price_with_taxes) / buy_price_with_taxes;
-
-                let Some(expected_item_volume_per_day) = average_history.map(|x| x.volume) else {
-                    log::debug!("No average_history for item {}", desc.name);
-                    return Ok(None);
-                };
-
-                let mut max_buy_vol = (expected_item_volume_per_day
-                    * self.config.station_trade.daily_volume_pct)
-                    .floor() as i64;
-
-                // limit investment
-                if (buy_price_with_taxes * max_buy_vol as f64) > self.config.max_investment_per_item
-                {
-                    max_buy_vol =
-                        (self.config.max_investment_per_item / buy_price_with_taxes).floor() as i64;
-                }
-
-                let rough_profit =
-                    (sell_price_with_taxes - buy_price_with_taxes) * max_buy_vol as f64;
-
-                let src_volume_on_market = market_data.orders.iter().sell_order_volume();
-
-                Ok(Some(PairCalculatedDataStationTrade {
+                )
+                .with_station_volume(station_volumes.get(&type_id).copied());
+                let competition = competition.get(&type_id).copied().unwrap_or_default();
+                Ok(calculate_station_trade(
                     desc,
-                    market: market_data,
-                    margin,
-                    rough_profit,
-                    recommend_buy: max_buy_vol,
-                    expenses: buy_price_with_taxes * max_buy_vol as f64,
-                    gain_per_item: sell_price_with_taxes,
-                    buy_price,
-                    sell_price,
-                    historical_average: average_history,
-                    market_volume: src_volume_on_market,
-                }))
+                    market_data,
+                    competition,
+                    self.config,
+                    &station_config,
+                ))
             })
             .collect::<anyhow::Result<Vec<_>>>()?
             .into_iter()
             .flatten()
-            .filter(|x| {
-                disable_filters
-                    || x.margin > self.config.margin_cutoff
-                        && x.historical_average.map(|x| x.volume).unwrap_or(0f64)
-                            >= self.config.station_trade.min_item_volume
-                        && self
-                            .config
-                            .min_profit
-                            .map_or(true, |min_prft| x.rough_profit > min_prft)
-            })
-            .sorted_by_key(|x| to_not_nan(-x.rough_profit))
-            .take(self.config.items_take)
             .collect_vec();
+        let item_data = pick_station_trades(item_data, self.config, disable_filters);
 
         Ok(StationTradeData { item_data })
     }
 }
 
-fn retain_item_groups<T>(
+/// Part of the price of an order paid in relist fees over its `modifications`.
+/// Every modification costs the broker fee on the remaining volume, discounted
+/// by Advanced Broker Relations; the volume is assumed to stay unfilled.
+pub fn relist_fee_rate(broker_fee: f64, relist: &ConfigRelist, modifications: f64) -> f64 {
+    let discount = (0.5 - 0.06 * relist.advanced_broker_relations as f64).max(0.);
+    broker_fee * discount * modifications
+}
+
+/// Prices and amounts of an item to trade in the station, `None` when there's
+/// nothing to base the prices on.
+pub fn calculate_station_trade(
+    desc: TypeDescription,
+    market_data: MarketData,
+    competition: Competition,
     config: &CommonConfig,
-    data_service: &DatadumpService,
-    items: &mut HashMap<i32, T>,
-    descriptions: &HashMap<i32, Option<TypeDescription>>,
-) -> Result<(), anyhow::Error> {
-    let exclude_group_ids = config
-        .station_trade
-        .exclude_groups
-        .as_ref()
-        .map(|exclude_groups| data_service.get_group_ids_for_groups(exclude_groups))
-        .transpose()?;
-    log::debug!("Station trade exclude groups {exclude_group_ids:?}");
-    let include_group_ids = config
-        .station_trade
-        .include_groups
-        .as_ref()
-        .map(|include_groups| data_service.get_group_ids_for_groups(include_groups))
-        .transpose()?;
-    log::debug!("Station trade include groups {include_group_ids:?}");
-    items.retain(|k, _| {
-        let desc = if let Some(desc) = descriptions
-            .get(k)
-            .and_then(|x| x.as_ref().cloned())
-            .clone()
-        {
-            desc
+    station_config: &Station,
+) -> Option<PairCalculatedDataStationTrade> {
+    let average_history = calculate_market_averages(config, &market_data);
+
+    let buy_price =
+        if let Ok(buy_price) = calculate_buy_price(average_history, &market_data, config) {
+            buy_price
         } else {
-            log::debug!("Couldn't find description for item {k} in cache");
-            return true;
+            log::debug!("No calculate_buy_price for item {}", desc.name);
+            return None;
         };
+    log::debug!("Item {} buy price: {}", desc.type_id, buy_price);
 
-        let x = include_group_ids
-            .as_ref()
-            .map(|include_group_ids| {
-                desc.market_group_id
-                    .map(|market_group_id| include_group_ids.contains(&market_group_id))
-                    .unwrap_or(false)
-            })
-            .unwrap_or(true)
-            && exclude_group_ids
-                .as_ref()
-                .map(|exclude_group_ids| {
-                    desc.market_group_id
-                        .map(|market_group_id| !exclude_group_ids.contains(&market_group_id))
-                        .unwrap_or(true)
-                })
-                .unwrap_or(true);
-        log::debug!(
-            "Item {}, market group id {:?}, excluded: {}",
-            desc.name,
-            desc.market_group_id,
-            !x
-        );
-        x
-    });
-    Ok(())
+    let (sell_price, sell_price_basis) =
+        calculate_sell_price_with_basis(average_history, &market_data, config, buy_price, true);
+
+    let relist = &config.station_trade.relist;
+    let modifications = relist.modifications_per_order
+        + relist.modifications_per_competitor * competition.recent_orders as f64;
+    let relist_fee = relist_fee_rate(station_config.broker_fee, relist, modifications);
+
+    let buy_price_with_taxes = buy_price * (1. + station_config.broker_fee + relist_fee);
+    let sell_price_with_taxes =
+        sell_price * (1. - station_config.broker_fee - relist_fee - config.sales_tax);
+
+    let margin = (sell_price_with_taxes - buy_price_with_taxes) / buy_price_with_taxes;
+
+    let Some(expected_item_volume_per_day) = average_history.map(|x| x.volume) else {
+        log::debug!("No average_history for item {}", desc.name);
+        return None;
+    };
+
+    let mut max_buy_vol =
+        (expected_item_volume_per_day * config.station_trade.daily_volume_pct).floor() as i64;
+
+    // limit investment
+    if (buy_price_with_taxes * max_buy_vol as f64) > config.max_investment_per_item {
+        max_buy_vol = (config.max_investment_per_item / buy_price_with_taxes).floor() as i64;
+    }
+
+    let rough_profit = (sell_price_with_taxes - buy_price_with_taxes) * max_buy_vol as f64;
+
+    let src_volume_on_market = market_data.orders.iter().sell_order_volume();
+
+    let competing_volume = market_data
+        .orders
+        .iter()
+        .sell_order_volume_up_to_price(sell_price);
+    let days_to_sell = help::locked_days(
+        help::time_to_sell(
+            max_buy_vol,
+            competing_volume,
+            expected_item_volume_per_day,
+            average_history.map_or(0., |x| x.volume_std_dev),
+        )
+        .map(|x| x.p50),
+    );
+
+    Some(PairCalculatedDataStationTrade {
+        desc,
+        market: market_data,
+        margin,
+        rough_profit,
+        recommend_buy: max_buy_vol,
+        expenses: buy_price_with_taxes * max_buy_vol as f64,
+        gain_per_item: sell_price_with_taxes,
+        buy_price,
+        sell_price,
+        sell_price_basis,
+        historical_average: average_history,
+        market_volume: src_volume_on_market,
+        days_to_sell,
+        competition,
+    })
+}
+
+/// Items passing the filters, best by the objective first.
+pub fn pick_station_trades(
+    items: Vec<PairCalculatedDataStationTrade>,
+    config: &CommonConfig,
+    disable_filters: bool,
+) -> Vec<PairCalculatedDataStationTrade> {
+    items
+        .into_iter()
+        .filter(|x| {
+            disable_filters
+                || x.margin > config.margin_cutoff
+                    && x.historical_average.map(|x| x.volume).unwrap_or(0f64)
+                        >= config.station_trade.min_item_volume
+                    && config
+                        .min_profit
+                        .map_or(true, |min_prft| x.rough_profit > min_prft)
+                    && help::passes_trend_filter(&config.trend_filter, x.historical_average)
+                    && config
+                        .station_trade
+                        .competition
+                        .max_score
+                        .map_or(true, |max_score| x.competition.score() <= max_score)
+        })
+        .sorted_by_key(|x| to_not_nan(-x.objective_value(config.objective)))
+        .take(config.items_take)
+        .collect_vec()
 }
 
 #[derive(Debug, Clone)]
@@ -267,8 +281,30 @@ pub struct PairCalculatedDataStationTrade {
     pub gain_per_item: f64,
     pub buy_price: f64,
     pub sell_price: f64,
+    pub sell_price_basis: SellPriceBasis,
     pub historical_average: Option<ItemTypeAveraged>,
     pub market_volume: i64,
+    /// Expected days until the bought items are sold, see [`help::locked_days`].
+    pub days_to_sell: f64,
+    pub competition: Competition,
+}
+
+impl PairCalculatedDataStationTrade {
+    /// Profit relative to expenses earned per day of capital lock-up,
+    /// `None` when nothing is spent.
+    pub fn return_per_day(&self) -> Option<f64> {
+        if self.buy_price <= 0. {
+            return None;
+        }
+        Some(self.margin / self.days_to_sell)
+    }
+
+    fn objective_value(&self, objective: Objective) -> f64 {
+        match objective {
+            Objective::Profit => self.rough_profit,
+            Objective::ProfitPerDay => self.rough_profit / self.days_to_sell,
+        }
+    }
 }
 
 fn calculate_buy_price(
@@ -276,12 +312,13 @@ fn calculate_buy_price(
     dest_market: &MarketData,
     config: &CommonConfig,
 ) -> anyhow::Result<f64> {
+    let orders = || genuine_orders(&dest_market.orders, dst_avgs, &config.manipulation);
     let dst_highest_buy_order = if let Some(dst_avgs) = dst_avgs {
-        dest_market.orders.iter().get_highest_buy_order_over_volume(
+        orders().get_highest_buy_order_over_volume(
             dst_avgs.volume * config.station_trade.dst_ignore_orders_under_volume_pct,
         )
     } else {
-        dest_market.orders.iter().sell_order_min_price()
+        orders().sell_order_min_price()
     };
 
     Ok(match (dst_highest_buy_order, dst_avgs) {
@@ -298,6 +335,124 @@ fn calculate_buy_price(
     })
 }
 
+/// How many traders fight over the top of the order book of an item.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Competition {
+    /// Distinct orders near the best price repriced or reissued in the last `recent_hours`.
+    pub recent_orders: usize,
+    /// Times per hour the best price was undercut by a single tick.
+    pub undercuts_per_hour: f64,
+}
+
+impl Competition {
+    /// Competition for every type seen in the snapshots of a station.
+    pub fn of(snapshots: &OrderSnapshots, config: &ConfigCompetition) -> HashMap<i32, Self> {
+        Self::at(snapshots, config, Utc::now())
+    }
+
+    /// Looks at every two consecutive snapshots taken in the last `recent_hours`.
+    /// Orders near the best price that weren't in the previous snapshot, or
+    /// changed their price or `issued` since, were updated by their owner.
+    fn at(
+        snapshots: &OrderSnapshots,
+        config: &ConfigCompetition,
+        now: DateTime<Utc>,
+    ) -> HashMap<i32, Self> {
+        let recent = Duration::try_seconds((config.recent_hours * 60. * 60.) as i64).unwrap();
+        let recent_since = now - recent;
+        let mut updated = HashMap::<i32, HashSet<i64>>::new();
+        let mut undercuts = HashMap::<i32, usize>::new();
+        let mut covered = Duration::zero();
+        for (previous, next) in snapshots
+            .snapshots
+            .iter()
+            .tuple_windows()
+            .filter(|(previous, _)| previous.time >= recent_since)
+        {
+            covered = covered + (next.time - previous.time);
+            let previous_orders = previous
+                .orders
+                .iter()
+                .map(|x| (x.order_id, x))
+                .collect::<HashMap<_, _>>();
+            let previous_tops = tops_of_books(&previous.orders, config.top_of_book_pct);
+            for ((type_id, is_buy_order), top) in
+                tops_of_books(&next.orders, config.top_of_book_pct)
+            {
+                let updated = updated.entry(type_id).or_default();
+                for order in &top {
+                    let changed = previous_orders
+                        .get(&order.order_id)
+                        .map_or(true, |x| x.price != order.price || x.issued != order.issued);
+                    if changed {
+                        updated.insert(order.order_id);
+                    }
+                }
+
+                let Some(previous_best) = previous_tops
+                    .get(&(type_id, is_buy_order))
+                    .and_then(|x| x.first())
+                    .map(|x| x.price)
+                else {
+                    continue;
+                };
+                let best = top[0].price;
+                let sign = if is_buy_order { 1. } else { -1. };
+                let tick = (outbid_price(previous_best, is_buy_order) - previous_best).abs();
+                let gap = sign * (best - previous_best);
+                if gap > 0. && gap <= tick * (1. + 1e-6) {
+                    *undercuts.entry(type_id).or_default() += 1;
+                }
+            }
+        }
+
+        let hours = covered.num_seconds() as f64 / 60. / 60.;
+        updated
+            .into_iter()
+            .map(|(type_id, orders)| {
+                let undercuts = undercuts.get(&type_id).copied().unwrap_or(0);
+                let competition = Self {
+                    recent_orders: orders.len(),
+                    undercuts_per_hour: if hours > 0. {
+                        undercuts as f64 / hours
+                    } else {
+                        0.
+                    },
+                };
+                (type_id, competition)
+            })
+            .collect()
+    }
+
+    /// Recently updated orders weighted up by how often the best price is
+    /// undercut by a single tick.
+    pub fn score(&self) -> f64 {
+        self.recent_orders as f64 * (1. + self.undercuts_per_hour)
+    }
+}
+
+/// Orders of each `(type, is_buy_order)` within `pct` of the best price, best first.
+fn tops_of_books(orders: &[SnapshotOrder], pct: f64) -> HashMap<(i32, bool), Vec<&SnapshotOrder>> {
+    orders
+        .iter()
+        .into_group_map_by(|x| (x.type_id, x.is_buy_order))
+        .into_iter()
+        .map(|((type_id, is_buy_order), side)| {
+            let sign = if is_buy_order { -1. } else { 1. };
+            let side = side
+                .into_iter()
+                .sorted_by_key(|x| to_not_nan(sign * x.price))
+                .collect_vec();
+            let best = side[0].price;
+            let top = side
+                .into_iter()
+                .take_while(|x| (x.price - best).abs() <= best * pct)
+                .collect();
+            ((type_id, is_buy_order), top)
+        })
+        .collect()
+}
+
 #[derive(Debug, Clone)]
 pub struct StationTradeData {
     item_data: Vec<PairCalculatedDataStationTrade>,
@@ -305,42 +460,96 @@ pub struct StationTradeData {
 
 impl StationTradeData {
     pub fn make_table_station_trade<'b>(&self, name_length: usize) -> Vec<Row<'b>> {
-        let rows = std::iter::once(Row::new(vec![
-            TableCell::new("id"),
-            TableCell::new("itm nm"),
-            TableCell::new("buy p"),
-            TableCell::new("sell p"),
-            TableCell::new("total expenses"),
-            TableCell::new("gain per item"),
-            TableCell::new("mrgn"),
-            TableCell::new("vlm src"),
-            TableCell::new("mkt src"),
-            TableCell::new("rgh prft"),
-            TableCell::new("buy"),
-        ]))
+        let rows = std::iter::once(Row::new(
+            vec![
+                TableCell::new("id"),
+                TableCell::new("itm nm"),
+                TableCell::new("buy p"),
+                TableCell::new("sell p"),
+                TableCell::new("total expenses"),
+                TableCell::new("gain per item"),
+                TableCell::new("mrgn"),
+                TableCell::new("vlm src"),
+                TableCell::new("mkt src"),
+                TableCell::new("rgh prft"),
+                TableCell::new("roc/d"),
+                TableCell::new("cmpt"),
+                TableCell::new("rcnt"),
+                TableCell::new("undct"),
+                TableCell::new("buy"),
+            ]
+            .into_iter()
+            .chain(help::trend_header_cells())
+            .collect::<Vec<_>>(),
+        ))
         .chain(self.item_data.iter().map(|it| {
             let short_name = it.desc.name[..(name_length.min(it.desc.name.len()))].to_owned();
-            Row::new(vec![
-                TableCell::new(format!("{}", it.desc.type_id)),
-                TableCell::new(short_name),
-                TableCell::new(format!("{:.2}", it.buy_price)),
-                TableCell::new(format!("{:.2}", it.sell_price)),
-                TableCell::new(format!("{:.2}", it.expenses)),
-                TableCell::new(format!("{:.2}", it.gain_per_item)),
-                TableCell::new(format!("{:.2}", it.margin)),
-                TableCell::new(format!(
-                    "{:.2}",
-                    it.historical_average.map(|x| x.volume).unwrap_or(0f64)
-                )),
-                TableCell::new(format!("{:.2}", it.market_volume)),
-                TableCell::new(format!("{:.2}", it.rough_profit)),
-                TableCell::new(format!("{}", it.recommend_buy)),
-            ])
+            Row::new(
+                vec![
+                    TableCell::new(format!("{}", it.desc.type_id)),
+                    TableCell::new(short_name),
+                    TableCell::new(format!("{:.2}", it.buy_price)),
+                    TableCell::new(format!("{:.2}", it.sell_price)),
+                    TableCell::new(format!("{:.2}", it.expenses)),
+                    TableCell::new(format!("{:.2}", it.gain_per_item)),
+                    TableCell::new(format!("{:.2}", it.margin)),
+                    TableCell::new(format!(
+                        "{:.2}",
+                        it.historical_average.map(|x| x.volume).unwrap_or(0f64)
+                    )),
+                    TableCell::new(format!("{:.2}", it.market_volume)),
+                    TableCell::new(format!("{:.2}", it.rough_profit)),
+                    TableCell::new(
+                        it.return_per_day()
+                            .map_or("N/A".to_string(), |x| format!("{:.4}", x)),
+                    ),
+                    TableCell::new(format!("{:.2}", it.competition.score())),
+                    TableCell::new(format!("{}", it.competition.recent_orders)),
+                    TableCell::new(format!("{:.2}", it.competition.undercuts_per_hour)),
+                    TableCell::new(format!("{}", it.recommend_buy)),
+                ]
+                .into_iter()
+                .chain(help::trend_cells(it.historical_average))
+                .collect::<Vec<_>>(),
+            )
         }))
         .collect::<Vec<_>>();
         rows
     }
 
+    pub fn make_table_suspicious_orders<'b>(&self, config: &CommonConfig) -> Vec<Row<'b>> {
+        help::make_table_suspicious_orders(
+            config,
+            self.item_data
+                .iter()
+                .map(|x| ("station", x.desc.name.as_str(), &x.market)),
+        )
+    }
+
+    /// Recommended items, recorded for the backtest.
+    pub fn recommendations(&self, station: &Station, config: &CommonConfig) -> Vec<Recommendation> {
+        let time = Utc::now();
+        self.item_data
+            .iter()
+            .map(|it| Recommendation {
+                time,
+                mode: cli::STATION_TRADING.to_string(),
+                source: station.name.clone(),
+                destination: station.name.clone(),
+                type_id: it.desc.type_id,
+                name: it.desc.name.clone(),
+                quantity: it.recommend_buy,
+                buy_price: it.buy_price,
+                sell_price: it.sell_price,
+                profit: it.rough_profit,
+                knobs: vec![
+                    format!("mode={}", cli::STATION_TRADING),
+                    it.sell_price_basis.knob(config),
+                ],
+            })
+            .collect()
+    }
+
     pub fn get_buy_order_data(&self) -> impl Iterator<Item = BuyOrderData> + '_ {
         self.item_data.iter().map(|x| BuyOrderData {
             type_id: x.desc.type_id,
@@ -355,3 +564,72 @@ pub struct BuyOrderData {
     pub item_price: f64,
     pub item_quantity: i64,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        order_snapshots::OrderSnapshot,
+        test_fixtures::{snapshot_order, start, TYPE_ID},
+    };
+
+    #[test]
+    fn test_competition_of_bot_war() {
+        let now = start();
+        let snapshot = |minutes: i64, orders: Vec<SnapshotOrder>| OrderSnapshot {
+            time: now - Duration::try_minutes(minutes).unwrap(),
+            orders,
+        };
+        let snapshots = OrderSnapshots {
+            snapshots: vec![
+                // too long ago to count
+                snapshot(300, vec![snapshot_order(1, false, 100.2)]),
+                snapshot(
+                    60,
+                    vec![snapshot_order(1, false, 100.), snapshot_order(4, true, 80.)],
+                ),
+                snapshot(
+                    30,
+                    vec![
+                        snapshot_order(1, false, 100.),
+                        snapshot_order(2, false, 99.99),
+                        snapshot_order(4, true, 80.),
+                    ],
+                ),
+                snapshot(
+                    0,
+                    vec![
+                        snapshot_order(1, false, 100.),
+                        snapshot_order(2, false, 99.98),
+                        // far from the top of the book
+                        snapshot_order(3, false, 150.),
+                        snapshot_order(4, true, 80.),
+                    ],
+                ),
+            ],
+        };
+        let competition = Competition::at(&snapshots, &ConfigCompetition::default(), now);
+
+        // order 2 was listed and repriced, twice undercutting by a tick within an hour
+        assert_eq!(
+            competition[&TYPE_ID],
+            Competition {
+                recent_orders: 1,
+                undercuts_per_hour: 2.,
+            }
+        );
+        assert_eq!(competition[&TYPE_ID].score(), 3.);
+    }
+
+    #[test]
+    fn test_relist_fee_rate() {
+        let relist = ConfigRelist {
+            modifications_per_order: 4.,
+            modifications_per_competitor: 0.,
+            advanced_broker_relations: 5,
+        };
+
+        assert!((relist_fee_rate(0.02, &relist, 4.) - 0.016).abs() < 1e-9);
+        assert_eq!(relist_fee_rate(0.02, &relist, 0.), 0.);
+    }
+}
@@ -1,16 +1,50 @@
+use std::collections::BTreeMap;
+
+use chrono::{Duration, NaiveDate};
 use rust_eveonline_esi::models::GetUniverseTypesTypeIdOk;
 use serde::{Deserialize, Serialize};
 
+use crate::{
+    config::HistoryGapFill, consts::DATE_FMT, requests::service::to_not_nan, stat::MedianStat,
+};
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct MarketsRegionHistory {
     pub average: Option<f64>,
     pub date: String,
     pub highest: Option<f64>,
     pub lowest: Option<f64>,
+    #[serde(default)]
     pub order_count: i64,
+    #[serde(default)]
     pub volume: i64,
 }
 
+impl MarketsRegionHistory {
+    /// Fills in missing prices from the ones that are present:
+    /// average is the middle of highest and lowest, highest and lowest
+    /// are bounded by the other known prices. Returns `None` when
+    /// nothing is known about prices of the day.
+    pub fn impute_prices(&self) -> Option<(f64, f64, f64)> {
+        let (average, highest, lowest) = match (self.average, self.highest, self.lowest) {
+            (Some(a), Some(h), Some(l)) => (a, h, l),
+            (None, Some(h), Some(l)) => ((h + l) / 2., h, l),
+            (Some(a), None, Some(l)) => (a, a.max(l), l),
+            (Some(a), Some(h), None) => (a, h, a.min(h)),
+            (Some(x), None, None) | (None, Some(x), None) | (None, None, Some(x)) => (x, x, x),
+            (None, None, None) => return None,
+        };
+        Some((average, highest, lowest))
+    }
+}
+
+/// How many history rows of an item had to be repaired.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ImputedRows {
+    pub imputed: usize,
+    pub dropped: usize,
+}
+
 #[derive(Debug, Serialize, Deserialize, Default, Clone)]
 pub struct ItemOrders {
     pub id: i32,
@@ -23,6 +57,100 @@ pub struct ItemHistory {
     pub history: Vec<MarketsRegionHistory>,
 }
 
+impl ItemHistory {
+    /// Imputes missing prices of history rows, rows without any price are dropped.
+    pub fn impute_missing_prices(&mut self) -> ImputedRows {
+        let mut rows = ImputedRows::default();
+        self.history.retain_mut(|x| {
+            let Some((average, highest, lowest)) = x.impute_prices() else {
+                rows.dropped += 1;
+                return false;
+            };
+            if x.average.is_none() || x.highest.is_none() || x.lowest.is_none() {
+                rows.imputed += 1;
+            }
+            x.average = Some(average);
+            x.highest = Some(highest);
+            x.lowest = Some(lowest);
+            true
+        });
+        rows
+    }
+
+    /// Adds rows for days without trades in the last `window_days` up to `today`.
+    pub fn fill_gaps(&mut self, fill: HistoryGapFill, window_days: i64, today: NaiveDate) {
+        if fill == HistoryGapFill::Leave {
+            return;
+        }
+
+        let history = std::mem::take(&mut self.history);
+        let median = |get: fn(&MarketsRegionHistory) -> Option<f64>| {
+            history
+                .iter()
+                .filter_map(get)
+                .map(to_not_nan)
+                .median()
+                .map(|x| *x)
+        };
+        let medians = (
+            median(|x| x.average),
+            median(|x| x.highest),
+            median(|x| x.lowest),
+        );
+
+        let mut dates = history
+            .into_iter()
+            .filter_map(
+                |x| match NaiveDate::parse_from_str(x.date.as_str(), DATE_FMT) {
+                    Ok(date) => Some((date, x)),
+                    Err(err) => {
+                        log::warn!(
+                            "Dropping history row of type {} with date {}: {err}",
+                            self.id,
+                            x.date
+                        );
+                        None
+                    }
+                },
+            )
+            .collect::<BTreeMap<_, _>>();
+
+        let window_start = today - Duration::try_days(window_days).unwrap();
+        let mut last_prices = dates
+            .range(..window_start)
+            .next_back()
+            .map(|(_, x)| (x.average, x.highest, x.lowest));
+        for date in window_start.iter_days().take_while(|&x| x <= today) {
+            if let Some(x) = dates.get(&date) {
+                last_prices = Some((x.average, x.highest, x.lowest));
+                continue;
+            }
+
+            let prices = match fill {
+                HistoryGapFill::Leave => None,
+                HistoryGapFill::ForwardFill => last_prices,
+                HistoryGapFill::Median => Some(medians),
+            };
+            // nothing to forward fill before the first day with trades
+            let Some((average, highest, lowest)) = prices else {
+                continue;
+            };
+            dates.insert(
+                date,
+                MarketsRegionHistory {
+                    average,
+                    date: date.format(DATE_FMT).to_string(),
+                    highest,
+                    lowest,
+                    order_count: 0,
+                    volume: 0,
+                },
+            );
+        }
+        self.history = dates.into_values().collect();
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Order {
     pub duration: i32,
@@ -37,12 +165,89 @@ pub struct Order {
     pub volume_total: i64,
 }
 
+/// Entry of the character wallet journal,
+/// as returned by `/characters/{character_id}/wallet/journal/`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JournalEntry {
+    pub id: i64,
+    pub date: String,
+    pub ref_type: String,
+    /// Negative when isk left the wallet.
+    #[serde(default)]
+    pub amount: Option<f64>,
+    #[serde(default)]
+    pub context_id: Option<i64>,
+    #[serde(default)]
+    pub context_id_type: Option<String>,
+}
+
+/// Skills of the character, as returned by `/characters/{character_id}/skills/`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CharacterSkills {
+    pub skills: Vec<CharacterSkill>,
+}
+
+impl CharacterSkills {
+    /// Level the skill works at, 0 when it isn't trained.
+    pub fn level(&self, skill_id: i32) -> i32 {
+        self.skills
+            .iter()
+            .find(|x| x.skill_id == skill_id)
+            .map_or(0, |x| x.active_skill_level)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CharacterSkill {
+    pub skill_id: i32,
+    pub active_skill_level: i32,
+}
+
+/// Standing of an NPC entity towards the character,
+/// as returned by `/characters/{character_id}/standings/`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Standing {
+    pub from_id: i32,
+    /// One of `agent`, `npc_corp` or `faction`.
+    pub from_type: String,
+    pub standing: f64,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct StationOwner {
+    pub corporation_id: i32,
+    pub faction_id: Option<i32>,
+}
+
+/// Order of the character, as returned by `/characters/{character_id}/orders/`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CharacterOrder {
+    pub order_id: i64,
+    pub type_id: i32,
+    pub location_id: i64,
+    /// Missing for sell orders.
+    #[serde(default)]
+    pub is_buy_order: bool,
+    pub price: f64,
+    pub volume_remain: i64,
+    pub volume_total: i64,
+    pub issued: String,
+}
+
 #[derive(Debug, Serialize, Deserialize, Default, Copy, Clone)]
 pub struct ItemTypeAveraged {
     pub average: f64,
     pub low_average: f64,
     pub high_average: f64,
     pub volume: f64,
+    /// Standard deviation of the daily traded volume.
+    pub volume_std_dev: f64,
+    /// Change of the average price per week relative to the average price.
+    pub price_trend: f64,
+    /// Standard deviation of the daily average price relative to the average price.
+    pub volatility: f64,
+    /// Change of the traded volume per week relative to the average volume.
+    pub volume_trend: f64,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -58,6 +263,9 @@ pub struct ItemHistoryDay {
 pub struct MarketData {
     pub history: Vec<ItemHistoryDay>,
     pub orders: Vec<Order>,
+    /// Daily volume traded at the station itself, observed from order snapshots.
+    #[serde(default)]
+    pub station_volume: Option<f64>,
 }
 
 impl MarketData {
@@ -75,8 +283,14 @@ impl MarketData {
                 })
                 .collect(),
             orders: orders.orders,
+            station_volume: None,
         }
     }
+
+    pub fn with_station_volume(mut self, station_volume: Option<f64>) -> Self {
+        self.station_volume = station_volume;
+        self
+    }
 }
 
 #[derive(Debug, Clone)]
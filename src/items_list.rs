@@ -1,4 +1,4 @@
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 
 use chrono::Duration;
 
@@ -9,16 +9,17 @@ use term_table::row::Row;
 use crate::{
     cached_data::CachedStuff,
     config::Config,
-    datadump_service::DatadumpService,
+    datadump_service::{DatadumpService, GroupFilter},
     good_items::{
-        sell_buy::{get_good_items_sell_buy, make_table_sell_buy},
-        sell_sell::{get_good_items_sell_sell, make_table_sell_sell},
+        backtest::Recommendation,
+        sell_buy::{get_good_items_sell_buy, make_table_sell_buy, recommendations_sell_buy},
+        sell_sell::{get_good_items_sell_sell, make_table_sell_sell, recommendations_sell_sell},
     },
     helper_ext::HashMapJoin,
     item_type::{ItemHistory, ItemOrders, MarketData, SystemMarketsItem, SystemMarketsItemData},
     load_create::{
         create_load_all_types, create_load_item_descriptions, create_load_prices,
-        load_or_create_history, load_or_create_orders,
+        load_or_create_history, load_or_create_orders, load_station_volumes,
     },
     requests::{
         item_history::ItemHistoryEsiService,
@@ -31,19 +32,17 @@ use crate::{
 };
 
 pub async fn compute_sell_sell<'a>(
-    mut pairs: Vec<SystemMarketsItemData>,
+    pairs: Vec<SystemMarketsItemData>,
     config: &Config,
     disable_filters: bool,
     simple_list: &mut Vec<SimpleDisplay>,
+    recommendations: &mut Vec<Recommendation>,
     name_len: usize,
-    cache: CachedStuff,
+    cache: &mut CachedStuff,
     force_no_refresh: bool,
     esi_requests: EsiRequestsService<'a>,
     esi_config: &Configuration,
-    data_service: DatadumpService,
 ) -> anyhow::Result<Vec<Row<'a>>> {
-    retain_item_groups(config, data_service, &mut pairs)?;
-
     let kms =
         get_zkb_frequencies(config, cache, force_no_refresh, esi_requests, esi_config).await?;
 
@@ -57,63 +56,13 @@ pub async fn compute_sell_sell<'a>(
             sell_price: x.item.dest_min_sell_price,
         })
         .collect();
+    *recommendations = recommendations_sell_sell(&good_items, config);
     Ok(make_table_sell_sell(&good_items, name_len))
 }
 
-fn retain_item_groups(
-    config: &Config,
-    data_service: DatadumpService,
-    pairs: &mut Vec<SystemMarketsItemData>,
-) -> Result<(), anyhow::Error> {
-    let exclude_group_ids = config
-        .common
-        .sell_sell
-        .exclude_groups
-        .as_ref()
-        .map(|exclude_groups| data_service.get_group_ids_for_groups(exclude_groups))
-        .transpose()?;
-    log::debug!("Sell sell exclude groups {exclude_group_ids:?}");
-    let include_group_ids = config
-        .common
-        .sell_sell
-        .include_groups
-        .as_ref()
-        .map(|include_groups| data_service.get_group_ids_for_groups(include_groups))
-        .transpose()?;
-    log::debug!("Sell sell include groups {include_group_ids:?}");
-    pairs.retain_mut(|pair| {
-        let x = include_group_ids
-            .as_ref()
-            .map(|include_group_ids| {
-                pair.desc
-                    .market_group_id
-                    .map(|market_group_id| include_group_ids.contains(&market_group_id))
-                    .unwrap_or(false)
-            })
-            .unwrap_or(true)
-            && exclude_group_ids
-                .as_ref()
-                .map(|exclude_group_ids| {
-                    pair.desc
-                        .market_group_id
-                        .map(|market_group_id| !exclude_group_ids.contains(&market_group_id))
-                        .unwrap_or(true)
-                })
-                .unwrap_or(true);
-        log::debug!(
-            "Item {}, market group id {:?}, excluded: {}",
-            pair.desc.name,
-            pair.desc.market_group_id,
-            !x
-        );
-        x
-    });
-    Ok(())
-}
-
 async fn get_zkb_frequencies(
     config: &Config,
-    mut cache: CachedStuff,
+    cache: &mut CachedStuff,
     force_no_refresh: bool,
     esi_requests: EsiRequestsService<'_>,
     esi_config: &Configuration,
@@ -128,7 +77,7 @@ async fn get_zkb_frequencies(
     let client = &esi_config.client;
     let config = &config;
 
-    let zkb = ZkbRequestsService::new(client);
+    let zkb = ZkbRequestsService::new(client, esi_requests.policy);
     let km_service = KillmailService::new(&zkb, esi_requests);
 
     let killmails = cache
@@ -144,10 +93,12 @@ async fn get_zkb_frequencies(
                 let km_service = &km_service;
 
                 async move {
+                    let pages = config.common.sell_sell.sell_sell_zkb.zkb_download_pages;
+                    let progress = KillmailService::progress(pages);
                     let mut kills = Vec::new();
-                    for page in 1..=config.common.sell_sell.sell_sell_zkb.zkb_download_pages {
+                    for page in 1..=pages {
                         let mut kills_page = km_service
-                            .get_killmails(&config.common.zkill_entity, page)
+                            .get_killmails(&config.common.zkill_entity, page, &progress)
                             .await?;
 
                         let kills_page_ids_hashset =
@@ -166,6 +117,7 @@ async fn get_zkb_frequencies(
 
                         kills.append(&mut kills_page);
                     }
+                    progress.finish();
 
                     kills.append(&mut previous.unwrap_or_default());
 
@@ -187,6 +139,7 @@ pub fn compute_sell_buy<'a>(
     config: &Config,
     disable_filters: bool,
     simple_list: &mut Vec<SimpleDisplay>,
+    recommendations: &mut Vec<Recommendation>,
     name_len: usize,
 ) -> anyhow::Result<Vec<Row<'a>>> {
     let good_items = get_good_items_sell_buy(pairs, config, disable_filters)?;
@@ -199,6 +152,7 @@ pub fn compute_sell_buy<'a>(
             sell_price: x.item.dest_min_sell_price,
         })
         .collect();
+    *recommendations = recommendations_sell_buy(&good_items, config);
     Ok(make_table_sell_buy(&good_items, name_len))
 }
 
@@ -209,6 +163,7 @@ pub async fn compute_pairs<'a>(
     character_id: i32,
     cache: &mut CachedStuff,
     data_service: &DatadumpService,
+    group_filter: &GroupFilter,
 ) -> anyhow::Result<Vec<SystemMarketsItemData>> {
     let source_region = esi_requests
         .find_region_id_station(&config.route.source.clone(), character_id)
@@ -223,38 +178,59 @@ pub async fn compute_pairs<'a>(
         create_load_item_descriptions(cache, &all_types, esi_requests).await?;
     let all_type_prices = create_load_prices(cache, esi_requests).await?;
 
+    let source_item_orders =
+        load_or_create_orders(cache, &config.common, esi_requests, source_region).await?;
+
+    let dest_item_orders =
+        load_or_create_orders(cache, &config.common, esi_requests, dest_region).await?;
+
+    let common_group_filter =
+        GroupFilter::new(data_service, config.common.include_groups.as_ref(), None)?;
+    // history is the slowest to download, so only get it for types that can end up in the table
+    let relevant_types = all_types
+        .iter()
+        .copied()
+        .filter(|id| {
+            let has_orders = |orders: &HashMap<i32, ItemOrders>| {
+                orders.get(id).map_or(false, |x| !x.orders.is_empty())
+            };
+            has_orders(&source_item_orders) || has_orders(&dest_item_orders)
+        })
+        .filter(|id| {
+            all_type_descriptions
+                .get(id)
+                .and_then(|x| x.as_ref())
+                .map_or(false, |desc| {
+                    common_group_filter.matches(desc.market_group_id)
+                        && group_filter.matches(desc.market_group_id)
+                })
+        })
+        .collect::<Vec<_>>();
+    log::info!(
+        "Loading history for {} of {} types",
+        relevant_types.len(),
+        all_types.len()
+    );
+
     let source_item_history = load_or_create_history(
         cache,
         source_region,
-        Duration::try_hours(config.common.item_history_timeout_hours).unwrap(),
+        &config.common,
         esi_history,
-        &all_types,
+        &relevant_types,
     )
     .await?;
     let dest_item_history = load_or_create_history(
         cache,
         dest_region,
-        Duration::try_hours(config.common.item_history_timeout_hours).unwrap(),
+        &config.common,
         esi_history,
-        &all_types,
-    )
-    .await?;
-
-    let source_item_orders = load_or_create_orders(
-        cache,
-        Duration::try_seconds((config.common.refresh_timeout_hours * 60. * 60.) as i64).unwrap(),
-        esi_requests,
-        source_region,
+        &relevant_types,
     )
     .await?;
 
-    let dest_item_orders = load_or_create_orders(
-        cache,
-        Duration::try_seconds((config.common.refresh_timeout_hours * 60. * 60.) as i64).unwrap(),
-        esi_requests,
-        dest_region,
-    )
-    .await?;
+    let source_volumes = load_station_volumes(cache, &config.common, source_region).await?;
+    let dest_volumes = load_station_volumes(cache, &config.common, dest_region).await?;
 
     let source_items = source_item_orders.outer_join(source_item_history);
     let dest_items = dest_item_orders.outer_join(dest_item_history);
@@ -276,29 +252,12 @@ pub async fn compute_pairs<'a>(
             };
             Some(SystemMarketsItem {
                 id: k,
-                source: MarketData::new(source.0, source.1),
-                destination: MarketData::new(dest.0, dest.1),
+                source: MarketData::new(source.0, source.1)
+                    .with_station_volume(source_volumes.get(&k).copied()),
+                destination: MarketData::new(dest.0, dest.1)
+                    .with_station_volume(dest_volumes.get(&k).copied()),
             })
         });
-    let group_ids = config
-        .common
-        .include_groups
-        .as_ref()
-        .map(|x| -> anyhow::Result<Vec<i32>> {
-            let groups = x
-                .iter()
-                .map(|name| {
-                    let children = data_service.get_all_group_id_with_root_name(name.as_str())?;
-
-                    Ok(children)
-                })
-                .collect::<anyhow::Result<Vec<_>>>()?
-                .into_iter()
-                .flatten()
-                .collect::<Vec<_>>();
-            Ok(groups)
-        })
-        .transpose()?;
     Ok(pairs
         .filter_map(|it| {
             let req_res = all_type_descriptions
@@ -315,14 +274,10 @@ pub async fn compute_pairs<'a>(
             };
 
             // include only specific groups
-            if let Some(ids) = &group_ids {
-                if !req_res
-                    .market_group_id
-                    .map(|x| ids.contains(&x))
-                    .unwrap_or(false)
-                {
-                    return None;
-                }
+            if !common_group_filter.matches(req_res.market_group_id)
+                || !group_filter.matches(req_res.market_group_id)
+            {
+                return None;
             }
 
             Some(SystemMarketsItemData {
@@ -12,9 +12,13 @@ pub mod items_list;
 pub mod load_create;
 pub mod logger;
 pub mod order_ext;
+pub mod order_snapshots;
+pub mod progress;
 pub mod requests;
 pub mod stat;
 pub mod system_interaction;
+#[cfg(test)]
+pub mod test_fixtures;
 pub mod zkb;
 
 use serde::{Deserialize, Serialize};
@@ -1,36 +1,99 @@
 use std::collections::HashMap;
 
-use chrono::Duration;
+use anyhow::anyhow;
+use chrono::{Duration, Utc};
 use futures::{stream, StreamExt};
+use rust_eveonline_esi::models::GetCharactersCharacterIdWalletTransactions200Ok;
 use tokio::join;
 
 use crate::{
-    cached_data::CachedStuff,
-    consts::{BUFFER_UNORDERED, CACHE_ALL_TYPES, CACHE_ALL_TYPE_DESC, CACHE_ALL_TYPE_PRICES},
+    cached_data::{CachedStuff, Expiring},
+    config::CommonConfig,
+    consts::{
+        BUFFER_UNORDERED, CACHE_ALL_TYPES, CACHE_ALL_TYPE_DESC, CACHE_ALL_TYPE_PRICES,
+        CACHE_RECOMMENDATIONS,
+    },
     error,
-    item_type::{ItemHistory, ItemOrders, TypeDescription},
-    requests::{item_history::ItemHistoryEsiService, service::EsiRequestsService},
+    good_items::backtest::{merge_recommendations, Recommendation},
+    item_type::{ImputedRows, ItemHistory, ItemOrders, TypeDescription},
+    order_snapshots::{OrderBookDiff, OrderSnapshot, OrderSnapshots},
+    progress::Progress,
+    requests::{
+        item_history::ItemHistoryEsiService, service::EsiRequestsService,
+        transactions::WalletEsiService,
+    },
     StationIdData,
 };
 
 pub async fn load_or_create_history(
     cache: &mut CachedStuff,
     region: StationIdData,
-    duration: Duration,
+    config: &CommonConfig,
     esi_history: &ItemHistoryEsiService<'_>,
-    all_types: &[i32],
+    types: &[i32],
+) -> anyhow::Result<HashMap<i32, ItemHistory>> {
+    let duration = Duration::try_hours(config.item_history_timeout_hours).unwrap();
+    let path = format!("{}-history.rmp", region.region_id);
+    let checkpoint_path = format!("{}-history.partial.rmp", region.region_id);
+    let checkpoint = cache.checkpoint(&checkpoint_path, duration);
+    let mut item_history = cache
+        .load_or_create_expiring_async(&path, vec![CACHE_ALL_TYPES], Some(duration), |_| async {
+            Ok(esi_history
+                .all_item_history(types, region.region_id, checkpoint)
+                .await)
+        })
+        .await?
+        .into_iter()
+        .map(|x| (x.id, x))
+        .collect::<HashMap<_, _>>();
+
+    // cached history may have been downloaded for a different set of types
+    let missing = types
+        .iter()
+        .copied()
+        .filter(|x| !item_history.contains_key(x))
+        .collect::<Vec<_>>();
+    if !missing.is_empty() {
+        log::info!(
+            "History of {} types is missing in {path}, downloading...",
+            missing.len()
+        );
+        let checkpoint = cache.checkpoint(&checkpoint_path, duration);
+        let downloaded = esi_history
+            .all_item_history(&missing, region.region_id, checkpoint)
+            .await;
+        item_history.extend(downloaded.data.into_iter().map(|x| (x.id, x)));
+
+        // types that failed again are retried next run anyway, don't expire everything else
+        let expires = downloaded.expires.filter(|&x| x > Utc::now());
+        item_history = cache
+            .update_expiring(
+                &path,
+                Expiring::new(item_history.into_values().collect::<Vec<_>>(), expires),
+            )?
+            .into_iter()
+            .map(|x| (x.id, x))
+            .collect();
+    }
+
+    repair_history(&mut item_history, region, config);
+    Ok(item_history)
+}
+
+/// History of all types cached for the region by earlier runs, nothing is downloaded.
+/// It's left as downloaded, without imputed prices or filled gaps.
+pub async fn load_cached_history(
+    cache: &mut CachedStuff,
+    region: StationIdData,
 ) -> anyhow::Result<HashMap<i32, ItemHistory>> {
+    let path = format!("{}-history.rmp", region.region_id);
     let item_history = cache
-        .load_or_create_async(
-            format!("{}-history.rmp", region.region_id),
-            vec![CACHE_ALL_TYPES],
-            Some(duration),
-            |_| async {
-                Ok(esi_history
-                    .all_item_history(all_types, region.region_id)
-                    .await?)
-            },
-        )
+        .load_or_create_expiring_async(&path, vec![], None, |_: Option<Vec<ItemHistory>>| async {
+            Err(anyhow!(
+                "No history of region {} is cached, run a trading mode there first",
+                region.region_id
+            ))
+        })
         .await?
         .into_iter()
         .map(|x| (x.id, x))
@@ -38,27 +101,232 @@ pub async fn load_or_create_history(
     Ok(item_history)
 }
 
+fn repair_history(
+    item_history: &mut HashMap<i32, ItemHistory>,
+    region: StationIdData,
+    config: &CommonConfig,
+) {
+    // cache keeps history as downloaded so that the strategy can be changed at any time
+    let today = Utc::now().date_naive();
+    let mut repaired_items = 0;
+    for history in item_history.values_mut() {
+        let rows = history.impute_missing_prices();
+        if rows != ImputedRows::default() {
+            repaired_items += 1;
+            log::info!(
+                "History of type {} in region {}: {} rows imputed, {} rows without prices dropped",
+                history.id,
+                region.region_id,
+                rows.imputed,
+                rows.dropped
+            );
+        }
+        history.fill_gaps(config.history_gap_fill, config.history_window_days, today);
+    }
+    if repaired_items > 0 {
+        log::warn!(
+            "History of {repaired_items} types in region {} had rows with missing prices",
+            region.region_id
+        );
+    }
+}
+
 pub async fn load_or_create_orders(
     cache: &mut CachedStuff,
-    duration: Duration,
+    config: &CommonConfig,
     esi_requests: &EsiRequestsService<'_>,
     source_region: StationIdData,
 ) -> anyhow::Result<HashMap<i32, ItemOrders>> {
+    let duration =
+        Duration::try_seconds((config.refresh_timeout_hours * 60. * 60.) as i64).unwrap();
+    let mut downloaded = false;
     let source_item_orders = cache
-        .load_or_create_async(
+        .load_or_create_expiring_async(
             format!(
                 "{}-{}-orders.rmp",
                 source_region.station_id.id, source_region.region_id
             ),
             vec![CACHE_ALL_TYPES],
             Some(duration),
-            |_| async { Ok(esi_requests.all_item_orders(source_region).await?) },
+            |_| {
+                let downloaded = &mut downloaded;
+                async move {
+                    let orders = esi_requests.all_item_orders(source_region).await?;
+                    *downloaded = true;
+                    Ok(orders)
+                }
+            },
         )
-        .await?
+        .await?;
+
+    if downloaded {
+        record_order_snapshot(cache, config, source_region, &source_item_orders).await?;
+    }
+
+    Ok(source_item_orders
         .into_iter()
         .map(|x| (x.id, x))
-        .collect::<HashMap<_, _>>();
-    Ok(source_item_orders)
+        .collect::<HashMap<_, _>>())
+}
+
+fn order_snapshots_path(station: StationIdData) -> String {
+    format!(
+        "{}-{}-order-snapshots.rmp",
+        station.station_id.id, station.region_id
+    )
+}
+
+/// Snapshots of the orders of the station recorded so far.
+pub async fn load_order_snapshots(
+    cache: &mut CachedStuff,
+    station: StationIdData,
+) -> anyhow::Result<OrderSnapshots> {
+    cache
+        .load_or_create_async(order_snapshots_path(station), vec![], None, |_| async {
+            Ok(OrderSnapshots::default())
+        })
+        .await
+}
+
+/// Daily volume of each type traded at the station, see [`OrderSnapshots::station_volumes`].
+pub async fn load_station_volumes(
+    cache: &mut CachedStuff,
+    config: &CommonConfig,
+    station: StationIdData,
+) -> anyhow::Result<HashMap<i32, f64>> {
+    let snapshots = load_order_snapshots(cache, station).await?;
+    Ok(station_volumes(config, station, &snapshots))
+}
+
+pub fn station_volumes(
+    config: &CommonConfig,
+    station: StationIdData,
+    snapshots: &OrderSnapshots,
+) -> HashMap<i32, f64> {
+    let volumes = snapshots.station_volumes(&config.station_volume);
+    if !volumes.is_empty() {
+        log::info!(
+            "Using volume of {} types observed at station {} over {} snapshots",
+            volumes.len(),
+            station.station_id.id,
+            snapshots.snapshots.len()
+        );
+    }
+    volumes
+}
+
+/// Adds freshly downloaded orders to the rolling snapshots of the station.
+async fn record_order_snapshot(
+    cache: &mut CachedStuff,
+    config: &CommonConfig,
+    station: StationIdData,
+    orders: &[ItemOrders],
+) -> anyhow::Result<()> {
+    let snapshot = OrderSnapshot::new(Utc::now(), station.station_id.id, orders);
+    let snapshots = cache
+        .load_or_create_async(
+            order_snapshots_path(station),
+            vec![],
+            // always append to the previous snapshots
+            Some(Duration::zero()),
+            |previous: Option<OrderSnapshots>| async move {
+                let mut snapshots = previous.unwrap_or_default();
+                snapshots.push(snapshot, config.order_snapshots_keep);
+                Ok(snapshots)
+            },
+        )
+        .await?;
+
+    if let [.., previous, latest] = snapshots.snapshots.as_slice() {
+        let mut total = OrderBookDiff::default();
+        for diff in previous.diff(latest).into_values() {
+            total += diff;
+        }
+        log::info!(
+            "Station {} since {}: {} orders filled, {} cancelled, {} repriced, {} new, \
+            {} items traded",
+            station.station_id.id,
+            previous.time,
+            total.filled,
+            total.cancelled,
+            total.repriced,
+            total.new,
+            total.traded_volume()
+        );
+    }
+    Ok(())
+}
+
+/// Recommendations of all previous runs, oldest first.
+pub async fn load_recommendations(cache: &mut CachedStuff) -> anyhow::Result<Vec<Recommendation>> {
+    let recommendations = cache
+        .load_or_create_json_async(CACHE_RECOMMENDATIONS, vec![], None, |_| async {
+            Ok(Vec::new())
+        })
+        .await?;
+    Ok(recommendations)
+}
+
+/// Adds recommendations of this run to the ones kept for backtesting,
+/// see [`merge_recommendations`].
+pub async fn record_recommendations(
+    cache: &mut CachedStuff,
+    config: &CommonConfig,
+    recommendations: Vec<Recommendation>,
+) -> anyhow::Result<()> {
+    if recommendations.is_empty() {
+        return Ok(());
+    }
+    let count = recommendations.len();
+    let keep_since = Utc::now() - Duration::try_days(config.backtest.keep_days).unwrap();
+    let all: Vec<Recommendation> = cache
+        .load_or_create_json_async(
+            CACHE_RECOMMENDATIONS,
+            vec![],
+            // always append to the previous recommendations
+            Some(Duration::zero()),
+            |previous: Option<Vec<Recommendation>>| async move {
+                Ok(merge_recommendations(
+                    previous.unwrap_or_default(),
+                    recommendations,
+                    keep_since,
+                ))
+            },
+        )
+        .await?;
+    log::info!("Recorded {count} recommendations, {} in total", all.len());
+    Ok(())
+}
+
+/// Wallet transactions of the character, newest first. ESI only returns recent ones,
+/// so every download is added to the ones cached before.
+pub async fn load_or_create_transactions(
+    cache: &mut CachedStuff,
+    wallet_esi_service: &WalletEsiService<'_>,
+    character_id: i32,
+) -> anyhow::Result<Vec<GetCharactersCharacterIdWalletTransactions200Ok>> {
+    let transactions = cache
+        .load_or_create_async(
+            format!("wallet-history-{character_id}.rmp"),
+            vec![],
+            Some(Duration::try_seconds(10 * 60).unwrap()),
+            |mut previous| async move {
+                let mut new_transactions = wallet_esi_service
+                    .get_transactions_history(character_id)
+                    .await?;
+
+                if let Some(ref mut previous) = previous {
+                    new_transactions.append(previous)
+                }
+
+                new_transactions.sort_unstable_by_key(|x| -x.transaction_id);
+                new_transactions.dedup_by_key(|x| x.transaction_id);
+
+                Ok(new_transactions)
+            },
+        )
+        .await?;
+    Ok(transactions)
 }
 
 pub async fn create_load_all_types(
@@ -99,18 +367,27 @@ pub async fn create_load_item_descriptions(
             vec![CACHE_ALL_TYPES],
             Some(Duration::try_days(7).unwrap()),
             |_| async {
+                let progress = Progress::new("Item descriptions", all_types.len());
                 let res = stream::iter(all_types.iter().copied())
                     .map(|id| {
                         let esi_requests = &esi_requests;
+                        let progress = &progress;
                         async move {
-                            let req_res = esi_requests.get_item_description(id).await?;
+                            let req_res = esi_requests.get_item_description(id).await;
+                            match req_res {
+                                Ok(Some(_)) => progress.inc(),
+                                _ => progress.inc_failed(),
+                            }
 
-                            Ok((id, req_res.map(|x| x.into())))
+                            Ok((id, req_res?.map(|x| x.into())))
                         }
                     })
                     .buffer_unordered(BUFFER_UNORDERED)
                     .collect::<Vec<error::Result<_>>>()
-                    .await
+                    .await;
+                progress.finish();
+
+                let res = res
                     .into_iter()
                     .collect::<error::Result<Vec<_>>>()?
                     .into_iter()
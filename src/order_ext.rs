@@ -1,9 +1,14 @@
-use std::ops::Deref;
+use std::{fmt::Display, ops::Deref};
 
+use chrono::{DateTime, Utc};
 use itertools::Itertools;
 use ordered_float::NotNan;
 
-use crate::{item_type::Order, requests::service::to_not_nan};
+use crate::{
+    config::ConfigManipulation,
+    item_type::{ItemTypeAveraged, Order},
+    requests::service::to_not_nan,
+};
 
 pub trait OrderIterExt<'a, It>
 where
@@ -11,6 +16,7 @@ where
 {
     fn sell_order_volume(self) -> i64;
     fn sell_order_min_price(self) -> Option<f64>;
+    fn sell_order_volume_up_to_price(self, price: f64) -> i64;
     fn get_lowest_sell_order_over_volume(self, volume: f64) -> Option<f64>;
     fn get_highest_buy_order_over_volume(self, volume: f64) -> Option<f64>;
 }
@@ -33,6 +39,12 @@ where
             .map(|x| *x)
     }
 
+    fn sell_order_volume_up_to_price(self, price: f64) -> i64 {
+        self.filter(|x| !x.is_buy_order && x.price <= price)
+            .map(|x| x.volume_remain)
+            .sum()
+    }
+
     fn get_lowest_sell_order_over_volume(self, volume: f64) -> Option<f64> {
         let mut accumulated_volume = 0_f64;
 
@@ -66,3 +78,152 @@ where
             })
     }
 }
+
+/// Why an order looks like bait rather than a genuine offer.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SuspiciousOrder {
+    /// Buy order far above or sell order far below the history price bands,
+    /// `deviation` is relative to the band.
+    FarFromHistory { deviation: f64 },
+    /// Fresh order outside the history price bands with a volume that doesn't fit the market.
+    FreshOddVolume { age_hours: f64 },
+}
+
+impl Display for SuspiciousOrder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SuspiciousOrder::FarFromHistory { deviation } => {
+                write!(f, "{:.0}% beyond history price band", deviation * 100.)
+            }
+            SuspiciousOrder::FreshOddVolume { age_hours } => {
+                write!(
+                    f,
+                    "issued {age_hours:.1}h ago with an odd volume outside price band"
+                )
+            }
+        }
+    }
+}
+
+/// Flags orders that would mislead pricing: buy orders placed above what the
+/// item ever traded at or sell orders below it.
+pub fn detect_suspicious_order(
+    order: &Order,
+    avgs: &ItemTypeAveraged,
+    config: &ConfigManipulation,
+    now: DateTime<Utc>,
+) -> Option<SuspiciousOrder> {
+    // how far the order is on the side of the band that benefits the other party
+    let deviation = if order.is_buy_order {
+        (order.price - avgs.high_average) / avgs.high_average
+    } else {
+        (avgs.low_average - order.price) / avgs.low_average
+    };
+    if deviation.is_nan() || deviation <= 0. {
+        return None;
+    }
+    if deviation > config.max_history_deviation {
+        return Some(SuspiciousOrder::FarFromHistory { deviation });
+    }
+
+    let age_hours = DateTime::parse_from_rfc3339(&order.issued)
+        .ok()
+        .map(|issued| (now - issued.with_timezone(&Utc)).num_minutes() as f64 / 60.)?;
+    let odd_volume =
+        order.volume_total == 1 || order.volume_total as f64 > avgs.volume * config.odd_volume_days;
+    if age_hours < config.fresh_order_hours && odd_volume {
+        return Some(SuspiciousOrder::FreshOddVolume { age_hours });
+    }
+    None
+}
+
+/// Orders that [`detect_suspicious_order`] flags, all orders pass without history.
+pub fn suspicious_orders<'a>(
+    orders: &'a [Order],
+    avgs: Option<ItemTypeAveraged>,
+    config: &ConfigManipulation,
+) -> Vec<(&'a Order, SuspiciousOrder)> {
+    let Some(avgs) = avgs else {
+        return Vec::new();
+    };
+    let now = Utc::now();
+    orders
+        .iter()
+        .filter_map(|x| detect_suspicious_order(x, &avgs, config, now).map(|reason| (x, reason)))
+        .collect()
+}
+
+/// Orders without the ones flagged by [`detect_suspicious_order`].
+pub fn genuine_orders<'a>(
+    orders: &'a [Order],
+    avgs: Option<ItemTypeAveraged>,
+    config: &'a ConfigManipulation,
+) -> impl Iterator<Item = &'a Order> + 'a {
+    let now = Utc::now();
+    orders.iter().filter(move |x| {
+        avgs.map_or(true, |avgs| {
+            detect_suspicious_order(x, &avgs, config, now).is_none()
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_fixtures;
+
+    fn order(is_buy_order: bool, price: f64, volume_total: i64, issued: DateTime<Utc>) -> Order {
+        Order {
+            issued: issued.to_rfc3339(),
+            volume_remain: volume_total,
+            volume_total,
+            ..test_fixtures::order(1, is_buy_order, price)
+        }
+    }
+
+    fn avgs() -> ItemTypeAveraged {
+        ItemTypeAveraged {
+            average: 100.,
+            low_average: 95.,
+            high_average: 105.,
+            volume: 100.,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_bait_buy_order_far_above_history() {
+        let now = Utc::now();
+        let config = ConfigManipulation::default();
+        let bait = order(true, 300., 10, now - chrono::Duration::try_days(3).unwrap());
+        let genuine = order(true, 104., 10, now);
+
+        assert!(matches!(
+            detect_suspicious_order(&bait, &avgs(), &config, now),
+            Some(SuspiciousOrder::FarFromHistory { .. })
+        ));
+        assert_eq!(
+            detect_suspicious_order(&genuine, &avgs(), &config, now),
+            None
+        );
+    }
+
+    #[test]
+    fn test_fresh_sell_order_with_odd_volume() {
+        let now = Utc::now();
+        let config = ConfigManipulation::default();
+        let fresh = order(
+            false,
+            80.,
+            1,
+            now - chrono::Duration::try_minutes(10).unwrap(),
+        );
+        let old = order(false, 80., 1, now - chrono::Duration::try_days(2).unwrap());
+
+        assert!(matches!(
+            detect_suspicious_order(&fresh, &avgs(), &config, now),
+            Some(SuspiciousOrder::FreshOddVolume { .. })
+        ));
+        assert_eq!(detect_suspicious_order(&old, &avgs(), &config, now), None);
+    }
+}
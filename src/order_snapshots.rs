@@ -0,0 +1,337 @@
+use std::collections::{HashMap, HashSet};
+
+use chrono::{DateTime, Duration, Utc};
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::ConfigStationVolume,
+    item_type::{ItemOrders, Order},
+};
+
+/// Orders of a station at the time they were downloaded.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OrderSnapshot {
+    pub time: DateTime<Utc>,
+    pub orders: Vec<SnapshotOrder>,
+}
+
+/// Fields of an [`Order`] needed to tell what happened to it between snapshots.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SnapshotOrder {
+    pub order_id: i64,
+    pub type_id: i32,
+    pub is_buy_order: bool,
+    pub price: f64,
+    pub volume_remain: i64,
+    pub issued: String,
+    pub duration: i32,
+}
+
+impl From<&Order> for SnapshotOrder {
+    fn from(value: &Order) -> Self {
+        Self {
+            order_id: value.order_id,
+            type_id: value.type_id,
+            is_buy_order: value.is_buy_order,
+            price: value.price,
+            volume_remain: value.volume_remain,
+            issued: value.issued.clone(),
+            duration: value.duration,
+        }
+    }
+}
+
+/// What happened to the orders of a type between two snapshots.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct OrderBookDiff {
+    /// Volume taken from sell orders, i.e. bought by someone.
+    pub sold_volume: i64,
+    /// Volume taken from buy orders, i.e. sold by someone.
+    pub bought_volume: i64,
+    /// Orders that disappeared because they were filled.
+    pub filled: usize,
+    /// Orders that disappeared without being filled, cancelled or expired.
+    pub cancelled: usize,
+    pub repriced: usize,
+    pub new: usize,
+}
+
+impl OrderBookDiff {
+    pub fn traded_volume(&self) -> i64 {
+        self.sold_volume + self.bought_volume
+    }
+
+    fn add_traded(&mut self, order: &SnapshotOrder, volume: i64) {
+        if order.is_buy_order {
+            self.bought_volume += volume;
+        } else {
+            self.sold_volume += volume;
+        }
+    }
+}
+
+impl std::ops::AddAssign for OrderBookDiff {
+    fn add_assign(&mut self, rhs: Self) {
+        self.sold_volume += rhs.sold_volume;
+        self.bought_volume += rhs.bought_volume;
+        self.filled += rhs.filled;
+        self.cancelled += rhs.cancelled;
+        self.repriced += rhs.repriced;
+        self.new += rhs.new;
+    }
+}
+
+impl OrderSnapshot {
+    /// Keeps only the orders located in the station, buy orders of other
+    /// systems reaching it are traded elsewhere.
+    pub fn new(time: DateTime<Utc>, station_id: i64, orders: &[ItemOrders]) -> Self {
+        Self {
+            time,
+            orders: orders
+                .iter()
+                .flat_map(|x| x.orders.iter())
+                .filter(|x| x.location_id == station_id)
+                .map(SnapshotOrder::from)
+                .collect(),
+        }
+    }
+
+    /// Changes from `self` to a later snapshot `next`, per type.
+    ///
+    /// ESI doesn't say why an order disappeared. One that was the best order
+    /// of its side and didn't run out of time is counted as filled, any other
+    /// as cancelled.
+    pub fn diff(&self, next: &OrderSnapshot) -> HashMap<i32, OrderBookDiff> {
+        let next_orders = next
+            .orders
+            .iter()
+            .map(|x| (x.order_id, x))
+            .collect::<HashMap<_, _>>();
+        let best_prices = best_prices(&self.orders);
+
+        let mut diffs = HashMap::<i32, OrderBookDiff>::new();
+        for order in &self.orders {
+            let diff = diffs.entry(order.type_id).or_default();
+            match next_orders.get(&order.order_id) {
+                // a modified order may as well have been relisted, only trust unchanged ones
+                Some(next_order) if next_order.price != order.price => diff.repriced += 1,
+                Some(next_order) => {
+                    diff.add_traded(order, order.volume_remain - next_order.volume_remain)
+                }
+                None => {
+                    let is_best = best_prices
+                        .get(&(order.type_id, order.is_buy_order))
+                        .map_or(false, |&best| best == order.price);
+                    if is_best && !order.expired_at(next.time) {
+                        diff.filled += 1;
+                        diff.add_traded(order, order.volume_remain);
+                    } else {
+                        diff.cancelled += 1;
+                    }
+                }
+            }
+        }
+
+        let previous_ids = self
+            .orders
+            .iter()
+            .map(|x| x.order_id)
+            .collect::<HashSet<_>>();
+        for order in next
+            .orders
+            .iter()
+            .filter(|x| !previous_ids.contains(&x.order_id))
+        {
+            diffs.entry(order.type_id).or_default().new += 1;
+        }
+        diffs
+    }
+}
+
+impl SnapshotOrder {
+    fn expired_at(&self, time: DateTime<Utc>) -> bool {
+        DateTime::parse_from_rfc3339(&self.issued).map_or(false, |issued| {
+            issued.with_timezone(&Utc) + Duration::try_days(self.duration as i64).unwrap() <= time
+        })
+    }
+}
+
+/// Highest buy and lowest sell price for each `(type, is_buy_order)`.
+fn best_prices(orders: &[SnapshotOrder]) -> HashMap<(i32, bool), f64> {
+    let mut best = HashMap::new();
+    for order in orders {
+        best.entry((order.type_id, order.is_buy_order))
+            .and_modify(|price: &mut f64| {
+                *price = if order.is_buy_order {
+                    price.max(order.price)
+                } else {
+                    price.min(order.price)
+                }
+            })
+            .or_insert(order.price);
+    }
+    best
+}
+
+/// Rolling series of snapshots of a station, oldest first.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct OrderSnapshots {
+    pub snapshots: Vec<OrderSnapshot>,
+}
+
+impl OrderSnapshots {
+    /// Adds a snapshot, keeping only the `keep` latest ones.
+    pub fn push(&mut self, snapshot: OrderSnapshot, keep: usize) {
+        self.snapshots.push(snapshot);
+        self.snapshots.sort_by_key(|x| x.time);
+        let excess = self.snapshots.len().saturating_sub(keep);
+        self.snapshots.drain(..excess);
+    }
+
+    /// Diffs between every two consecutive snapshots summed up per type,
+    /// along with the time they cover.
+    pub fn total_diff(&self) -> (HashMap<i32, OrderBookDiff>, Duration) {
+        let mut total = HashMap::<i32, OrderBookDiff>::new();
+        for (previous, next) in self.snapshots.iter().tuple_windows() {
+            for (type_id, diff) in previous.diff(next) {
+                *total.entry(type_id).or_default() += diff;
+            }
+        }
+        (total, self.covered())
+    }
+
+    /// Time between the first and the last snapshot.
+    pub fn covered(&self) -> Duration {
+        match (self.snapshots.first(), self.snapshots.last()) {
+            (Some(first), Some(last)) => last.time - first.time,
+            _ => Duration::zero(),
+        }
+    }
+
+    /// Volume traded at the station per day for each type seen in the snapshots,
+    /// empty until the snapshots are numerous and long enough to be trusted.
+    pub fn station_volumes(&self, config: &ConfigStationVolume) -> HashMap<i32, f64> {
+        let days = self.covered().num_seconds() as f64 / 60. / 60. / 24.;
+        let enough = self.snapshots.len() >= config.min_snapshots && days >= config.min_days;
+        if !config.enabled || !enough {
+            return HashMap::new();
+        }
+        self.observed_daily_volume()
+    }
+
+    /// Volume traded at the station per day for each type seen in the snapshots.
+    pub fn observed_daily_volume(&self) -> HashMap<i32, f64> {
+        let (total, covered) = self.total_diff();
+        let days = covered.num_seconds() as f64 / 60. / 60. / 24.;
+        if days <= 0. {
+            return HashMap::new();
+        }
+        total
+            .into_iter()
+            .map(|(type_id, diff)| (type_id, diff.traded_volume() as f64 / days))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_fixtures::{self, snapshot_order, start, TYPE_ID};
+
+    fn order(order_id: i64, is_buy_order: bool, price: f64, volume_remain: i64) -> SnapshotOrder {
+        SnapshotOrder {
+            volume_remain,
+            ..snapshot_order(order_id, is_buy_order, price)
+        }
+    }
+
+    #[test]
+    fn test_diff_between_snapshots() {
+        let time = start();
+        let previous = OrderSnapshot {
+            time,
+            orders: vec![
+                order(1, false, 10., 5),
+                order(2, false, 11., 5),
+                order(3, false, 12., 5),
+                order(4, true, 8., 20),
+            ],
+        };
+        let next = OrderSnapshot {
+            time: time + Duration::try_hours(1).unwrap(),
+            orders: vec![
+                // order 1 was filled, order 3 cancelled, order 2 was relisted cheaper
+                order(2, false, 10.5, 3),
+                order(4, true, 8., 15),
+                order(5, false, 10.4, 10),
+            ],
+        };
+
+        let diff = previous.diff(&next)[&TYPE_ID];
+        assert_eq!(
+            diff,
+            OrderBookDiff {
+                sold_volume: 5,
+                bought_volume: 5,
+                filled: 1,
+                cancelled: 1,
+                repriced: 1,
+                new: 1,
+            }
+        );
+    }
+
+    #[test]
+    fn test_remote_buy_orders_are_not_station_volume() {
+        let local = test_fixtures::order(1, false, 10.);
+        let remote = Order {
+            location_id: 2,
+            ..test_fixtures::order(2, true, 8.)
+        };
+        let orders = |orders: Vec<Order>| {
+            vec![ItemOrders {
+                id: TYPE_ID,
+                orders,
+            }]
+        };
+        let snapshots = OrderSnapshots {
+            snapshots: vec![
+                OrderSnapshot::new(start(), 1, &orders(vec![local.clone(), remote])),
+                OrderSnapshot::new(
+                    start() + Duration::try_hours(1).unwrap(),
+                    1,
+                    &orders(vec![local]),
+                ),
+            ],
+        };
+
+        let volume = snapshots.observed_daily_volume();
+        assert_eq!(volume.get(&TYPE_ID).copied().unwrap_or(0.), 0.);
+    }
+
+    #[test]
+    fn test_push_keeps_latest() {
+        let time = Utc::now();
+        let mut snapshots = OrderSnapshots::default();
+        for hours in [2, 0, 1] {
+            let snapshot = OrderSnapshot {
+                time: time + Duration::try_hours(hours).unwrap(),
+                orders: Vec::new(),
+            };
+            snapshots.push(snapshot, 2);
+        }
+        let times = snapshots
+            .snapshots
+            .iter()
+            .map(|x| x.time)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            times,
+            vec![
+                time + Duration::try_hours(1).unwrap(),
+                time + Duration::try_hours(2).unwrap()
+            ]
+        );
+    }
+}
@@ -0,0 +1,195 @@
+use std::{
+    io::{IsTerminal, Write},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+const BAR_WIDTH: usize = 30;
+const BAR_REDRAW_INTERVAL: Duration = Duration::from_millis(100);
+const LOG_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Progress of a long running download.
+///
+/// Drawn as a live bar on stderr when it is a terminal, otherwise
+/// reported as periodic log lines.
+pub struct Progress {
+    label: String,
+    total: Option<AtomicU64>,
+    /// Items finished, successfully or not.
+    done: AtomicU64,
+    errors: AtomicU64,
+    started: Instant,
+    last_report: Mutex<Instant>,
+    is_terminal: bool,
+}
+
+impl Progress {
+    pub fn new(label: impl Into<String>, total: usize) -> Self {
+        Self::with_total(label.into(), Some(total as u64))
+    }
+
+    /// Progress of a download whose size isn't known beforehand, like paged requests.
+    pub fn unbounded(label: impl Into<String>) -> Self {
+        Self::with_total(label.into(), None)
+    }
+
+    fn with_total(label: String, total: Option<u64>) -> Self {
+        let now = Instant::now();
+        Self {
+            label,
+            total: total.map(AtomicU64::new),
+            done: AtomicU64::new(0),
+            errors: AtomicU64::new(0),
+            started: now,
+            last_report: Mutex::new(now),
+            is_terminal: std::io::stderr().is_terminal(),
+        }
+    }
+
+    pub fn inc(&self) {
+        self.done.fetch_add(1, Ordering::Relaxed);
+        self.report(false);
+    }
+
+    /// Error that didn't finish an item, like a request that is retried.
+    pub fn inc_error(&self) {
+        self.errors.fetch_add(1, Ordering::Relaxed);
+        self.report(false);
+    }
+
+    /// Item that finished with an error.
+    pub fn inc_failed(&self) {
+        self.errors.fetch_add(1, Ordering::Relaxed);
+        self.inc();
+    }
+
+    /// Corrects the total when it was only estimated.
+    pub fn adjust_total(&self, by: i64) {
+        if let Some(ref total) = self.total {
+            let _ = total.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |x| {
+                Some(x.saturating_add_signed(by))
+            });
+        }
+    }
+
+    fn total(&self) -> Option<u64> {
+        self.total.as_ref().map(|x| x.load(Ordering::Relaxed))
+    }
+
+    pub fn errors(&self) -> u64 {
+        self.errors.load(Ordering::Relaxed)
+    }
+
+    pub fn finish(&self) {
+        self.report(true);
+        if self.is_terminal {
+            eprintln!();
+        }
+    }
+
+    fn report(&self, force: bool) {
+        let interval = if self.is_terminal {
+            BAR_REDRAW_INTERVAL
+        } else {
+            LOG_INTERVAL
+        };
+        {
+            let mut last_report = self.last_report.lock().unwrap();
+            if !force && last_report.elapsed() < interval {
+                return;
+            }
+            *last_report = Instant::now();
+        }
+
+        let line = progress_line(
+            self.done.load(Ordering::Relaxed),
+            self.total(),
+            self.errors(),
+            self.started.elapsed(),
+        );
+        if self.is_terminal {
+            let bar = self.total().map(|total| {
+                let filled = (self.done.load(Ordering::Relaxed) as usize * BAR_WIDTH)
+                    .checked_div(total as usize)
+                    .unwrap_or(BAR_WIDTH)
+                    .min(BAR_WIDTH);
+                format!(
+                    "[{}{}] ",
+                    "#".repeat(filled),
+                    "-".repeat(BAR_WIDTH - filled)
+                )
+            });
+            let mut stderr = std::io::stderr().lock();
+            let _ = write!(
+                stderr,
+                "\r\x1b[2K{}: {}{line}",
+                self.label,
+                bar.unwrap_or_default()
+            );
+            let _ = stderr.flush();
+            log::debug!("{}: {line}", self.label);
+        } else {
+            log::info!("{}: {line}", self.label);
+        }
+    }
+}
+
+fn progress_line(done: u64, total: Option<u64>, errors: u64, elapsed: Duration) -> String {
+    let rate = done as f64 / elapsed.as_secs_f64().max(f64::EPSILON);
+    let mut line = match total {
+        Some(total) => format!("{done}/{total}"),
+        None => format!("{done}"),
+    };
+    line += &format!(", {rate:.1}/s");
+    if let Some(total) = total {
+        if rate > 0. {
+            let eta = total.saturating_sub(done) as f64 / rate;
+            line += &format!(", ETA {}", format_duration(Duration::from_secs_f64(eta)));
+        }
+    }
+    line += &format!(", {errors} errors");
+    line
+}
+
+fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    format!("{:02}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_progress_line_with_total() {
+        let line = progress_line(50, Some(150), 2, Duration::from_secs(10));
+        assert_eq!(line, "50/150, 5.0/s, ETA 00:00:20, 2 errors");
+    }
+
+    #[test]
+    fn test_progress_line_without_total() {
+        let line = progress_line(7, None, 0, Duration::from_secs(2));
+        assert_eq!(line, "7, 3.5/s, 0 errors");
+    }
+
+    #[test]
+    fn test_retries_are_not_done() {
+        let progress = Progress::new("Test", 10);
+        progress.inc_error();
+        progress.inc_failed();
+        progress.inc();
+        progress.adjust_total(-4);
+
+        assert_eq!(progress.done.load(Ordering::Relaxed), 2);
+        assert_eq!(progress.errors(), 2);
+        assert_eq!(progress.total(), Some(6));
+    }
+
+    #[test]
+    fn test_format_duration() {
+        assert_eq!(format_duration(Duration::from_secs(3725)), "01:02:05");
+    }
+}
@@ -1,6 +1,10 @@
 pub mod error;
+pub mod error_limit;
+pub mod esi_http;
+pub mod etag;
 pub mod item_history;
 pub mod paged_all;
+pub mod policy;
 pub mod retry;
 pub mod service;
 pub mod transactions;
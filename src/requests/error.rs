@@ -62,6 +62,50 @@ enum EsiApiErrorEnum {
     ),
     #[error("Ui open window error: {0}")]
     UiOpenWindow(#[from] apis::Error<PostUiOpenwindowMarketdetailsError>),
+    #[error("Request error: {0}")]
+    Request(#[from] reqwest::Error),
+    #[error("Response deserialization error: {0}")]
+    Deserialization(#[from] serde_json::Error),
+    #[error("Response error {0}: {1}")]
+    Response(StatusCode, String),
+}
+
+impl EsiApiError {
+    pub fn from_response(status: StatusCode, content: impl Into<String>) -> Self {
+        let content = content.into();
+        // some endpoints return 500 code with the real status in the message
+        let code = if content.contains("Undefined 404 response") {
+            StatusCode::NOT_FOUND
+        } else if content.contains("Undefined 429 response") {
+            StatusCode::TOO_MANY_REQUESTS
+        } else {
+            status
+        };
+        EsiApiError {
+            internal: EsiApiErrorEnum::Response(status, content),
+            status: code,
+        }
+    }
+}
+
+impl From<reqwest::Error> for EsiApiError {
+    fn from(x: reqwest::Error) -> Self {
+        let code = x.status().unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+        EsiApiError {
+            internal: x.into(),
+            status: code,
+        }
+    }
+}
+
+impl From<serde_json::Error> for EsiApiError {
+    fn from(x: serde_json::Error) -> Self {
+        // the same body won't deserialize any better when asked again
+        EsiApiError {
+            internal: x.into(),
+            status: StatusCode::UNPROCESSABLE_ENTITY,
+        }
+    }
 }
 
 impl From<apis::Error<GetMarketsPricesError>> for EsiApiError {
@@ -0,0 +1,149 @@
+use std::{
+    sync::{
+        atomic::{AtomicI64, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use reqwest::header::HeaderMap;
+
+const HEADER_REMAIN: &str = "x-esi-error-limit-remain";
+const HEADER_RESET: &str = "x-esi-error-limit-reset";
+
+/// Below this many remaining errors requests are spread over the rest of the window.
+const LOW_ERRORS_REMAIN: i64 = 50;
+/// Below this many remaining errors requests wait until the window resets.
+const CRITICAL_ERRORS_REMAIN: i64 = 10;
+
+/// Tracks the ESI error budget reported by the `X-ESI-Error-Limit-Remain`
+/// and `X-ESI-Error-Limit-Reset` response headers.
+#[derive(Debug)]
+pub struct EsiErrorLimit {
+    remain: AtomicI64,
+    reset_at: Mutex<Option<Instant>>,
+}
+
+impl EsiErrorLimit {
+    pub const fn new() -> Self {
+        Self {
+            remain: AtomicI64::new(-1),
+            reset_at: Mutex::new(None),
+        }
+    }
+
+    pub fn update_from_headers(&self, headers: &HeaderMap) {
+        let parse = |name: &str| {
+            headers
+                .get(name)
+                .and_then(|x| x.to_str().ok())
+                .and_then(|x| x.parse::<i64>().ok())
+        };
+        let (Some(remain), Some(reset)) = (parse(HEADER_REMAIN), parse(HEADER_RESET)) else {
+            return;
+        };
+
+        let previous = self.remain.swap(remain, Ordering::Relaxed);
+        *self.reset_at.lock().unwrap() =
+            Some(Instant::now() + Duration::from_secs(reset.max(0) as u64));
+        if remain < LOW_ERRORS_REMAIN && remain != previous {
+            log::warn!("ESI error limit: {remain} errors remain, resets in {reset}s");
+        }
+    }
+
+    /// Whether any response has reported the error limit yet.
+    pub fn is_known(&self) -> bool {
+        self.remain.load(Ordering::Relaxed) >= 0
+    }
+
+    /// Time left until the current error window resets.
+    pub fn until_reset(&self) -> Option<Duration> {
+        let reset_at = (*self.reset_at.lock().unwrap())?;
+        Some(reset_at.saturating_duration_since(Instant::now()))
+    }
+
+    /// How long a request should wait before being sent.
+    pub fn delay(&self) -> Option<Duration> {
+        let remain = self.remain.load(Ordering::Relaxed);
+        if !(0..LOW_ERRORS_REMAIN).contains(&remain) {
+            return None;
+        }
+        let until_reset = self.until_reset()?;
+        if until_reset.is_zero() {
+            return None;
+        }
+
+        if remain < CRITICAL_ERRORS_REMAIN {
+            // add a second so that the window has really reset
+            Some(until_reset + Duration::from_secs(1))
+        } else {
+            Some(until_reset / remain as u32)
+        }
+    }
+
+    pub async fn wait_for_budget(&self) {
+        if let Some(delay) = self.delay() {
+            log::debug!("Error limit is low, waiting {delay:?}...");
+            tokio::time::sleep(delay).await;
+        }
+    }
+}
+
+impl Default for EsiErrorLimit {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use reqwest::header::HeaderValue;
+
+    use super::*;
+
+    fn headers(remain: &str, reset: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(HEADER_REMAIN, HeaderValue::from_str(remain).unwrap());
+        headers.insert(HEADER_RESET, HeaderValue::from_str(reset).unwrap());
+        headers
+    }
+
+    #[test]
+    fn test_no_delay_when_unknown() {
+        let limit = EsiErrorLimit::new();
+        assert!(!limit.is_known());
+        assert_eq!(limit.delay(), None);
+    }
+
+    #[test]
+    fn test_no_delay_with_plenty_of_budget() {
+        let limit = EsiErrorLimit::new();
+        limit.update_from_headers(&headers("100", "60"));
+        assert!(limit.is_known());
+        assert_eq!(limit.delay(), None);
+    }
+
+    #[test]
+    fn test_spread_delay_with_low_budget() {
+        let limit = EsiErrorLimit::new();
+        limit.update_from_headers(&headers("30", "60"));
+        let delay = limit.delay().unwrap();
+        assert!(delay <= Duration::from_secs(2), "{delay:?}");
+        assert!(delay > Duration::from_millis(1500), "{delay:?}");
+    }
+
+    #[test]
+    fn test_wait_for_reset_with_critical_budget() {
+        let limit = EsiErrorLimit::new();
+        limit.update_from_headers(&headers("3", "20"));
+        let delay = limit.delay().unwrap();
+        assert!(delay > Duration::from_secs(20), "{delay:?}");
+    }
+
+    #[test]
+    fn test_ignores_missing_headers() {
+        let limit = EsiErrorLimit::new();
+        limit.update_from_headers(&HeaderMap::new());
+        assert!(!limit.is_known());
+    }
+}
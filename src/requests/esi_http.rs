@@ -0,0 +1,75 @@
+use reqwest::{header, Method, RequestBuilder, Response};
+use rust_eveonline_esi::apis::configuration::Configuration;
+use serde::de::DeserializeOwned;
+
+use super::{
+    error::{EsiApiError, Result},
+    error_limit::EsiErrorLimit,
+};
+
+/// Request to an ESI endpoint (`path` is relative to the configured base path)
+/// with the user agent and the access token of `config`.
+pub fn esi_request(
+    config: &Configuration,
+    method: Method,
+    path: &str,
+    query: &[(&str, String)],
+) -> RequestBuilder {
+    let mut request = config
+        .client
+        .request(method, format!("{}{}", config.base_path, path))
+        .query(query);
+    if let Some(ref user_agent) = config.user_agent {
+        request = request.header(header::USER_AGENT, user_agent.clone());
+    }
+    if let Some(ref token) = config.oauth_access_token {
+        request = request.bearer_auth(token.to_owned());
+    }
+    request
+}
+
+/// Sends the request and records the error limit the response reports, every
+/// ESI request goes through here so that the limit stays current.
+pub async fn send_esi(error_limit: &EsiErrorLimit, request: RequestBuilder) -> Result<Response> {
+    let response = request.send().await?;
+    error_limit.update_from_headers(response.headers());
+    Ok(response)
+}
+
+/// Turns an unsuccessful response into an error.
+pub async fn error_for_status(response: Response) -> Result<Response> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+    let content = response.text().await.unwrap_or_default();
+    Err(EsiApiError::from_response(status, content))
+}
+
+/// GET an ESI endpoint without caching the response and deserialize the body.
+pub async fn get_esi_json<T>(
+    config: &Configuration,
+    error_limit: &EsiErrorLimit,
+    path: &str,
+    query: &[(&str, String)],
+) -> Result<T>
+where
+    T: DeserializeOwned,
+{
+    let request = esi_request(config, Method::GET, path, query);
+    let response = error_for_status(send_esi(error_limit, request).await?).await?;
+    let body = response.bytes().await?;
+    Ok(serde_json::from_slice(&body)?)
+}
+
+/// POST to an ESI endpoint that answers without a body.
+pub async fn post_esi(
+    config: &Configuration,
+    error_limit: &EsiErrorLimit,
+    path: &str,
+    query: &[(&str, String)],
+) -> Result<()> {
+    let request = esi_request(config, Method::POST, path, query);
+    error_for_status(send_esi(error_limit, request).await?).await?;
+    Ok(())
+}
@@ -0,0 +1,233 @@
+use std::{collections::HashMap, path::PathBuf, sync::Mutex};
+
+use chrono::{DateTime, Duration, Utc};
+use itertools::Itertools;
+use reqwest::{
+    header::{self, HeaderMap},
+    Method, StatusCode,
+};
+use rust_eveonline_esi::apis::configuration::Configuration;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::{
+    cached_data::Expiring,
+    consts::{ETAGS_KEEP_DAYS, ETAGS_MAX_BYTES},
+};
+
+use super::{
+    error::{EsiApiError, Result},
+    error_limit::EsiErrorLimit,
+    esi_http::{error_for_status, esi_request, send_esi},
+};
+
+/// Remembers ETags and bodies of ESI responses so that unchanged resources
+/// are revalidated with `If-None-Match` instead of being downloaded again.
+/// Saved when dropped, so responses of a run that failed are kept too.
+#[derive(Debug)]
+pub struct EtagCache {
+    entries: Mutex<HashMap<String, EtagEntry>>,
+    path: PathBuf,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct EtagEntry {
+    etag: String,
+    body: Vec<u8>,
+    #[serde(default)]
+    expires: Option<DateTime<Utc>>,
+    #[serde(default = "Utc::now")]
+    last_used: DateTime<Utc>,
+}
+
+impl EtagCache {
+    pub fn load(path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        let entries = match std::fs::read(&path) {
+            Ok(bytes) => rmp_serde::from_slice(bytes.as_slice()).unwrap_or_else(|err| {
+                log::warn!("Couldn't deserialize etag cache {path:?}: {err}");
+                HashMap::new()
+            }),
+            Err(_) => HashMap::new(),
+        };
+        log::debug!("Loaded {} etags from {path:?}", entries.len());
+
+        Self {
+            entries: Mutex::new(entries),
+            path,
+        }
+    }
+
+    /// Saves entries used in the last `ETAGS_KEEP_DAYS`, the least recently used
+    /// ones are dropped when the bodies take more than `ETAGS_MAX_BYTES`.
+    pub fn save(&self) -> anyhow::Result<()> {
+        let mut entries = self.entries.lock().unwrap();
+        let keep_since = Utc::now() - Duration::try_days(ETAGS_KEEP_DAYS).unwrap();
+        evict(&mut entries, keep_since, ETAGS_MAX_BYTES);
+        let bytes = rmp_serde::to_vec(&*entries)?;
+        std::fs::write(&self.path, bytes)?;
+        log::debug!("Saved {} etags to {:?}", entries.len(), self.path);
+        Ok(())
+    }
+
+    /// GET an ESI endpoint (`path` is relative to the configured base path)
+    /// and deserialize the body. A `304 Not Modified` reuses the cached body,
+    /// and a cached body that hasn't expired yet is returned without a request.
+    pub async fn get_json<T>(
+        &self,
+        config: &Configuration,
+        error_limit: &EsiErrorLimit,
+        path: &str,
+        query: &[(&str, String)],
+    ) -> Result<Expiring<T>>
+    where
+        T: DeserializeOwned,
+    {
+        let key = cache_key(path, query);
+
+        if let Some(fresh) = self.get_fresh(&key) {
+            return fresh;
+        }
+
+        let mut request = esi_request(config, Method::GET, path, query);
+        let known_etag = self
+            .entries
+            .lock()
+            .unwrap()
+            .get(&key)
+            .map(|x| x.etag.clone());
+        if let Some(etag) = known_etag {
+            request = request.header(header::IF_NONE_MATCH, etag);
+        }
+
+        let response = send_esi(error_limit, request).await?;
+        let status = response.status();
+        let expires = parse_expires(response.headers());
+
+        if status == StatusCode::NOT_MODIFIED {
+            log::trace!("Not modified: {key}");
+            let mut entries = self.entries.lock().unwrap();
+            let entry = entries
+                .get_mut(&key)
+                .ok_or_else(|| EsiApiError::from_response(status, "No cached body for 304"))?;
+            entry.expires = expires;
+            entry.last_used = Utc::now();
+            let data = serde_json::from_slice(entry.body.as_slice())?;
+            return Ok(Expiring::new(data, expires));
+        }
+        let response = error_for_status(response).await?;
+
+        let etag = response
+            .headers()
+            .get(header::ETAG)
+            .and_then(|x| x.to_str().ok())
+            .map(ToOwned::to_owned);
+        let body = response.bytes().await?.to_vec();
+        let data = serde_json::from_slice(body.as_slice())?;
+
+        if let Some(etag) = etag {
+            self.entries.lock().unwrap().insert(
+                key,
+                EtagEntry {
+                    etag,
+                    body,
+                    expires,
+                    last_used: Utc::now(),
+                },
+            );
+        }
+
+        Ok(Expiring::new(data, expires))
+    }
+
+    fn get_fresh<T>(&self, key: &str) -> Option<Result<Expiring<T>>>
+    where
+        T: DeserializeOwned,
+    {
+        let mut entries = self.entries.lock().unwrap();
+        let entry = entries.get_mut(key)?;
+        let expires = entry.expires.filter(|&x| x > Utc::now())?;
+        log::trace!("Not expired yet: {key}");
+        entry.last_used = Utc::now();
+        Some(
+            serde_json::from_slice(entry.body.as_slice())
+                .map(|data| Expiring::new(data, Some(expires)))
+                .map_err(Into::into),
+        )
+    }
+}
+
+impl Drop for EtagCache {
+    fn drop(&mut self) {
+        if let Err(err) = self.save() {
+            log::warn!("Couldn't save etag cache {:?}: {err}", self.path);
+        }
+    }
+}
+
+fn evict(entries: &mut HashMap<String, EtagEntry>, keep_since: DateTime<Utc>, max_bytes: usize) {
+    entries.retain(|_, x| x.last_used >= keep_since);
+
+    let mut size = entries.values().map(|x| x.body.len()).sum::<usize>();
+    let least_recent = entries
+        .iter()
+        .map(|(key, x)| (x.last_used, x.body.len(), key.clone()))
+        .sorted()
+        .collect_vec();
+    for (_, len, key) in least_recent {
+        if size <= max_bytes {
+            break;
+        }
+        entries.remove(&key);
+        size -= len;
+    }
+}
+
+pub fn parse_expires(headers: &HeaderMap) -> Option<DateTime<Utc>> {
+    let expires = headers.get(header::EXPIRES)?.to_str().ok()?;
+    DateTime::parse_from_rfc2822(expires)
+        .map(|x| x.with_timezone(&Utc))
+        .map_err(|err| log::debug!("Couldn't parse Expires header '{expires}': {err}"))
+        .ok()
+}
+
+fn cache_key(path: &str, query: &[(&str, String)]) -> String {
+    let query = query
+        .iter()
+        .sorted()
+        .map(|(k, v)| format!("{k}={v}"))
+        .join("&");
+    format!("{path}?{query}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(body_len: usize, last_used: DateTime<Utc>) -> EtagEntry {
+        EtagEntry {
+            etag: "\"etag\"".to_string(),
+            body: vec![0; body_len],
+            expires: None,
+            last_used,
+        }
+    }
+
+    #[test]
+    fn test_evict_old_and_least_recent() {
+        let now = Utc::now();
+        let hours_ago = |x| now - Duration::try_hours(x).unwrap();
+        let mut entries = HashMap::from([
+            ("old".to_string(), entry(10, hours_ago(48))),
+            ("least recent".to_string(), entry(10, hours_ago(3))),
+            ("recent".to_string(), entry(10, hours_ago(2))),
+            ("latest".to_string(), entry(10, hours_ago(1))),
+        ]);
+
+        evict(&mut entries, hours_ago(24), 20);
+
+        assert_eq!(
+            entries.keys().sorted().collect_vec(),
+            vec!["latest", "recent"]
+        );
+    }
+}
@@ -1,135 +1,63 @@
-use std::collections::HashMap;
+use std::collections::HashSet;
 
-use governor::DefaultDirectRateLimiter;
 use rust_eveonline_esi::apis::configuration::Configuration;
 
 use crate::{
+    cached_data::{Checkpoint, Expiring},
     consts::BUFFER_UNORDERED,
-    requests::{paged_all::OnlyOk, retry},
+    progress::Progress,
+    requests::retry,
 };
 use crate::{
-    consts::DATE_FMT,
     item_type::{ItemHistory, MarketsRegionHistory},
     requests::retry::RetryResult,
 };
-use chrono::{Duration, NaiveDate, Utc};
+use chrono::Utc;
 use reqwest::StatusCode;
 
 use super::{
     error::{EsiApiError, Result},
-    service::to_not_nan,
+    etag::EtagCache,
+    policy::{Host, RequestPolicy},
 };
 
-use crate::stat::MedianStat;
-
 use futures::{stream, StreamExt};
-use itertools::Itertools;
-
-use rust_eveonline_esi::apis::market_api::{self, GetMarketsRegionIdHistoryParams};
 
 pub struct ItemHistoryEsiService<'a> {
     pub config: &'a Configuration,
-    pub error_limiter: &'a DefaultDirectRateLimiter,
-    pub request_limiter: &'a DefaultDirectRateLimiter,
+    pub policy: &'a RequestPolicy,
+    pub etags: &'a EtagCache,
 }
 impl<'a> ItemHistoryEsiService<'a> {
     pub async fn all_item_history(
         &self,
         item_types: &[i32],
         region_id: i32,
-    ) -> Result<Vec<ItemHistory>> {
-        let mut data = self.download_item_data(item_types, region_id).await?;
-
-        // fill blanks
-        for item in data.iter_mut() {
-            let history = std::mem::take(&mut item.history);
-            let avg = history
-                .iter()
-                .map(|x| x.average.unwrap())
-                .map(to_not_nan)
-                .median()
-                .map(|x| *x);
-            let high = history
-                .iter()
-                .map(|x| x.highest.unwrap())
-                .map(to_not_nan)
-                .median()
-                .map(|x| *x);
-            let low = history
-                .iter()
-                .map(|x| x.lowest.unwrap())
-                .map(to_not_nan)
-                .median()
-                .map(|x| *x);
-
-            // take earliest date
-            let mut dates = history
-                .into_iter()
-                .map(|x| {
-                    let date = NaiveDate::parse_from_str(x.date.as_str(), DATE_FMT).unwrap();
-                    (date, x)
-                })
-                .collect::<HashMap<_, _>>();
-            let current_date = Utc::now().naive_utc().date();
-            let past_date = current_date - Duration::try_days(360).unwrap();
-
-            for date in past_date.iter_days() {
-                if dates.contains_key(&date) {
-                    continue;
-                }
-
-                dates.insert(
-                    date,
-                    MarketsRegionHistory {
-                        average: avg,
-                        date: date.format(DATE_FMT).to_string(),
-                        highest: high,
-                        lowest: low,
-                        order_count: 0,
-                        volume: 0,
-                    },
-                );
-
-                if date == current_date {
-                    break;
-                }
-            }
-            let new_history = dates.into_iter().sorted_by_key(|x| x.0);
-            for it in new_history {
-                item.history.push(it.1);
-            }
-        }
-
-        Ok(data)
+        checkpoint: Checkpoint<ItemHistory>,
+    ) -> Expiring<Vec<ItemHistory>> {
+        self.download_item_data(item_types, region_id, checkpoint)
+            .await
     }
 
     async fn get_item_type_history(
         &self,
         region_id: i32,
         item_type: i32,
-    ) -> Result<Option<ItemHistory>> {
-        let res: Option<ItemHistory> = retry::retry_smart_with_error_limiter(
-            self.request_limiter,
-            self.error_limiter,
-            || async {
+    ) -> Result<Option<Expiring<ItemHistory>>> {
+        let res: Option<Expiring<ItemHistory>> =
+            retry::retry_smart(self.policy, Host::Esi, || async {
                 log::debug!("Downloading market history, type {item_type}, region_id {region_id}");
-                let hist_for_type: Result<_> = async {
-                    Ok(market_api::get_markets_region_id_history(
+                // own type instead of the generated one so that rows with missing prices
+                // don't fail deserialization of the whole history
+                let hist_for_type: Result<Expiring<Vec<MarketsRegionHistory>>> = self
+                    .etags
+                    .get_json(
                         self.config,
-                        GetMarketsRegionIdHistoryParams {
-                            region_id,
-                            type_id: item_type,
-                            datasource: None,
-                            if_none_match: None,
-                        },
+                        &self.policy.error_limit,
+                        &format!("/markets/{region_id}/history/"),
+                        &[("type_id", item_type.to_string())],
                     )
-                    .await?
-                    .entity
-                    .unwrap()
-                    .into_ok()
-                    .unwrap())
-                }
-                .await;
+                    .await;
 
                 // turn all 404 errors into empty vecs
                 let hist_for_type = match hist_for_type {
@@ -140,12 +68,12 @@ impl<'a> ItemHistoryEsiService<'a> {
                             ..
                         },
                     ) => {
-                        self.error_limiter.until_ready().await;
+                        self.wait_after_error().await;
                         log::debug!("Making empty hist_for_type: {api_err:?}");
-                        Vec::new()
+                        Expiring::new(Vec::new(), None)
                     }
                     Err(e) => {
-                        self.error_limiter.until_ready().await;
+                        self.wait_after_error().await;
                         log::debug!(
                             "Region id: {region_id}; Item type: {item_type} Returning error: {e:?}"
                         );
@@ -153,43 +81,88 @@ impl<'a> ItemHistoryEsiService<'a> {
                     }
                 };
 
-                let item = ItemHistory {
+                let item = hist_for_type.map(|history| ItemHistory {
                     id: item_type,
-                    history: hist_for_type
-                        .into_iter()
-                        .map(|x| MarketsRegionHistory {
-                            average: Some(x.average),
-                            date: x.date,
-                            highest: Some(x.highest),
-                            lowest: Some(x.lowest),
-                            order_count: x.order_count,
-                            volume: x.volume,
-                        })
-                        .collect(),
-                };
+                    history,
+                });
                 Ok(RetryResult::Success(item))
-            },
-        )
-        .await?;
+            })
+            .await?;
         Ok(res)
     }
 
+    async fn wait_after_error(&self) {
+        self.policy.after_error(Host::Esi).await;
+    }
+
+    /// Downloads history of the types missing from `checkpoint`. Types that
+    /// failed to download are left out, and the result expires immediately so
+    /// that they are downloaded on the next run.
     async fn download_item_data(
         &self,
         item_types: &[i32],
         region_id: i32,
-    ) -> Result<Vec<ItemHistory>> {
-        let hists = stream::iter(item_types)
-            .map(|&item_type| self.get_item_type_history(region_id, item_type))
+        mut checkpoint: Checkpoint<ItemHistory>,
+    ) -> Expiring<Vec<ItemHistory>> {
+        let downloaded = checkpoint
+            .items()
+            .iter()
+            .map(|x| x.id)
+            .collect::<HashSet<_>>();
+        let missing = item_types
+            .iter()
+            .copied()
+            .filter(|x| !downloaded.contains(x))
+            .collect::<Vec<_>>();
+        if !downloaded.is_empty() {
+            log::info!(
+                "Resuming history download for region {region_id}: {} types left of {}",
+                missing.len(),
+                item_types.len()
+            );
+        }
+
+        let progress = Progress::new(format!("History of region {region_id}"), missing.len());
+        let mut hists = stream::iter(missing)
+            .map(|item_type| async move {
+                (
+                    item_type,
+                    self.get_item_type_history(region_id, item_type).await,
+                )
+            })
             .buffer_unordered(BUFFER_UNORDERED);
 
-        Ok(hists
-            .collect::<Vec<_>>()
-            .await
-            .into_iter()
-            .collect::<Result<Vec<_>>>()?
-            .into_iter()
-            .flatten()
-            .collect::<Vec<_>>())
+        let mut failed = Vec::new();
+        while let Some((item_type, hist)) = hists.next().await {
+            match hist {
+                Ok(Some(hist)) => {
+                    progress.inc();
+                    checkpoint.push(hist);
+                }
+                Ok(None) => {
+                    progress.inc_failed();
+                    failed.push(item_type);
+                }
+                Err(e) => {
+                    progress.inc_failed();
+                    log::debug!("Couldn't download history of type {item_type}: {e}");
+                    failed.push(item_type);
+                }
+            }
+        }
+        progress.finish();
+
+        if failed.is_empty() {
+            return checkpoint.finish();
+        }
+
+        checkpoint.save();
+        failed.sort_unstable();
+        log::warn!(
+            "Couldn't download history of {} types in region {region_id}, \
+            they will be retried on the next run: {failed:?}",
+            failed.len()
+        );
+        Expiring::new(checkpoint.into_items(), Some(Utc::now()))
     }
 }
@@ -26,20 +26,33 @@ use rust_eveonline_esi::{
     },
 };
 
-use crate::requests::retry::{self, RetryResult};
+use crate::{
+    cached_data::{earliest_expiry, Expiring},
+    progress::Progress,
+    requests::retry::{self, RetryResult},
+};
 
-use super::error::EsiApiError;
+use super::{
+    error::EsiApiError,
+    policy::{Host, RequestPolicy},
+};
 
-pub async fn get_all_pages<Ret, F, T>(get: F) -> Result<Vec<T>, super::error::EsiApiError>
+pub async fn get_all_pages<Ret, F, T>(
+    policy: &RequestPolicy,
+    label: &str,
+    get: F,
+) -> Result<Expiring<Vec<T>>, super::error::EsiApiError>
 where
     F: Fn(i32) -> Ret,
-    Ret: Future<Output = Result<Vec<T>, super::error::EsiApiError>>,
+    Ret: Future<Output = Result<Expiring<Vec<T>>, super::error::EsiApiError>>,
     T: Debug,
 {
+    let progress = Progress::unbounded(format!("{label} pages"));
     let mut all_items = Vec::new();
+    let mut expires = None;
     let mut page = 1;
     loop {
-        let page_items = retry::retry_smart(|| async {
+        let page_items = retry::retry_smart(policy, Host::Esi, || async {
             match get(page).await {
                 Ok(x) => Ok(RetryResult::Success(x)),
 
@@ -47,7 +60,7 @@ where
                 Err(EsiApiError {
                     status: StatusCode::NOT_FOUND,
                     ..
-                }) => Ok(RetryResult::Success(Vec::new())),
+                }) => Ok(RetryResult::Success(Expiring::new(Vec::new(), None))),
 
                 // 403 Forbidden is sometimes thrown randomly??? retry in this case
                 Err(
@@ -57,6 +70,7 @@ where
                     },
                 ) => {
                     log::warn!("{err} when getting pages, retrying...");
+                    progress.inc_error();
                     Ok(RetryResult::Retry)
                 }
 
@@ -66,16 +80,19 @@ where
         .await?;
         let mut page_items = page_items.unwrap_or_else(|| {
             log::warn!("Max retry count exceeded and error wasn't resolved.");
-            Vec::new()
+            Expiring::new(Vec::new(), None)
         });
-        if page_items.is_empty() {
+        if page_items.data.is_empty() {
             break;
         }
-        all_items.append(&mut page_items);
+        expires = earliest_expiry(expires, page_items.expires);
+        all_items.append(&mut page_items.data);
+        progress.inc();
 
         page += 1;
     }
-    Ok(all_items)
+    progress.finish();
+    Ok(Expiring::new(all_items, expires))
 }
 
 pub trait OnlyOk<T, E>: Sized {
@@ -0,0 +1,120 @@
+use std::{num::NonZeroU32, time::Duration};
+
+use governor::{DefaultDirectRateLimiter, Quota, RateLimiter};
+use rand::Rng;
+
+use crate::consts::RETRIES;
+
+use super::error_limit::EsiErrorLimit;
+
+const ESI_REQUESTS_PER_MINUTE: u32 = 600;
+const ESI_ERRORS_PER_MINUTE: u32 = 100;
+// zkillboard allows only one request per second
+const ZKB_REQUESTS_PER_SECOND: u32 = 1;
+const ZKB_ERRORS_PER_MINUTE: u32 = 10;
+
+const SERVER_ERROR_RETRIES: u32 = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Host {
+    Esi,
+    Zkillboard,
+}
+
+/// Limits and retry behaviour shared by every service making requests.
+pub struct RequestPolicy {
+    esi: HostLimits,
+    zkillboard: HostLimits,
+    /// How many times a request asking to be retried is repeated.
+    pub retries: u32,
+    /// How many times a request failing with a server error is repeated,
+    /// `None` repeats it until it succeeds.
+    pub server_error_retries: Option<u32>,
+    pub backoff: Backoff,
+    pub error_limit: EsiErrorLimit,
+}
+
+pub struct HostLimits {
+    pub requests: DefaultDirectRateLimiter,
+    pub errors: DefaultDirectRateLimiter,
+}
+
+impl RequestPolicy {
+    pub fn limits(&self, host: Host) -> &HostLimits {
+        match host {
+            Host::Esi => &self.esi,
+            Host::Zkillboard => &self.zkillboard,
+        }
+    }
+
+    /// Waits until a request to `host` is allowed to be sent.
+    pub async fn until_ready(&self, host: Host) {
+        if host == Host::Esi {
+            self.error_limit.wait_for_budget().await;
+        }
+        self.limits(host).requests.until_ready().await;
+    }
+
+    /// Waits after a request to `host` has failed.
+    pub async fn after_error(&self, host: Host) {
+        // the fixed quota is only a fallback until ESI reports the real budget
+        if host == Host::Esi && self.error_limit.is_known() {
+            self.error_limit.wait_for_budget().await;
+        } else {
+            self.limits(host).errors.until_ready().await;
+        }
+    }
+}
+
+impl Default for RequestPolicy {
+    fn default() -> Self {
+        Self {
+            esi: HostLimits {
+                requests: RateLimiter::direct(per_minute(ESI_REQUESTS_PER_MINUTE)),
+                errors: RateLimiter::direct(per_minute(ESI_ERRORS_PER_MINUTE)),
+            },
+            zkillboard: HostLimits {
+                requests: RateLimiter::direct(Quota::per_second(
+                    NonZeroU32::new(ZKB_REQUESTS_PER_SECOND).unwrap(),
+                )),
+                errors: RateLimiter::direct(per_minute(ZKB_ERRORS_PER_MINUTE)),
+            },
+            retries: RETRIES,
+            server_error_retries: Some(SERVER_ERROR_RETRIES),
+            backoff: Backoff::default(),
+            error_limit: EsiErrorLimit::new(),
+        }
+    }
+}
+
+fn per_minute(amount: u32) -> Quota {
+    Quota::per_minute(NonZeroU32::new(amount).unwrap())
+}
+
+/// Exponential backoff with random jitter.
+#[derive(Debug, Clone, Copy)]
+pub struct Backoff {
+    pub base: Duration,
+    pub max: Duration,
+    /// Fraction of the delay that is randomized.
+    pub jitter: f64,
+}
+
+impl Backoff {
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let exp = self.base.saturating_mul(2u32.saturating_pow(attempt));
+        let delay = exp.min(self.max);
+        let jitter = rand::thread_rng().gen_range(0.0..=self.jitter);
+        delay.mul_f64(1. - jitter)
+    }
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Self {
+            base: Duration::from_secs(1),
+            max: Duration::from_secs(60),
+            jitter: 0.5,
+        }
+    }
+}
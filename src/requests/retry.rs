@@ -1,17 +1,19 @@
-use std::{num::NonZeroU32, panic::Location, time::Duration};
+use std::{panic::Location, time::Duration};
 
-use super::error::EsiApiError;
-use crate::consts::RETRIES;
+use super::{
+    error::EsiApiError,
+    policy::{Host, RequestPolicy},
+};
 use futures::Future;
-use governor::{DefaultDirectRateLimiter, Quota, RateLimiter};
-use reqwest::StatusCode;
-
-const ERROR_LIMITED_RETRY_DELAY: u64 = 60;
+use reqwest::{
+    header::{self, HeaderMap},
+    StatusCode,
+};
 
 #[track_caller]
-pub fn retry_smart_with_error_limiter<'a, T, Fut, F, E>(
-    request_limiter: &'a DefaultDirectRateLimiter,
-    error_limiter: &'a DefaultDirectRateLimiter,
+pub fn retry_smart<'a, T, Fut, F, E>(
+    policy: &'a RequestPolicy,
+    host: Host,
     func: F,
 ) -> impl Future<Output = Result<Option<T>, E>> + 'a
 where
@@ -20,27 +22,14 @@ where
     E: RetryableError + std::fmt::Display + std::fmt::Debug,
 {
     let caller = std::panic::Location::caller();
-    async move { retry_internal(func, *caller, request_limiter, error_limiter).await }
-}
-
-#[track_caller]
-pub fn retry_smart<T, Fut, F, E>(func: F) -> impl Future<Output = Result<Option<T>, E>>
-where
-    Fut: Future<Output = Result<RetryResult<T>, E>>,
-    F: Fn() -> Fut,
-    E: RetryableError + std::fmt::Display + std::fmt::Debug,
-{
-    let error_limiter = RateLimiter::direct(Quota::per_minute(NonZeroU32::new(100).unwrap()));
-    let request_limiter = RateLimiter::direct(Quota::per_minute(NonZeroU32::new(1000).unwrap()));
-    let caller = std::panic::Location::caller();
-    async move { retry_internal(func, *caller, &request_limiter, &error_limiter).await }
+    async move { retry_internal(func, *caller, policy, host).await }
 }
 
 async fn retry_internal<'a, T, Fut, F, E>(
     func: F,
     caller: Location<'a>,
-    request_limiter: &'a DefaultDirectRateLimiter,
-    error_limiter: &'a DefaultDirectRateLimiter,
+    policy: &'a RequestPolicy,
+    host: Host,
 ) -> Result<Option<T>, E>
 where
     Fut: Future<Output = Result<RetryResult<T>, E>>,
@@ -48,34 +37,54 @@ where
     E: RetryableError + std::fmt::Display + std::fmt::Debug,
 {
     let mut retries = 0;
+    let mut error_retries = 0;
     loop {
-        request_limiter.until_ready().await;
+        policy.until_ready(host).await;
 
         log::trace!("[{caller}] Trying...");
         let out = func().await;
         match out {
             Ok(RetryResult::Success(x)) => break Ok(Some(x)),
-            Ok(RetryResult::Retry) => {
-                if retries > RETRIES {
+            Ok(result @ (RetryResult::Retry | RetryResult::Throttled(_))) => {
+                if retries > policy.retries {
                     log::debug!("Retries finished. Retried {retries} times.");
                     break Ok(None);
                 }
 
+                let delay = match result {
+                    // being throttled again right away only makes it last longer
+                    RetryResult::Throttled(after) => after.unwrap_or(policy.backoff.max),
+                    _ => policy.backoff.delay(retries),
+                };
                 retries += 1;
-                log::debug!("Retrying in {ERROR_LIMITED_RETRY_DELAY}s...");
-                tokio::time::sleep(Duration::from_secs_f32(ERROR_LIMITED_RETRY_DELAY as f32)).await;
+                log::debug!("Retrying in {delay:?}...");
+                tokio::time::sleep(delay).await;
             }
             Err(ref e) if e.is_error_limited() => {
-                log::debug!("[{caller}] Retry: Error limited: {e:?}");
-                tokio::time::sleep(Duration::from_secs(ERROR_LIMITED_RETRY_DELAY)).await;
+                let delay = policy
+                    .error_limit
+                    .until_reset()
+                    .filter(|x| !x.is_zero())
+                    .unwrap_or(policy.backoff.max);
+                log::debug!("[{caller}] Retry in {delay:?}: Error limited: {e:?}");
+                tokio::time::sleep(delay).await;
             }
             Err(ref e) if e.is_too_many_requests() => {
-                log::debug!("[{caller}] Retry: Too many requests: {e:?}");
-                tokio::time::sleep(Duration::from_secs(ERROR_LIMITED_RETRY_DELAY)).await;
+                let delay = policy.backoff.max;
+                log::debug!("[{caller}] Retry in {delay:?}: Too many requests: {e:?}");
+                tokio::time::sleep(delay).await;
             }
-            Err(ref e) if e.is_common_ccp_error() => {
-                log::debug!("[{caller}] Retry: Error: {e:?}");
-                error_limiter.until_ready().await;
+            Err(e)
+                if e.is_common_ccp_error()
+                    && policy
+                        .server_error_retries
+                        .map_or(true, |max| error_retries < max) =>
+            {
+                let delay = policy.backoff.delay(error_retries);
+                error_retries += 1;
+                log::debug!("[{caller}] Retry {error_retries} in {delay:?}: Error: {e:?}");
+                policy.after_error(host).await;
+                tokio::time::sleep(delay).await;
             }
             Err(e) => {
                 log::debug!("[{caller}] Error broke out: {e:?}");
@@ -87,9 +96,17 @@ where
 
 pub enum RetryResult<T> {
     Retry,
+    /// The server asked to slow down, optionally saying for how long.
+    Throttled(Option<Duration>),
     Success(T),
 }
 
+/// Delay from the `Retry-After` header, when it is given in seconds.
+pub fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let seconds = headers.get(header::RETRY_AFTER)?.to_str().ok()?;
+    seconds.trim().parse().ok().map(Duration::from_secs)
+}
+
 pub trait RetryableError {
     fn is_common_ccp_error(&self) -> bool;
     fn is_error_limited(&self) -> bool;
@@ -1,48 +1,43 @@
+use anyhow::anyhow;
 use std::collections::HashMap;
 
 use crate::{
+    cached_data::{earliest_expiry, Expiring},
     consts::{self, BUFFER_UNORDERED},
-    requests::paged_all::OnlyOk,
+    requests::policy::{Host, RequestPolicy},
     requests::retry::{self, retry_smart},
     StationId,
 };
 use crate::{
-    item_type::ItemOrders,
+    item_type::{
+        CharacterOrder, CharacterSkills, ItemOrders, JournalEntry, Standing, StationOwner,
+    },
     requests::{paged_all::get_all_pages, retry::RetryResult},
     Station, StationIdData,
 };
 use chrono::NaiveDateTime;
 use reqwest::StatusCode;
-use serde::{Deserialize, Serialize};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 
-use super::error::{EsiApiError, Result};
+use super::{
+    error::{EsiApiError, Result},
+    esi_http::{get_esi_json, post_esi},
+    etag::EtagCache,
+};
 use crate::item_type::Order;
 
 use futures::{stream, StreamExt};
 use itertools::Itertools;
 use ordered_float::NotNan;
 use rust_eveonline_esi::{
-    apis::{
-        configuration::Configuration,
-        killmails_api::{self, GetKillmailsKillmailIdKillmailHashParams},
-        market_api::{
-            self, GetMarketsPricesParams, GetMarketsRegionIdOrdersParams,
-            GetMarketsRegionIdTypesParams, GetMarketsStructuresStructureIdParams,
-        },
-        routes_api::{self, GetRouteOriginDestinationParams},
-        search_api::{get_characters_character_id_search, GetCharactersCharacterIdSearchParams},
-        universe_api::{
-            self, GetUniverseConstellationsConstellationIdParams,
-            GetUniverseConstellationsConstellationIdSuccess, GetUniverseStationsStationIdParams,
-            GetUniverseStructuresStructureIdParams, GetUniverseSystemsSystemIdParams,
-            GetUniverseSystemsSystemIdSuccess, GetUniverseTypesTypeIdParams,
-        },
-        user_interface_api::{self, PostUiOpenwindowMarketdetailsParams},
-    },
+    apis::configuration::Configuration,
     models::{
-        get_markets_region_id_orders_200_ok, GetKillmailsKillmailIdKillmailHashItem,
-        GetKillmailsKillmailIdKillmailHashItemsItem, GetMarketsPrices200Ok,
-        GetMarketsRegionIdOrders200Ok, GetUniverseTypesTypeIdOk,
+        get_markets_region_id_orders_200_ok, GetCharactersCharacterIdSearchOk,
+        GetKillmailsKillmailIdKillmailHashItem, GetKillmailsKillmailIdKillmailHashItemsItem,
+        GetKillmailsKillmailIdKillmailHashOk, GetMarketsPrices200Ok, GetMarketsRegionIdOrders200Ok,
+        GetMarketsStructuresStructureId200Ok, GetUniverseConstellationsConstellationIdOk,
+        GetUniverseStationsStationIdOk, GetUniverseStructuresStructureIdOk,
+        GetUniverseSystemsSystemIdOk, GetUniverseTypesTypeIdOk,
     },
 };
 
@@ -50,10 +45,19 @@ use tokio::sync::RwLock;
 
 pub struct EsiRequestsService<'a> {
     pub config: &'a Configuration,
+    pub etags: &'a EtagCache,
+    pub policy: &'a RequestPolicy,
+    /// Jumps between two systems for the whole run, routes don't change.
+    distances: RwLock<HashMap<(i32, i32), Option<usize>>>,
 }
 impl<'a> EsiRequestsService<'a> {
-    pub fn new(config: &'a Configuration) -> Self {
-        Self { config }
+    pub fn new(config: &'a Configuration, etags: &'a EtagCache, policy: &'a RequestPolicy) -> Self {
+        Self {
+            config,
+            etags,
+            policy,
+            distances: RwLock::new(HashMap::new()),
+        }
     }
 
     pub async fn find_region_id_station(
@@ -62,133 +66,58 @@ impl<'a> EsiRequestsService<'a> {
         character_id: i32,
     ) -> Result<StationIdData> {
         // find system id
-        let station_id = if station.is_citadel {
-            get_characters_character_id_search(
-                self.config,
-                GetCharactersCharacterIdSearchParams {
-                    categories: vec!["structure".to_string()],
-                    character_id,
-                    search: station.name.to_string(),
-                    accept_language: None,
-                    datasource: None,
-                    if_none_match: None,
-                    language: None,
-                    strict: None,
-                    token: None,
-                },
-            )
-            .await?
-            .entity
-            .unwrap()
-            .into_ok()
-            .unwrap()
-            .structure
-            .expect("Citadel not found")[0]
+        let category = if station.is_citadel {
+            "structure"
         } else {
-            get_characters_character_id_search(
-                self.config,
-                GetCharactersCharacterIdSearchParams {
-                    character_id,
-                    token: None,
-                    categories: vec!["station".to_string()],
-                    search: station.name.to_string(),
-                    accept_language: None,
-                    datasource: None,
-                    if_none_match: None,
-                    language: None,
-                    strict: None,
-                },
+            "station"
+        };
+        let search = self
+            .get_uncached::<GetCharactersCharacterIdSearchOk>(
+                &format!("/characters/{character_id}/search/"),
+                &[
+                    ("categories", category.to_string()),
+                    ("search", station.name.to_string()),
+                ],
             )
-            .await?
-            .entity
-            .unwrap()
-            .into_ok()
-            .unwrap()
-            .station
-            .unwrap()
-            .into_iter()
-            .next()
-            .unwrap() as i64
+            .await?;
+        let station_id = if station.is_citadel {
+            search.structure.expect("Citadel not found")[0]
+        } else {
+            search.station.unwrap().into_iter().next().unwrap() as i64
         };
         let system_id = if station.is_citadel {
-            universe_api::get_universe_structures_structure_id(
-                self.config,
-                GetUniverseStructuresStructureIdParams {
-                    structure_id: station_id,
-                    datasource: None,
-                    if_none_match: None,
-                    token: None,
-                },
+            self.get_uncached::<GetUniverseStructuresStructureIdOk>(
+                &format!("/universe/structures/{station_id}/"),
+                &[],
             )
-            .await
-            .unwrap()
-            .entity
-            .unwrap()
-            .into_ok()
-            .unwrap()
+            .await?
             .solar_system_id
         } else {
-            universe_api::get_universe_stations_station_id(
-                self.config,
-                GetUniverseStationsStationIdParams {
-                    station_id: station_id as i32,
-                    datasource: None,
-                    if_none_match: None,
-                },
+            self.get_uncached::<GetUniverseStationsStationIdOk>(
+                &format!("/universe/stations/{station_id}/"),
+                &[],
             )
-            .await
-            .unwrap()
-            .entity
-            .unwrap()
-            .into_ok()
-            .unwrap()
+            .await?
             .system_id
         };
 
         // get system constellation
-        // TODO: use into_ok instead
-        let constellation = if let GetUniverseSystemsSystemIdSuccess::Status200(jita_const) =
-            universe_api::get_universe_systems_system_id(
-                self.config,
-                GetUniverseSystemsSystemIdParams {
-                    system_id,
-                    accept_language: None,
-                    datasource: None,
-                    if_none_match: None,
-                    language: None,
-                },
+        let constellation = self
+            .get_uncached::<GetUniverseSystemsSystemIdOk>(
+                &format!("/universe/systems/{system_id}/"),
+                &[],
             )
-            .await
-            .unwrap()
-            .entity
-            .unwrap()
-        {
-            jita_const.constellation_id
-        } else {
-            panic!();
-        };
+            .await?
+            .constellation_id;
 
         // get system region
-        let region = if let GetUniverseConstellationsConstellationIdSuccess::Status200(ok) =
-            universe_api::get_universe_constellations_constellation_id(
-                self.config,
-                GetUniverseConstellationsConstellationIdParams {
-                    constellation_id: constellation,
-                    accept_language: None,
-                    datasource: None,
-                    if_none_match: None,
-                    language: None,
-                },
+        let region = self
+            .get_uncached::<GetUniverseConstellationsConstellationIdOk>(
+                &format!("/universe/constellations/{constellation}/"),
+                &[],
             )
-            .await
-            .unwrap()
-            .entity
-            .unwrap()
-        {
-            ok.region_id
-        } else {
-            panic!();
-        };
+            .await?
+            .region_id;
         Ok(StationIdData {
             station_id: StationId {
                 is_citadel: station.is_citadel,
@@ -199,22 +128,59 @@ impl<'a> EsiRequestsService<'a> {
         })
     }
 
+    /// Jumps from `origin` to `destination`, `None` when there is no route.
+    async fn get_distance(&self, origin: i32, destination: i32) -> Result<Option<usize>> {
+        if let Some(&dist) = self.distances.read().await.get(&(origin, destination)) {
+            return Ok(dist);
+        }
+        log::debug!(
+            "Distance between origin {origin} and dest {destination} not in cache, \
+            making request..."
+        );
+        let dist = retry_smart(self.policy, Host::Esi, || async {
+            let res = self
+                .get_uncached::<Vec<i32>>(&format!("/route/{origin}/{destination}/"), &[])
+                .await?;
+
+            Ok::<_, EsiApiError>(RetryResult::Success(res))
+        })
+        .await?
+        .map(|x| Some(x.len()))
+        .unwrap_or_else(|| {
+            log::warn!(
+                "Couldn't calculate distance between origin {origin} and dest {destination}"
+            );
+            None
+        });
+        self.distances
+            .write()
+            .await
+            .insert((origin, destination), dist);
+        log::debug!("Distance between origin {origin} and dest {destination} is {dist:?}");
+        Ok(dist)
+    }
+
+    /// GET an ESI endpoint whose responses aren't worth keeping in the etag cache.
+    async fn get_uncached<T: DeserializeOwned>(
+        &self,
+        path: &str,
+        query: &[(&str, String)],
+    ) -> Result<T> {
+        get_esi_json(self.config, &self.policy.error_limit, path, query).await
+    }
+
     pub async fn get_item_description(&self, id: i32) -> Result<Option<GetUniverseTypesTypeIdOk>> {
-        let res = retry::retry_smart::<_, _, _, EsiApiError>(|| async {
-            let res = universe_api::get_universe_types_type_id(
-                self.config,
-                GetUniverseTypesTypeIdParams {
-                    type_id: id,
-                    accept_language: None,
-                    datasource: None,
-                    if_none_match: None,
-                    language: None,
-                },
-            )
-            .await?
-            .entity
-            .unwrap();
-            Ok(RetryResult::Success(res.into_ok().unwrap()))
+        let res = retry::retry_smart::<_, _, _, EsiApiError>(self.policy, Host::Esi, || async {
+            let res = self
+                .etags
+                .get_json(
+                    self.config,
+                    &self.policy.error_limit,
+                    &format!("/universe/types/{id}/"),
+                    &[],
+                )
+                .await?;
+            Ok(RetryResult::Success(res.data))
         })
         .await?;
 
@@ -222,115 +188,66 @@ impl<'a> EsiRequestsService<'a> {
     }
 
     pub async fn get_ajusted_prices(&self) -> anyhow::Result<Option<Vec<GetMarketsPrices200Ok>>> {
-        let res = retry::retry_smart::<_, _, _, EsiApiError>(|| async {
-            let res = market_api::get_markets_prices(
-                self.config,
-                GetMarketsPricesParams {
-                    datasource: None,
-                    if_none_match: None,
-                },
-            )
-            .await?
-            .entity
-            .unwrap();
-            Ok(RetryResult::Success(res.into_ok().unwrap()))
+        let res = retry::retry_smart::<_, _, _, EsiApiError>(self.policy, Host::Esi, || async {
+            let res = self.get_uncached("/markets/prices/", &[]).await?;
+            Ok(RetryResult::Success(res))
         })
         .await?;
 
         Ok(res)
     }
 
-    pub async fn get_orders_station(&self, station: StationIdData) -> Result<Vec<Order>> {
+    pub async fn get_orders_station(&self, station: StationIdData) -> Result<Expiring<Vec<Order>>> {
+        self.get_orders(station, None).await
+    }
+
+    /// Same as [`Self::get_orders_station`] for a single type.
+    pub async fn get_orders_station_type(
+        &self,
+        station: StationIdData,
+        type_id: i32,
+    ) -> Result<Expiring<Vec<Order>>> {
+        self.get_orders(station, Some(type_id)).await
+    }
+
+    async fn get_orders(
+        &self,
+        station: StationIdData,
+        type_id: Option<i32>,
+    ) -> Result<Expiring<Vec<Order>>> {
         // download all orders
         log::info!("Downloading region orders...");
-        let pages: Vec<GetMarketsRegionIdOrders200Ok> = get_all_pages(|page| async move {
-            let orders = market_api::get_markets_region_id_orders(
-                self.config,
-                GetMarketsRegionIdOrdersParams {
-                    order_type: "all".to_string(),
-                    region_id: station.region_id,
-                    datasource: None,
-                    if_none_match: None,
-                    page: Some(page),
-                    type_id: None,
-                },
-            )
-            .await?
-            .entity
-            .unwrap();
-
-            Ok(orders.into_ok().unwrap())
-        })
+        let Expiring {
+            data: pages,
+            mut expires,
+        } = get_all_pages::<_, _, GetMarketsRegionIdOrders200Ok>(
+            self.policy,
+            "Region orders",
+            |page| async move {
+                self.etags
+                    .get_json(
+                        self.config,
+                        &self.policy.error_limit,
+                        &format!("/markets/{}/orders/", station.region_id),
+                        &[
+                            ("order_type", "all".to_string()),
+                            ("page", page.to_string()),
+                        ]
+                        .into_iter()
+                        .chain(type_id.map(|x| ("type_id", x.to_string())))
+                        .collect_vec(),
+                    )
+                    .await
+            },
+        )
         .await?;
         log::info!("All region orders downloaded. Calculating distances...");
 
-        let distance_cache = RwLock::new(HashMap::new());
-
         // calculate distance to all buy orders
         let pages: Vec<(GetMarketsRegionIdOrders200Ok, Option<usize>)> = stream::iter(pages)
             .map(|x| async {
                 let dist_if_buy = if x.is_buy_order {
-                    let map = distance_cache.read().await;
-                    let dist = match map.get(&x.system_id) {
-                        Some(&dist) => dist,
-                        None => {
-                            drop(map);
-                            log::debug!(
-                                "Distance between origin {} and dest {} not in cache, making request...",
-                                station.system_id,
-                                x.system_id
-                            );
-                            let dist =
-                                retry_smart(
-                                    || async {
-                                        let res = routes_api::get_route_origin_destination(
-                                            self.config,
-                                            GetRouteOriginDestinationParams {
-                                                destination: x.system_id,
-                                                origin: station.system_id,
-                                                avoid: None,
-                                                connections: None,
-                                                datasource: None,
-                                                flag: None,
-                                                if_none_match: None,
-                                            },
-                                        )
-                                        .await?
-                                        .entity
-                                        .unwrap();
-
-                                        Ok::<_,EsiApiError>(RetryResult::Success(res.into_ok().unwrap()))
-                                    },
-                                )
-                                .await?
-                                .map(|x| Some(x.len()))
-                                .unwrap_or_else(|| {
-                                    log::warn!(
-                                        "Couldn't calculate distance between origin {} and dest {}",
-                                        station.system_id,
-                                        x.system_id
-                                    );
-                                    None
-                                });
-                            log::debug!(
-                                "Inserting distance between origin {} and dest {} into cache...",
-                                station.system_id,
-                                x.system_id
-                            );
-                            let mut map = distance_cache.write().await;
-                            map.insert(x.system_id,dist);
-                            dist
-                        }
-                    };
-
-                    log::debug!(
-                        "Distance between origin {} and dest {} is {:?}",
-                        station.system_id,
-                        x.system_id,
-                        dist
-                    );
-
-                    dist
+                    self.get_distance(station.system_id, x.system_id).await?
                 } else {
                     None
                 };
@@ -384,38 +301,36 @@ impl<'a> EsiRequestsService<'a> {
 
         if station.station_id.is_citadel {
             log::info!("Loading citadel orders...");
-            let mut orders_in_citadel = get_all_pages(|page| async move {
-                let orders = market_api::get_markets_structures_structure_id(
-                    self.config,
-                    GetMarketsStructuresStructureIdParams {
-                        structure_id: station.station_id.id,
-                        datasource: None,
-                        if_none_match: None,
-                        page: Some(page),
-                        token: None,
-                    },
-                )
-                .await?
-                .entity
-                .unwrap();
-
-                Ok(orders.into_ok().unwrap())
-            })
-            .await?
-            .into_iter()
-            .map(|it| Order {
-                duration: it.duration,
-                is_buy_order: it.is_buy_order,
-                issued: it.issued,
-                location_id: it.location_id,
-                min_volume: it.min_volume,
-                order_id: it.order_id,
-                price: it.price,
-                type_id: it.type_id,
-                volume_remain: it.volume_remain as i64,
-                volume_total: it.volume_total as i64,
-            })
-            .collect::<Vec<_>>();
+            let orders_in_citadel =
+                get_all_pages(self.policy, "Citadel orders", |page| async move {
+                    self.etags
+                        .get_json::<Vec<GetMarketsStructuresStructureId200Ok>>(
+                            self.config,
+                            &self.policy.error_limit,
+                            &format!("/markets/structures/{}/", station.station_id.id),
+                            &[("page", page.to_string())],
+                        )
+                        .await
+                })
+                .await?;
+            expires = earliest_expiry(expires, orders_in_citadel.expires);
+            let mut orders_in_citadel = orders_in_citadel
+                .data
+                .into_iter()
+                .filter(|it| type_id.map_or(true, |x| x == it.type_id))
+                .map(|it| Order {
+                    duration: it.duration,
+                    is_buy_order: it.is_buy_order,
+                    issued: it.issued,
+                    location_id: it.location_id,
+                    min_volume: it.min_volume,
+                    order_id: it.order_id,
+                    price: it.price,
+                    type_id: it.type_id,
+                    volume_remain: it.volume_remain as i64,
+                    volume_total: it.volume_total as i64,
+                })
+                .collect::<Vec<_>>();
             log::info!("All citadel orders loaded.");
 
             orders_in_station.append(&mut orders_in_citadel);
@@ -430,17 +345,23 @@ impl<'a> EsiRequestsService<'a> {
         // skip these
         orders_in_station.retain(|x| x.min_volume == 1);
 
-        Ok(orders_in_station)
+        Ok(Expiring::new(orders_in_station, expires))
     }
 
-    pub async fn all_item_orders(&self, station: StationIdData) -> Result<Vec<ItemOrders>> {
+    pub async fn all_item_orders(
+        &self,
+        station: StationIdData,
+    ) -> Result<Expiring<Vec<ItemOrders>>> {
         let station_orders = self.get_orders_station(station).await?;
-        let station_orders = station_orders.into_iter().into_group_map_by(|x| x.type_id);
 
-        Ok(station_orders
-            .into_iter()
-            .map(|(id, orders)| ItemOrders { id, orders })
-            .collect_vec())
+        Ok(station_orders.map(|station_orders| {
+            station_orders
+                .into_iter()
+                .into_group_map_by(|x| x.type_id)
+                .into_iter()
+                .map(|(id, orders)| ItemOrders { id, orders })
+                .collect_vec()
+        }))
     }
 
     pub async fn get_killmail_items_frequency(
@@ -448,20 +369,14 @@ impl<'a> EsiRequestsService<'a> {
         killmail_id: i32,
         hash: String,
     ) -> Result<Option<Killmail>> {
-        let km = retry::retry_smart::<_, _, _, EsiApiError>(|| async {
-            let res = killmails_api::get_killmails_killmail_id_killmail_hash(
-                self.config,
-                GetKillmailsKillmailIdKillmailHashParams {
-                    killmail_hash: hash.clone(),
-                    killmail_id,
-                    datasource: None,
-                    if_none_match: None,
-                },
-            )
-            .await?
-            .entity
-            .unwrap();
-            Ok(RetryResult::Success(res.into_ok().unwrap()))
+        let km = retry::retry_smart::<_, _, _, EsiApiError>(self.policy, Host::Esi, || async {
+            let res = self
+                .get_uncached::<GetKillmailsKillmailIdKillmailHashOk>(
+                    &format!("/killmails/{killmail_id}/{hash}/"),
+                    &[],
+                )
+                .await?;
+            Ok(RetryResult::Success(res))
         })
         .await?;
         let km = match km {
@@ -502,42 +417,173 @@ impl<'a> EsiRequestsService<'a> {
     }
 
     pub async fn get_all_item_types(&self, region_id: i32) -> Result<Vec<i32>> {
-        let pages = get_all_pages(|page| {
-            let config = &self.config;
-            async move {
-                let types = market_api::get_markets_region_id_types(
-                    config,
-                    GetMarketsRegionIdTypesParams {
-                        region_id,
-                        datasource: None,
-                        if_none_match: None,
-                        page: Some(page),
-                    },
+        let pages = get_all_pages(self.policy, "Item types", |page| async move {
+            let types = self
+                .get_uncached(
+                    &format!("/markets/{region_id}/types/"),
+                    &[("page", page.to_string())],
                 )
-                .await?
-                .entity
-                .unwrap();
+                .await?;
+            Ok(Expiring::new(types, None))
+        })
+        .await?;
 
-                Ok(types.into_ok().unwrap())
-            }
+        Ok(pages.data)
+    }
+
+    /// Open orders of the character, needs the `esi-markets.read_character_orders.v1` scope.
+    pub async fn get_character_orders(
+        &self,
+        character_id: i32,
+    ) -> anyhow::Result<Vec<CharacterOrder>> {
+        self.get_scoped_json(
+            &format!("/characters/{character_id}/orders/"),
+            "character orders",
+            "esi-markets.read_character_orders.v1",
+        )
+        .await
+    }
+
+    /// Trained skills of the character, needs the `esi-skills.read_skills.v1` scope.
+    pub async fn get_character_skills(&self, character_id: i32) -> anyhow::Result<CharacterSkills> {
+        self.get_scoped_json(
+            &format!("/characters/{character_id}/skills/"),
+            "character skills",
+            "esi-skills.read_skills.v1",
+        )
+        .await
+    }
+
+    /// Standings of NPC factions, corporations and agents towards the character,
+    /// needs the `esi-characters.read_standings.v1` scope.
+    pub async fn get_character_standings(
+        &self,
+        character_id: i32,
+    ) -> anyhow::Result<Vec<Standing>> {
+        self.get_scoped_json(
+            &format!("/characters/{character_id}/standings/"),
+            "character standings",
+            "esi-characters.read_standings.v1",
+        )
+        .await
+    }
+
+    /// Corporation owning an NPC station and the faction of that corporation.
+    pub async fn get_station_owner(&self, station_id: i64) -> anyhow::Result<StationOwner> {
+        #[derive(Deserialize)]
+        struct StationData {
+            owner: Option<i32>,
+        }
+        #[derive(Deserialize)]
+        struct CorporationData {
+            faction_id: Option<i32>,
+        }
+
+        let station = self
+            .get_public_json::<StationData>(&format!("/universe/stations/{station_id}/"))
+            .await?;
+        let corporation_id = station
+            .owner
+            .ok_or_else(|| anyhow!("Station {station_id} has no owner"))?;
+        let corporation = self
+            .get_public_json::<CorporationData>(&format!("/corporations/{corporation_id}/"))
+            .await?;
+        Ok(StationOwner {
+            corporation_id,
+            faction_id: corporation.faction_id,
+        })
+    }
+
+    async fn get_public_json<T: DeserializeOwned>(&self, path: &str) -> anyhow::Result<T> {
+        retry::retry_smart::<_, _, _, EsiApiError>(self.policy, Host::Esi, || async {
+            let res = self
+                .etags
+                .get_json(self.config, &self.policy.error_limit, path, &[])
+                .await?;
+            Ok(RetryResult::Success(res.data))
+        })
+        .await?
+        .ok_or_else(|| anyhow!("Couldn't load {path} after multiple retries"))
+    }
+
+    /// Downloads data of the character, telling how to log in again when the cached
+    /// login lacks the `scope`.
+    async fn get_scoped_json<T: DeserializeOwned>(
+        &self,
+        path: &str,
+        what: &str,
+        scope: &str,
+    ) -> anyhow::Result<T> {
+        let data = retry::retry_smart(self.policy, Host::Esi, || async {
+            let data = self
+                .etags
+                .get_json::<T>(self.config, &self.policy.error_limit, path, &[])
+                .await?;
+            Ok::<_, EsiApiError>(RetryResult::Success(data.data))
+        })
+        .await;
+        match data {
+            Ok(Some(data)) => Ok(data),
+            Ok(None) => Err(anyhow!("Couldn't load {what} after multiple retries")),
+            Err(EsiApiError {
+                status: StatusCode::FORBIDDEN,
+                ..
+            }) => Err(anyhow!(
+                "Not allowed to read {what}, remove the cached login in cache/{} \
+                to log in again with the {scope} scope",
+                consts::CACHE_AUTH
+            )),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Orders of the character that were filled, cancelled or expired
+    /// in the last 90 days.
+    pub async fn get_character_orders_history(
+        &self,
+        character_id: i32,
+    ) -> anyhow::Result<Vec<CharacterOrder>> {
+        let orders = get_all_pages(self.policy, "Character order history", |page| async move {
+            self.etags
+                .get_json(
+                    self.config,
+                    &self.policy.error_limit,
+                    &format!("/characters/{character_id}/orders/history/"),
+                    &[("page", page.to_string())],
+                )
+                .await
         })
         .await?;
+        Ok(orders.data)
+    }
 
-        Ok(pages)
+    /// Wallet journal of the last 30 days, needs the `esi-wallet.read_character_wallet.v1`
+    /// scope.
+    pub async fn get_wallet_journal(&self, character_id: i32) -> anyhow::Result<Vec<JournalEntry>> {
+        let journal = get_all_pages(self.policy, "Wallet journal", |page| async move {
+            self.etags
+                .get_json(
+                    self.config,
+                    &self.policy.error_limit,
+                    &format!("/characters/{character_id}/wallet/journal/"),
+                    &[("page", page.to_string())],
+                )
+                .await
+        })
+        .await?;
+        Ok(journal.data)
     }
 
     pub async fn open_market_type(&self, type_id: i32) -> Result<()> {
-        retry::retry_smart(|| async {
-            let response_content = user_interface_api::post_ui_openwindow_marketdetails(
+        retry::retry_smart(self.policy, Host::Esi, || async {
+            let response_content = post_esi(
                 self.config,
-                PostUiOpenwindowMarketdetailsParams {
-                    type_id,
-                    datasource: None,
-                    token: None,
-                },
+                &self.policy.error_limit,
+                "/ui/openwindow/marketdetails/",
+                &[("type_id", type_id.to_string())],
             )
             .await;
-            match response_content.map_err(|x| x.into()) {
+            match response_content {
                 Ok(ok) => Ok::<_, EsiApiError>(RetryResult::Success(ok)),
                 Err(EsiApiError {
                     status: StatusCode::FORBIDDEN,
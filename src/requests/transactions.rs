@@ -1,39 +1,33 @@
 use anyhow::anyhow;
 use rust_eveonline_esi::{
-    apis::{
-        configuration::Configuration,
-        wallet_api::{self, GetCharactersCharacterIdWalletTransactionsParams},
-    },
-    models::GetCharactersCharacterIdWalletTransactions200Ok,
+    apis::configuration::Configuration, models::GetCharactersCharacterIdWalletTransactions200Ok,
 };
 
-use super::{error::EsiApiError, paged_all::OnlyOk, retry::retry_smart};
+use super::{
+    error::EsiApiError,
+    esi_http::get_esi_json,
+    policy::{Host, RequestPolicy},
+    retry::retry_smart,
+};
 
 pub struct WalletEsiService<'a> {
     pub esi_config: &'a Configuration,
+    pub policy: &'a RequestPolicy,
 }
 impl<'a> WalletEsiService<'a> {
     pub async fn get_transactions_history(
         &self,
         character_id: i32,
     ) -> anyhow::Result<Vec<GetCharactersCharacterIdWalletTransactions200Ok>> {
-        let transactions = retry_smart(|| async {
+        let transactions = retry_smart(self.policy, Host::Esi, || async {
             Ok::<_, EsiApiError>(super::retry::RetryResult::Success(
-                wallet_api::get_characters_character_id_wallet_transactions(
+                get_esi_json(
                     self.esi_config,
-                    GetCharactersCharacterIdWalletTransactionsParams {
-                        character_id,
-                        from_id: None,
-                        datasource: None,
-                        if_none_match: None,
-                        token: None,
-                    },
+                    &self.policy.error_limit,
+                    &format!("/characters/{character_id}/wallet/transactions/"),
+                    &[],
                 )
-                .await?
-                .entity
-                .unwrap()
-                .into_ok()
-                .unwrap(),
+                .await?,
             ))
         })
         .await?
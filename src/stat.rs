@@ -46,3 +46,226 @@ where
         }
     }
 }
+
+pub trait TrimmedMeanStat {
+    /// Mean after dropping `trim` fraction of the smallest and of the largest values.
+    fn trimmed_mean(self, trim: f64) -> Option<NotNan<f64>>;
+}
+
+impl<I> TrimmedMeanStat for I
+where
+    I: Iterator<Item = NotNan<f64>>,
+{
+    fn trimmed_mean(self, trim: f64) -> Option<NotNan<f64>> {
+        let sorted = self.sorted().collect::<Vec<_>>();
+        let cut = (sorted.len() as f64 * trim.clamp(0., 0.5)).floor() as usize;
+        let kept = if cut * 2 < sorted.len() {
+            &sorted[cut..sorted.len() - cut]
+        } else {
+            // everything got trimmed, fall back to the middle
+            let middle = sorted.len().saturating_sub(1) / 2;
+            &sorted[middle..sorted.len().min(middle + 1)]
+        };
+        kept.iter().copied().average()
+    }
+}
+
+pub trait WeightedMedianStat {
+    /// Value below which half of the total weight lies.
+    fn weighted_median(self) -> Option<NotNan<f64>>;
+}
+
+impl<I> WeightedMedianStat for I
+where
+    I: Iterator<Item = (NotNan<f64>, f64)>,
+{
+    fn weighted_median(self) -> Option<NotNan<f64>> {
+        let sorted = self
+            .filter(|x| x.1 > 0.)
+            .sorted_by_key(|x| x.0)
+            .collect::<Vec<_>>();
+        let total = sorted.iter().map(|x| x.1).sum::<f64>();
+        let mut cumulative = 0.;
+        for (value, weight) in sorted {
+            cumulative += weight;
+            if cumulative >= total / 2. {
+                return Some(value);
+            }
+        }
+        None
+    }
+}
+
+/// Scales MAD so that it estimates standard deviation of normally distributed values.
+const MAD_TO_STDDEV: f64 = 1.4826;
+
+pub trait OutlierStat {
+    /// Values further than `threshold` (scaled) median absolute deviations
+    /// from the median are rejected.
+    fn without_outliers(self, threshold: f64) -> Vec<NotNan<f64>>;
+}
+
+impl<I> OutlierStat for I
+where
+    I: Iterator<Item = NotNan<f64>>,
+{
+    fn without_outliers(self, threshold: f64) -> Vec<NotNan<f64>> {
+        let values = self.collect::<Vec<_>>();
+        let Some(median) = values.iter().copied().median() else {
+            return values;
+        };
+        let deviation = |x: &NotNan<f64>| NotNan::new((**x - *median).abs()).unwrap();
+        let mad = values.iter().map(deviation).median().unwrap();
+        // when most values are the same MAD is zero, mean deviation still sees the spread
+        let spread = if *mad > 0. {
+            mad
+        } else {
+            values.iter().map(deviation).average().unwrap()
+        };
+        let max_deviation = *spread * MAD_TO_STDDEV * threshold;
+        values
+            .into_iter()
+            .filter(|x| *deviation(x) <= max_deviation)
+            .collect()
+    }
+}
+
+pub trait EwmaStat {
+    /// Exponentially weighted moving average of values ordered from oldest to newest.
+    fn ewma(self, alpha: f64) -> Option<NotNan<f64>>;
+}
+
+impl<I> EwmaStat for I
+where
+    I: Iterator<Item = NotNan<f64>>,
+{
+    fn ewma(self, alpha: f64) -> Option<NotNan<f64>> {
+        self.reduce(|acc, x| NotNan::new(alpha * *x + (1. - alpha) * *acc).expect("EWMA is NaN"))
+    }
+}
+
+pub trait StdDevStat {
+    /// Population standard deviation.
+    fn std_dev(self) -> Option<NotNan<f64>>;
+}
+
+impl<I> StdDevStat for I
+where
+    I: Iterator<Item = NotNan<f64>>,
+{
+    fn std_dev(self) -> Option<NotNan<f64>> {
+        let values = self.collect::<Vec<_>>();
+        let mean = values.iter().copied().average()?;
+        values
+            .iter()
+            .map(|x| NotNan::new((**x - *mean).powi(2)).unwrap())
+            .average()
+            .map(|x| NotNan::new(x.sqrt()).unwrap())
+    }
+}
+
+pub trait SlopeStat {
+    /// Slope of the least squares line through `(x, y)` points.
+    fn slope(self) -> Option<f64>;
+}
+
+impl<I> SlopeStat for I
+where
+    I: Iterator<Item = (f64, f64)>,
+{
+    fn slope(self) -> Option<f64> {
+        let points = self.collect::<Vec<_>>();
+        if points.len() < 2 {
+            return None;
+        }
+        let count = points.len() as f64;
+        let mean_x = points.iter().map(|x| x.0).sum::<f64>() / count;
+        let mean_y = points.iter().map(|x| x.1).sum::<f64>() / count;
+        let covariance = points
+            .iter()
+            .map(|(x, y)| (x - mean_x) * (y - mean_y))
+            .sum::<f64>();
+        let variance = points
+            .iter()
+            .map(|(x, _)| (x - mean_x).powi(2))
+            .sum::<f64>();
+        if variance > 0. {
+            Some(covariance / variance)
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn values(xs: &[f64]) -> impl Iterator<Item = NotNan<f64>> + '_ {
+        xs.iter().map(|&x| NotNan::new(x).unwrap())
+    }
+
+    #[test]
+    fn test_trimmed_mean_drops_extremes() {
+        let mean = values(&[1., 10., 11., 12., 1000.]).trimmed_mean(0.2);
+        assert_eq!(mean.map(|x| *x), Some(11.));
+    }
+
+    #[test]
+    fn test_trimmed_mean_trimming_everything() {
+        let mean = values(&[1., 2.]).trimmed_mean(0.5);
+        assert_eq!(mean.map(|x| *x), Some(1.));
+    }
+
+    #[test]
+    fn test_weighted_median_follows_volume() {
+        let median = values(&[100., 110., 1000.])
+            .zip([50., 40., 5.])
+            .weighted_median();
+        assert_eq!(median.map(|x| *x), Some(100.));
+    }
+
+    #[test]
+    fn test_weighted_median_without_weight() {
+        let median = values(&[100., 110.]).zip([0., 0.]).weighted_median();
+        assert_eq!(median, None);
+    }
+
+    #[test]
+    fn test_without_outliers_rejects_manipulated_day() {
+        let kept = values(&[100., 102., 98., 101., 1000.]).without_outliers(3.);
+        assert_eq!(
+            kept.into_iter().map(|x| *x).collect::<Vec<_>>(),
+            vec![100., 102., 98., 101.]
+        );
+    }
+
+    #[test]
+    fn test_without_outliers_with_zero_mad() {
+        let kept = values(&[100., 100., 100., 100., 1000.]).without_outliers(3.);
+        assert_eq!(kept.len(), 4);
+    }
+
+    #[test]
+    fn test_ewma_weights_recent_values() {
+        let ewma = values(&[100., 100., 200.]).ewma(0.5);
+        assert_eq!(ewma.map(|x| *x), Some(150.));
+    }
+
+    #[test]
+    fn test_std_dev() {
+        let std_dev = values(&[2., 4., 4., 4., 5., 5., 7., 9.]).std_dev();
+        assert_eq!(std_dev.map(|x| *x), Some(2.));
+    }
+
+    #[test]
+    fn test_slope_of_line() {
+        let slope = [(0., 1.), (1., 3.), (2., 5.)].into_iter().slope();
+        assert_eq!(slope, Some(2.));
+    }
+
+    #[test]
+    fn test_slope_needs_two_x() {
+        assert_eq!([(1., 1.), (1., 3.)].into_iter().slope(), None);
+    }
+}
@@ -10,7 +10,11 @@ use itertools::Itertools;
 
 use crate::{
     consts::UD_SOCKET_PATH,
-    good_items::{items_prices::ItemInput, station_trading::StationTradeData},
+    good_items::{
+        items_prices::ItemInput,
+        my_orders::{MyOrdersData, MyOrdersService},
+        station_trading::StationTradeData,
+    },
     requests::service::EsiRequestsService,
 };
 
@@ -88,6 +92,57 @@ pub fn communicate_paste_sell_order_prices(prices: Vec<f64>) -> anyhow::Result<(
     Ok(())
 }
 
+/// Walks through outbid orders: opens the market of each one and, when the hotkey
+/// is pressed in its modify order window, pastes the price that outbids the
+/// current book.
+pub async fn communicate_modify_orders<'a>(
+    my_orders_service: &MyOrdersService<'a>,
+    mut my_orders: MyOrdersData,
+) -> anyhow::Result<()> {
+    log::info!("Listening for hotkeys...");
+    send_notification("Listening to hotkeys")?;
+
+    let path = &Path::new(UD_SOCKET_PATH);
+    if path.exists() {
+        remove_file(path)?;
+    }
+
+    let listener = UdStreamListener::bind(UD_SOCKET_PATH)?;
+    let order_ids = my_orders
+        .outbid_orders()
+        .map(|x| (x.order.order_id, x.order.type_id))
+        .collect_vec();
+    for (order_id, type_id) in order_ids {
+        my_orders_service
+            .esi_requests
+            .open_market_type(type_id)
+            .await?;
+
+        // user opens "modify order"
+        let conn = listener.accept()?;
+        // the book could have changed while earlier orders were being updated
+        my_orders_service
+            .refresh_type(&mut my_orders, type_id)
+            .await?;
+        match my_orders.get(order_id).and_then(|x| x.new_price()) {
+            Some(price) => {
+                log::info!("Pasting {price} for order {order_id}");
+                save_to_clipboard_cmd(&format!("{}", price))?;
+                paste_from_clipboard_cmd()?;
+            }
+            None => {
+                log::info!("Order {order_id} is not outbid anymore, skipping");
+                send_notification("Order is not outbid anymore")?;
+            }
+        }
+        conn.shutdown(Shutdown::Write)?;
+    }
+
+    log::info!("Finished listening");
+
+    Ok(())
+}
+
 pub fn save_to_clipboard_cmd(line: &str) -> anyhow::Result<()> {
     log::info!("Copying '{line}' to clipboard...");
     cmd_lib::spawn! {
@@ -0,0 +1,74 @@
+//! Orders and transactions to build test cases from.
+//!
+//! Everything is of Tritanium in location 1 and starts at [`start`], tests
+//! override the fields they care about with struct update syntax.
+
+use chrono::{DateTime, Duration, Utc};
+
+use crate::{
+    good_items::ledger::LedgerTransaction,
+    item_type::{CharacterOrder, Order},
+    order_snapshots::SnapshotOrder,
+};
+
+pub const TYPE_ID: i32 = 34;
+
+pub fn start() -> DateTime<Utc> {
+    DateTime::parse_from_rfc3339("2024-01-01T00:00:00Z")
+        .unwrap()
+        .with_timezone(&Utc)
+}
+
+/// Market order of 10 items issued at [`start`].
+pub fn order(order_id: i64, is_buy_order: bool, price: f64) -> Order {
+    Order {
+        duration: 90,
+        is_buy_order,
+        issued: start().to_rfc3339(),
+        location_id: 1,
+        min_volume: 1,
+        order_id,
+        price,
+        type_id: TYPE_ID,
+        volume_remain: 10,
+        volume_total: 10,
+    }
+}
+
+/// Same as [`order`], as the owner of the order sees it.
+pub fn character_order(order_id: i64, is_buy_order: bool, price: f64) -> CharacterOrder {
+    CharacterOrder {
+        order_id,
+        type_id: TYPE_ID,
+        location_id: 1,
+        is_buy_order,
+        price,
+        volume_remain: 10,
+        volume_total: 10,
+        issued: start().to_rfc3339(),
+    }
+}
+
+/// Same as [`order`], as recorded in a snapshot.
+pub fn snapshot_order(order_id: i64, is_buy_order: bool, price: f64) -> SnapshotOrder {
+    SnapshotOrder::from(&order(order_id, is_buy_order, price))
+}
+
+/// Transaction made `transaction_id` hours after [`start`].
+pub fn transaction(
+    transaction_id: i64,
+    location_id: i64,
+    is_buy: bool,
+    quantity: i64,
+    unit_price: f64,
+) -> LedgerTransaction {
+    LedgerTransaction {
+        transaction_id,
+        date: start() + Duration::try_hours(transaction_id).unwrap(),
+        type_id: TYPE_ID,
+        location_id,
+        is_buy,
+        quantity,
+        unit_price,
+    }
+}
@@ -5,10 +5,11 @@ use serde::{Deserialize, Serialize};
 
 use crate::{
     consts::BUFFER_UNORDERED,
+    progress::Progress,
     requests::service::{EsiRequestsService, Killmail},
 };
 
-use super::zkb_requests::{ZkbRequestsService, ZkillEntity};
+use super::zkb_requests::{ZkbRequestsService, ZkillEntity, ZKB_PAGE_KILLMAILS};
 
 pub struct KillmailService<'a> {
     zkb: &'a ZkbRequestsService<'a>,
@@ -42,20 +43,37 @@ impl<'a> KillmailService<'a> {
         }
     }
 
+    /// Progress over killmails of `pages` pages, its total is corrected as pages
+    /// are downloaded.
+    pub fn progress(pages: u32) -> Progress {
+        Progress::new("Killmails", pages as usize * ZKB_PAGE_KILLMAILS)
+    }
+
     pub async fn get_killmails(
         &self,
         entity: &ZkillEntity,
         page: u32,
+        progress: &Progress,
     ) -> Result<Vec<Killmail>, anyhow::Error> {
         let kms = self.zkb.get_kb_page(entity, page).await?;
-        let frequencies = kms.into_iter().map(|km| {
-            self.esi
+        progress.adjust_total(kms.len() as i64 - ZKB_PAGE_KILLMAILS as i64);
+        let frequencies = kms.into_iter().map(|km| async move {
+            let res = self
+                .esi
                 .get_killmail_items_frequency(km.killmail_id, km.zkb.hash)
+                .await;
+            match res {
+                Ok(Some(_)) => progress.inc(),
+                _ => progress.inc_failed(),
+            }
+            res
         });
-        let km_freqs: Vec<Killmail> = stream::iter(frequencies)
+        let km_freqs = stream::iter(frequencies)
             .buffer_unordered(BUFFER_UNORDERED)
             .collect::<Vec<_>>()
-            .await
+            .await;
+
+        let km_freqs: Vec<Killmail> = km_freqs
             .into_iter()
             .collect::<crate::requests::error::Result<Vec<_>>>()?
             .into_iter()
@@ -65,7 +83,7 @@ impl<'a> KillmailService<'a> {
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ItemFrequencies {
     pub items: HashMap<i32, i64>,
     pub period_seconds: i64,
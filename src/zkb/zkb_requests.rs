@@ -1,18 +1,25 @@
 use serde::{Deserialize, Serialize};
 
-use crate::requests::retry::{retry_smart, RetryResult};
+use crate::requests::{
+    policy::{Host, RequestPolicy},
+    retry::{retry_after, retry_smart, RetryResult},
+};
+
+/// Killmails on a full page of zkillboard losses.
+pub const ZKB_PAGE_KILLMAILS: usize = 200;
 
 pub struct ZkbRequestsService<'a> {
     client: &'a reqwest::Client,
+    policy: &'a RequestPolicy,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ZkillEntity {
     pub id: u32,
     pub tp: ZkillEntityType,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
 pub enum ZkillEntityType {
     Corporation,
     Alliance,
@@ -30,8 +37,8 @@ impl ZkillEntityType {
 }
 
 impl<'a> ZkbRequestsService<'a> {
-    pub fn new(client: &'a reqwest::Client) -> Self {
-        Self { client }
+    pub fn new(client: &'a reqwest::Client, policy: &'a RequestPolicy) -> Self {
+        Self { client, policy }
     }
 
     pub async fn get_killmails(
@@ -58,7 +65,7 @@ impl<'a> ZkbRequestsService<'a> {
     ) -> Result<Vec<Kill>, reqwest::Error> {
         log::info!("Getting killmails page {page}...");
 
-        let kills_page = retry_smart(|| async {
+        let kills_page = retry_smart(self.policy, Host::Zkillboard, || async {
             let url = format!(
                 "https://zkillboard.com/api/losses/{}/{}/page/{}/",
                 entity_type.zkill_filter_string(),
@@ -67,8 +74,8 @@ impl<'a> ZkbRequestsService<'a> {
             );
             let response = self.client.get(url.clone()).send().await?;
             if response.status() == 429 {
-                log::warn!("Zkill returned status 429. Retrying in 60 seconds...");
-                return Ok(RetryResult::Retry);
+                log::warn!("Zkill returned status 429. Retrying...");
+                return Ok(RetryResult::Throttled(retry_after(response.headers())));
             }
 
             let full = response.bytes().await?;
@@ -87,9 +94,6 @@ impl<'a> ZkbRequestsService<'a> {
                 })
                 .unwrap();
 
-            // zkillboard allows only one request per second
-            tokio::time::sleep(std::time::Duration::from_secs_f32(1.)).await;
-
             Ok(RetryResult::Success(kills_page))
         })
         .await?